@@ -0,0 +1,62 @@
+//! Every token the service signs is recorded, one JSON object per line,
+//! so tokens found in the wild can be traced back to when and for whom
+//! they were minted.
+
+use std::{net::SocketAddr, path::Path};
+
+use serde::Serialize;
+use tokio::{fs::File, io::AsyncWriteExt, sync::Mutex};
+
+#[derive(Serialize)]
+pub struct AuditEntry<'a> {
+    /// Unix time in milliseconds
+    pub signed_at: i64,
+    /// Who asked for the token
+    pub peer: SocketAddr,
+    /// Key the token was signed with
+    pub kid: &'a str,
+    pub sub: &'a str,
+    pub uid: i32,
+    pub jti: &'a str,
+    pub exp: usize,
+}
+
+/// Appends to a file when given one, only traces entries otherwise
+pub struct AuditLog {
+    file: Option<Mutex<File>>,
+}
+
+impl AuditLog {
+    pub async fn open(path: Option<&Path>) -> std::io::Result<Self> {
+        let file = match path {
+            Some(path) => Some(Mutex::new(
+                tokio::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(path)
+                    .await?,
+            )),
+            None => None,
+        };
+
+        Ok(Self { file })
+    }
+
+    /// Records a signed token, tokens which can't be recorded must not be
+    /// handed out
+    pub async fn record(&self, entry: &AuditEntry<'_>) -> std::io::Result<()> {
+        let mut line = serde_json::to_string(entry)?;
+
+        tracing::info!(target: "audit", "{line}");
+
+        if let Some(file) = &self.file {
+            line.push('\n');
+
+            let mut file = file.lock().await;
+            file.write_all(line.as_bytes()).await?;
+            file.flush().await?;
+        }
+
+        Ok(())
+    }
+}
@@ -1,28 +1,54 @@
-use std::{net::SocketAddr, str::FromStr};
+use std::{
+    net::SocketAddr,
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
 use anyhow::anyhow;
-use axum::{extract::Json, http::StatusCode, routing::post, Router};
-use jsonwebtoken::EncodingKey;
+use audit::{AuditEntry, AuditLog};
+use aws_common::api::{
+    auth::{
+        AwsClaims, Credentials, ImportedCredentials, IntrospectRequest, IntrospectResponse,
+        IssuedTokens, JwtResponse, LogoutRequest, RefreshRequest, RegisteredUser,
+        SHARED_SECRET_MIN_LEN,
+    },
+    errors::AwsError,
+};
+use axum::{
+    extract::{ConnectInfo, Json, Path},
+    http::StatusCode,
+    routing::{delete, get, post},
+    Extension, Router,
+};
+use jsonwebtoken::jwk::JwkSet;
+use keys::{KeyWatcher, Keys};
 use lazy_static::lazy_static;
-use serde::{Deserialize, Serialize};
+use sea_orm::{ConnectOptions, Database, DatabaseConnection};
+use sea_orm_migration::MigratorTrait;
+use sessions::Session;
+use signed::Signed;
 use tower_http::cors;
 
+mod audit;
+mod entities;
+mod keys;
+mod migrator;
+mod sessions;
+mod signed;
+mod users;
+
+const JWT_TOKEN_VALIDITY: Duration = Duration::from_secs(15 * 60);
+
 lazy_static! {
-    pub static ref JWT_ENCODING_KEY: EncodingKey = EncodingKey::from_ec_pem(
-        std::fs::read_to_string(
-            std::env::var("JWT_PRIVATE_KEY_PATH").expect("env var to be present")
-        )
-        .expect("to be able to read private key file")
-        .as_bytes()
+    /// Only requests signed with it are served
+    pub static ref AUTH_SHARED_SECRET: Vec<u8> = std::fs::read_to_string(
+        std::env::var("AUTH_SHARED_SECRET_PATH").expect("env var to be present")
     )
-    .expect("Private key to be valid");
-}
-
-#[derive(Serialize, Deserialize, Debug)]
-pub struct AwsClaims {
-    pub sub: String,
-    pub exp: usize,
-    pub uid: i32,
+    .expect("to be able to read shared secret file")
+    .trim()
+    .as_bytes()
+    .to_vec();
 }
 
 pub async fn fallback(_: axum::http::Uri) -> StatusCode {
@@ -34,9 +60,46 @@ async fn main() -> anyhow::Result<()> {
     tracing_subscriber::fmt::try_init()
         .map_err(|_| anyhow!("Failed to install tracing_subscriber"))?;
 
+    if AUTH_SHARED_SECRET.len() < SHARED_SECRET_MIN_LEN {
+        return Err(anyhow!(
+            "Shared secret must be at least {SHARED_SECRET_MIN_LEN} bytes"
+        ));
+    }
+
+    let audit_path = std::env::var("AUDIT_LOG_PATH").ok();
+    let audit = Arc::new(AuditLog::open(audit_path.as_deref().map(AsRef::as_ref)).await?);
+
+    let keys = KeyWatcher {
+        dir: std::env::var("JWT_KEYS_DIR")
+            .expect("JWT_KEYS_DIR to be present")
+            .into(),
+        signing_kid: std::env::var("JWT_SIGNING_KID").ok(),
+    }
+    .start()?;
+
+    let mut db_opts = ConnectOptions::new(std::env::var("DB_URL").expect("DB_URL to be present"));
+    db_opts.sqlx_logging(false);
+
+    let db = Database::connect(db_opts).await?;
+    migrator::Migrator::up(&db, None).await?;
+
     let app = Router::new()
         .fallback(fallback)
-        .nest("/api/v1", Router::new().route("/sign", post(sign_claims)))
+        .nest(
+            "/api/v1",
+            Router::new()
+                .route("/register", post(register))
+                .route("/login", post(login))
+                .route("/refresh", post(refresh))
+                .route("/logout", post(logout))
+                .route("/introspect", post(introspect))
+                .route("/users/import", post(import_user))
+                .route("/users/:uid", delete(delete_user)),
+        )
+        .route("/.well-known/jwks.json", get(get_jwks))
+        .layer(Extension(Arc::new(db)))
+        .layer(Extension(audit))
+        .layer(Extension(keys))
         .layer(cors::CorsLayer::very_permissive());
 
     let addr =
@@ -46,19 +109,170 @@ async fn main() -> anyhow::Result<()> {
     tracing::info!("Listening on {}", addr);
 
     axum::Server::bind(&addr)
-        .serve(app.into_make_service())
+        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
         .await?;
 
     Ok(())
 }
 
-async fn sign_claims(Json(body): Json<AwsClaims>) -> Result<Json<String>, StatusCode> {
-    let token = jsonwebtoken::encode(
-        &jsonwebtoken::Header::new(jsonwebtoken::Algorithm::ES256),
-        &body,
-        &JWT_ENCODING_KEY,
-    )
-    .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+fn now() -> Duration {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+}
+
+/// Unix time in milliseconds, as stored in the database
+pub fn now_ms() -> i64 {
+    now().as_millis() as i64
+}
+
+/// Public keys tokens are verified with, current and past ones
+async fn get_jwks(Extension(keys): Extension<Keys>) -> Json<JwkSet> {
+    Json(keys.current().jwks())
+}
+
+/// Signs an access token for the session, which is handed out only once
+/// recorded
+async fn issue(
+    keys: &Keys,
+    audit: &AuditLog,
+    peer: SocketAddr,
+    session: Session,
+    username: String,
+) -> Result<IssuedTokens, AwsError> {
+    let now = now();
+
+    let claims = AwsClaims {
+        sub: username,
+        exp: (now + JWT_TOKEN_VALIDITY).as_secs() as usize,
+        uid: session.user_id,
+        jti: session.jti,
+    };
+
+    let ring = keys.current();
+    let key = ring.signing();
+
+    let mut header = jsonwebtoken::Header::new(jsonwebtoken::Algorithm::ES256);
+    header.kid = Some(key.kid.clone());
+
+    let jwt = jsonwebtoken::encode(&header, &claims, &key.encoding)
+        .map_err(|_| AwsError::JwtSignatureFailure)?;
+
+    audit
+        .record(&AuditEntry {
+            signed_at: now.as_millis() as i64,
+            peer,
+            kid: &key.kid,
+            sub: &claims.sub,
+            uid: claims.uid,
+            jti: &claims.jti,
+            exp: claims.exp,
+        })
+        .await
+        .map_err(|e| {
+            tracing::error!("Audit log {e:#?}");
+            AwsError::UnknownServerError
+        })?;
+
+    Ok(IssuedTokens {
+        uid: claims.uid,
+        username: claims.sub,
+        tokens: JwtResponse {
+            jwt,
+            refresh_token: session.refresh_token,
+        },
+    })
+}
+
+async fn register(
+    Extension(db): Extension<Arc<DatabaseConnection>>,
+    Signed(Credentials { username, password }): Signed<Credentials>,
+) -> Result<(StatusCode, Json<RegisteredUser>), AwsError> {
+    let user = users::register(&db, &username, &password).await?;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(RegisteredUser {
+            uid: user.id,
+            username: user.username,
+        }),
+    ))
+}
+
+async fn login(
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    Extension(db): Extension<Arc<DatabaseConnection>>,
+    Extension(audit): Extension<Arc<AuditLog>>,
+    Extension(keys): Extension<Keys>,
+    Signed(Credentials { username, password }): Signed<Credentials>,
+) -> Result<Json<IssuedTokens>, AwsError> {
+    let user = users::verify(&db, &username, &password).await?;
+    let session = sessions::start(&db, user.id).await?;
+
+    Ok(Json(
+        issue(&keys, &audit, peer, session, user.username).await?,
+    ))
+}
+
+/// Trades a refresh token for a new access token and refresh token
+async fn refresh(
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    Extension(db): Extension<Arc<DatabaseConnection>>,
+    Extension(audit): Extension<Arc<AuditLog>>,
+    Extension(keys): Extension<Keys>,
+    Signed(RefreshRequest { refresh_token }): Signed<RefreshRequest>,
+) -> Result<Json<IssuedTokens>, AwsError> {
+    let session = sessions::rotate(&db, &refresh_token).await?;
+    let user = users::find(&db, session.user_id)
+        .await
+        .map_err(|_| AwsError::InvalidRefreshToken)?;
+
+    Ok(Json(
+        issue(&keys, &audit, peer, session, user.username).await?,
+    ))
+}
+
+/// Ends the session of the access token `jti`
+async fn logout(
+    Extension(db): Extension<Arc<DatabaseConnection>>,
+    Signed(LogoutRequest { jti }): Signed<LogoutRequest>,
+) -> Result<StatusCode, AwsError> {
+    sessions::revoke(&db, &jti)
+        .await
+        .map_err(|_| AwsError::UnknownServerError)?;
+
+    Ok(StatusCode::OK)
+}
+
+/// Whether an access token's session is live, the backend asks before
+/// serving it
+async fn introspect(
+    Extension(db): Extension<Arc<DatabaseConnection>>,
+    Signed(IntrospectRequest { uid, jti }): Signed<IntrospectRequest>,
+) -> Result<Json<IntrospectResponse>, AwsError> {
+    let active = sessions::is_active(&db, uid, &jti)
+        .await
+        .map_err(|_| AwsError::UnknownServerError)?;
+
+    Ok(Json(IntrospectResponse { active }))
+}
+
+async fn delete_user(
+    Extension(db): Extension<Arc<DatabaseConnection>>,
+    Path(uid): Path<i32>,
+    Signed(()): Signed<()>,
+) -> Result<StatusCode, AwsError> {
+    users::delete(&db, uid).await?;
+
+    Ok(StatusCode::OK)
+}
+
+/// Takes over credentials the backend stored before the service owned them
+async fn import_user(
+    Extension(db): Extension<Arc<DatabaseConnection>>,
+    Signed(imported): Signed<ImportedCredentials>,
+) -> Result<StatusCode, AwsError> {
+    users::import(&db, imported).await?;
 
-    Ok(token.into())
+    Ok(StatusCode::OK)
 }
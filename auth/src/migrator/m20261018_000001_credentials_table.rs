@@ -0,0 +1,59 @@
+use sea_orm_migration::prelude::*;
+
+pub struct Migration;
+
+impl MigrationName for Migration {
+    fn name(&self) -> &'static str {
+        "m20261018_000001_credentials_table"
+    }
+}
+
+#[sea_orm_migration::async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(Credential::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(Credential::Id)
+                            .integer()
+                            .not_null()
+                            .primary_key()
+                            .auto_increment(),
+                    )
+                    .col(
+                        ColumnDef::new(Credential::Username)
+                            .string()
+                            .not_null()
+                            .unique_key(),
+                    )
+                    .col(ColumnDef::new(Credential::PasswordHash).string().not_null())
+                    .col(
+                        ColumnDef::new(Credential::CreatedAt)
+                            .big_integer()
+                            .not_null(),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(Credential::Table).to_owned())
+            .await
+    }
+}
+
+/// What a user logs in with. Its id is the user's id on the backend too
+#[derive(Iden)]
+pub enum Credential {
+    Table,
+    Id,
+    Username,
+    /// Argon2 PHC string
+    PasswordHash,
+    CreatedAt,
+}
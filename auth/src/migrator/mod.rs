@@ -0,0 +1,16 @@
+pub struct Migrator;
+
+use sea_orm_migration::MigratorTrait;
+
+pub mod m20261018_000001_credentials_table;
+pub mod m20261018_000002_refresh_tokens_table;
+
+#[sea_orm_migration::async_trait::async_trait]
+impl MigratorTrait for Migrator {
+    fn migrations() -> Vec<Box<dyn sea_orm_migration::MigrationTrait>> {
+        vec![
+            Box::new(m20261018_000001_credentials_table::Migration),
+            Box::new(m20261018_000002_refresh_tokens_table::Migration),
+        ]
+    }
+}
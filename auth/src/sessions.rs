@@ -0,0 +1,219 @@
+//! Access tokens are short-lived, a session lives on through its refresh
+//! token which is rotated on every use. Only hashes of refresh tokens are
+//! stored, and every access token names its session through its `jti`, so
+//! revoking the session stops its access token right away.
+
+use std::time::Duration;
+
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use aws_common::api::errors::AwsError;
+use sea_orm::{
+    ActiveModelTrait, ActiveValue, ColumnTrait, DatabaseConnection, DbErr, EntityTrait, QueryFilter,
+};
+use sha2::{Digest, Sha256};
+
+use crate::{entities::refresh_token, now_ms};
+
+/// Sessions not refreshed for this long have to log in again
+const REFRESH_TOKEN_VALIDITY: Duration = Duration::from_secs(60 * 60 * 24 * 30);
+
+fn random_hex() -> String {
+    let mut bytes = [0u8; 16];
+    OsRng.fill_bytes(&mut bytes);
+
+    format!("{:032x}", u128::from_le_bytes(bytes))
+}
+
+/// 256 random bits, in hex
+fn random_token() -> String {
+    format!("{}{}", random_hex(), random_hex())
+}
+
+/// SHA-256 of a token, in hex. Tokens are random enough to not need a
+/// slow hash
+fn hash(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+/// Tokens of a session, the access token's claims still have to be signed
+pub struct Session {
+    pub user_id: i32,
+    pub jti: String,
+    pub refresh_token: String,
+}
+
+fn expires_at() -> i64 {
+    now_ms() + REFRESH_TOKEN_VALIDITY.as_millis() as i64
+}
+
+/// Starts a session for the user, dropping their expired ones
+pub async fn start(db: &DatabaseConnection, user_id: i32) -> Result<Session, AwsError> {
+    refresh_token::Entity::delete_many()
+        .filter(refresh_token::Column::UserId.eq(user_id))
+        .filter(refresh_token::Column::ExpiresAt.lt(now_ms()))
+        .exec(db)
+        .await
+        .map_err(|_| AwsError::UnknownServerError)?;
+
+    let session = Session {
+        user_id,
+        jti: random_hex(),
+        refresh_token: random_token(),
+    };
+
+    refresh_token::ActiveModel {
+        user_id: ActiveValue::set(user_id),
+        token_hash: ActiveValue::set(hash(&session.refresh_token)),
+        jti: ActiveValue::set(session.jti.clone()),
+        created_at: ActiveValue::set(now_ms()),
+        expires_at: ActiveValue::set(expires_at()),
+        ..Default::default()
+    }
+    .insert(db)
+    .await
+    .map_err(|_| AwsError::UnknownServerError)?;
+
+    Ok(session)
+}
+
+/// Trades a refresh token for new tokens of its session. A token already
+/// rotated away was leaked or stolen, so presenting it revokes the session
+pub async fn rotate(db: &DatabaseConnection, refresh_token: &str) -> Result<Session, AwsError> {
+    let token_hash = hash(refresh_token);
+
+    let Some(current) = refresh_token::Entity::find()
+        .filter(refresh_token::Column::TokenHash.eq(&token_hash))
+        .one(db)
+        .await
+        .map_err(|_| AwsError::UnknownServerError)?
+    else {
+        let reused = refresh_token::Entity::find()
+            .filter(refresh_token::Column::PreviousHash.eq(&token_hash))
+            .one(db)
+            .await
+            .map_err(|_| AwsError::UnknownServerError)?;
+
+        if let Some(reused) = reused {
+            tracing::warn!(
+                "Refresh token of session {id} reused, revoking it",
+                id = reused.id
+            );
+
+            revoke(db, &reused.jti)
+                .await
+                .map_err(|_| AwsError::UnknownServerError)?;
+        }
+
+        return Err(AwsError::InvalidRefreshToken);
+    };
+
+    if current.revoked_at.is_some() || current.expires_at < now_ms() {
+        return Err(AwsError::InvalidRefreshToken);
+    }
+
+    let session = Session {
+        user_id: current.user_id,
+        jti: random_hex(),
+        refresh_token: random_token(),
+    };
+
+    // Concurrent refreshes only rotate the token once
+    let res = refresh_token::Entity::update_many()
+        .col_expr(
+            refresh_token::Column::TokenHash,
+            hash(&session.refresh_token).into(),
+        )
+        .col_expr(
+            refresh_token::Column::PreviousHash,
+            token_hash.clone().into(),
+        )
+        .col_expr(refresh_token::Column::Jti, session.jti.clone().into())
+        .col_expr(refresh_token::Column::ExpiresAt, expires_at().into())
+        .filter(refresh_token::Column::Id.eq(current.id))
+        .filter(refresh_token::Column::TokenHash.eq(token_hash))
+        .filter(refresh_token::Column::RevokedAt.is_null())
+        .exec(db)
+        .await
+        .map_err(|_| AwsError::UnknownServerError)?;
+
+    if res.rows_affected != 1 {
+        return Err(AwsError::InvalidRefreshToken);
+    }
+
+    Ok(session)
+}
+
+/// Ends the session the access token `jti` belongs to
+pub async fn revoke(db: &DatabaseConnection, jti: &str) -> Result<(), DbErr> {
+    refresh_token::Entity::update_many()
+        .col_expr(refresh_token::Column::RevokedAt, now_ms().into())
+        .filter(refresh_token::Column::Jti.eq(jti))
+        .filter(refresh_token::Column::RevokedAt.is_null())
+        .exec(db)
+        .await?;
+
+    Ok(())
+}
+
+/// Whether the access token `jti` of the user is the latest of a live
+/// session. Sessions of deleted users are deleted along with them, which
+/// is how the backend learns of logouts and deletions right away
+pub async fn is_active(db: &DatabaseConnection, user_id: i32, jti: &str) -> Result<bool, DbErr> {
+    let session = refresh_token::Entity::find()
+        .filter(refresh_token::Column::UserId.eq(user_id))
+        .filter(refresh_token::Column::Jti.eq(jti))
+        .filter(refresh_token::Column::RevokedAt.is_null())
+        .one(db)
+        .await?;
+
+    Ok(session.is_some())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{entities, migrator::Migrator};
+    use sea_orm::Database;
+    use sea_orm_migration::MigratorTrait;
+
+    #[tokio::test]
+    async fn test_refresh_tokens_rotate_and_revoke() {
+        let db = Database::connect("sqlite::memory:").await.unwrap();
+        Migrator::up(&db, None).await.unwrap();
+
+        let user = entities::credential::ActiveModel {
+            username: ActiveValue::set("user".to_string()),
+            password_hash: ActiveValue::set(String::new()),
+            created_at: ActiveValue::set(now_ms()),
+            ..Default::default()
+        }
+        .insert(&db)
+        .await
+        .unwrap();
+
+        let first = start(&db, user.id).await.unwrap();
+        assert!(is_active(&db, user.id, &first.jti).await.unwrap());
+
+        let second = rotate(&db, &first.refresh_token).await.unwrap();
+        assert_eq!(second.user_id, user.id);
+        assert!(!is_active(&db, user.id, &first.jti).await.unwrap());
+        assert!(is_active(&db, user.id, &second.jti).await.unwrap());
+
+        // Reusing the rotated token revokes the whole session
+        assert!(matches!(
+            rotate(&db, &first.refresh_token).await,
+            Err(AwsError::InvalidRefreshToken)
+        ));
+        assert!(!is_active(&db, user.id, &second.jti).await.unwrap());
+        assert!(matches!(
+            rotate(&db, &second.refresh_token).await,
+            Err(AwsError::InvalidRefreshToken)
+        ));
+
+        let other = start(&db, user.id).await.unwrap();
+        revoke(&db, &other.jti).await.unwrap();
+        assert!(!is_active(&db, user.id, &other.jti).await.unwrap());
+        assert!(rotate(&db, &other.refresh_token).await.is_err());
+        assert!(rotate(&db, "unknown").await.is_err());
+    }
+}
@@ -0,0 +1,147 @@
+use std::{collections::HashMap, net::SocketAddr, sync::Mutex};
+
+use aws_common::api::{
+    auth::{
+        verify_request_signature, SignedRequest, NONCE_HEADER, SIGNATURE_HEADER,
+        SIGNATURE_MAX_SKEW_MS, TIMESTAMP_HEADER,
+    },
+    errors::AwsError,
+};
+use axum::{
+    async_trait,
+    body::{Bytes, HttpBody},
+    extract::{ConnectInfo, FromRequest, OriginalUri},
+    http::Request,
+    BoxError,
+};
+use lazy_static::lazy_static;
+use serde::de::DeserializeOwned;
+
+use crate::{now_ms, AUTH_SHARED_SECRET};
+
+lazy_static! {
+    static ref NONCES: Nonces = Nonces::default();
+}
+
+/// Nonces of the signed requests accepted within the last skew, so none
+/// of them is accepted twice. Older requests are rejected by their
+/// timestamp already
+#[derive(Default)]
+struct Nonces {
+    seen: Mutex<HashMap<String, i64>>,
+}
+
+impl Nonces {
+    /// Whether the nonce of a request signed at `timestamp` wasn't seen
+    /// yet, remembering it
+    fn first_use(&self, nonce: &str, timestamp: i64, now: i64) -> bool {
+        let mut seen = self.seen.lock().unwrap();
+
+        seen.retain(|_, at| now.abs_diff(*at) <= SIGNATURE_MAX_SKEW_MS as u64);
+
+        seen.insert(nonce.to_string(), timestamp).is_none()
+    }
+}
+
+/// JSON body of a request signed with the shared secret, for the method
+/// and path it was sent to. Requests without a body read as `null`
+pub struct Signed<T>(pub T);
+
+#[async_trait]
+impl<S, B, T> FromRequest<S, B> for Signed<T>
+where
+    B: HttpBody + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<BoxError>,
+    S: Send + Sync,
+    T: DeserializeOwned,
+{
+    type Rejection = AwsError;
+
+    async fn from_request(req: Request<B>, state: &S) -> Result<Self, Self::Rejection> {
+        let method = req.method().to_string();
+        // Nested routers see the path without their prefix
+        let path = match req.extensions().get::<OriginalUri>() {
+            Some(OriginalUri(uri)) => uri.path().to_string(),
+            None => req.uri().path().to_string(),
+        };
+        let peer = req
+            .extensions()
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(peer)| *peer);
+
+        let timestamp = req
+            .headers()
+            .get(TIMESTAMP_HEADER)
+            .and_then(|timestamp| timestamp.to_str().ok())
+            .and_then(|timestamp| timestamp.parse::<i64>().ok());
+        let nonce = req
+            .headers()
+            .get(NONCE_HEADER)
+            .and_then(|nonce| nonce.to_str().ok())
+            .map(ToString::to_string);
+        let signature = req
+            .headers()
+            .get(SIGNATURE_HEADER)
+            .and_then(|signature| signature.to_str().ok())
+            .map(ToString::to_string);
+
+        let body = Bytes::from_request(req, state)
+            .await
+            .map_err(|_| AwsError::Unauthorized)?;
+
+        let now = now_ms();
+
+        let authenticated = match (timestamp, nonce, signature) {
+            (Some(timestamp), Some(nonce), Some(signature)) => {
+                verify_request_signature(
+                    &AUTH_SHARED_SECRET,
+                    &SignedRequest {
+                        timestamp,
+                        nonce: &nonce,
+                        method: &method,
+                        path: &path,
+                        body: &body,
+                    },
+                    &signature,
+                    now,
+                ) && NONCES.first_use(&nonce, timestamp, now)
+            }
+            _ => false,
+        };
+
+        if !authenticated {
+            tracing::warn!(
+                target: "audit",
+                "Rejected unauthenticated request to {method} {path} from {peer:?}"
+            );
+            return Err(AwsError::Unauthorized);
+        }
+
+        let body: &[u8] = if body.is_empty() { b"null" } else { &body };
+
+        serde_json::from_slice(body)
+            .map(Signed)
+            .map_err(|_| AwsError::InvalidCredentials)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nonces_are_accepted_once_within_the_skew() {
+        let nonces = Nonces::default();
+
+        assert!(nonces.first_use("a", 1_000, 1_000));
+        assert!(!nonces.first_use("a", 1_000, 1_500));
+        assert!(nonces.first_use("b", 1_000, 1_500));
+
+        // Past the skew the timestamp rejects the request, the nonce is
+        // forgotten
+        let later = 1_000 + SIGNATURE_MAX_SKEW_MS + 1;
+        assert!(nonces.first_use("c", later, later));
+        assert_eq!(nonces.seen.lock().unwrap().len(), 1);
+    }
+}
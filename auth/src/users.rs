@@ -0,0 +1,255 @@
+//! Credentials of every user, passwords only stored hashed with Argon2.
+//! Users are numbered here, the backend keeping the same ids for their
+//! accounts.
+
+use argon2::{
+    password_hash::{rand_core::OsRng, SaltString},
+    Argon2, PasswordHash, PasswordHasher, PasswordVerifier,
+};
+use aws_common::api::{auth::ImportedCredentials, errors::AwsError};
+use sea_orm::{
+    ActiveModelTrait, ActiveValue, ColumnTrait, DatabaseConnection, DbErr, EntityTrait,
+    QueryFilter, RuntimeErr, SqlxError, SqlxMySqlError,
+};
+
+use crate::{
+    entities::{credential, refresh_token},
+    now_ms,
+};
+
+const MINIMUM_PASSWORD_LENGTH: usize = 12;
+
+/// Whether the statement failed for breaking a unique constraint
+fn is_unique_violation(e: &DbErr) -> bool {
+    let (DbErr::Exec(RuntimeErr::SqlxError(SqlxError::Database(e)))
+    | DbErr::Query(RuntimeErr::SqlxError(SqlxError::Database(e)))) = e
+    else {
+        return false;
+    };
+
+    if let Some(e) = e.try_downcast_ref::<SqlxMySqlError>() {
+        return e.number() == 1062;
+    }
+
+    // Extended SQLite codes of unique and primary key constraints
+    matches!(e.code().as_deref(), Some("2067" | "1555"))
+}
+
+/// Error of a failed insert of credentials, a duplicate username only
+/// when it broke a unique constraint
+fn insert_error(e: DbErr) -> AwsError {
+    if is_unique_violation(&e) {
+        return AwsError::DuplicateUsername;
+    }
+
+    tracing::error!("Inserting credentials {e:#?}");
+
+    AwsError::UnknownServerError
+}
+
+fn password_secure_check(pass: &str) -> bool {
+    pass.chars().any(|c| c.is_ascii_digit())
+        && pass.chars().any(|c| c.is_uppercase())
+        && pass.chars().any(|c| c.is_lowercase())
+        && pass.chars().any(|c| !c.is_alphabetic())
+}
+
+pub async fn register(
+    db: &DatabaseConnection,
+    username: &str,
+    password: &str,
+) -> Result<credential::Model, AwsError> {
+    if password.len() < MINIMUM_PASSWORD_LENGTH {
+        return Err(AwsError::PasswordTooShort);
+    }
+
+    if !password_secure_check(password) {
+        return Err(AwsError::PasswordTooWeak);
+    }
+
+    let salt = SaltString::generate(&mut OsRng);
+
+    let password_hash = Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map_err(|_| AwsError::UnknownServerError)?
+        .to_string();
+
+    credential::ActiveModel {
+        username: ActiveValue::set(username.to_string()),
+        password_hash: ActiveValue::set(password_hash),
+        created_at: ActiveValue::set(now_ms()),
+        ..Default::default()
+    }
+    .insert(db)
+    .await
+    .map_err(insert_error)
+}
+
+/// Credentials of the user, if `password` is theirs
+pub async fn verify(
+    db: &DatabaseConnection,
+    username: &str,
+    password: &str,
+) -> Result<credential::Model, AwsError> {
+    let user = credential::Entity::find()
+        .filter(credential::Column::Username.eq(username))
+        .one(db)
+        .await
+        .map_err(|_| AwsError::UnknownServerError)?
+        .ok_or(AwsError::InvalidCredentials)?;
+
+    let password_hash =
+        PasswordHash::new(&user.password_hash).map_err(|_| AwsError::UnknownServerError)?;
+
+    Argon2::default()
+        .verify_password(password.as_bytes(), &password_hash)
+        .map_err(|_| AwsError::InvalidCredentials)?;
+
+    Ok(user)
+}
+
+pub async fn find(db: &DatabaseConnection, uid: i32) -> Result<credential::Model, AwsError> {
+    credential::Entity::find_by_id(uid)
+        .one(db)
+        .await
+        .map_err(|_| AwsError::UnknownServerError)?
+        .ok_or_else(|| AwsError::UserNotFound(uid.to_string()))
+}
+
+/// Deletes the credentials of the user and ends all of their sessions
+pub async fn delete(db: &DatabaseConnection, uid: i32) -> Result<(), AwsError> {
+    refresh_token::Entity::delete_many()
+        .filter(refresh_token::Column::UserId.eq(uid))
+        .exec(db)
+        .await
+        .map_err(|_| AwsError::UnknownServerError)?;
+
+    credential::Entity::delete_by_id(uid)
+        .exec(db)
+        .await
+        .map_err(|_| AwsError::UnknownServerError)?;
+
+    Ok(())
+}
+
+/// Takes over credentials the backend stored, keeping the user's id.
+/// Importing them again is a no-op
+pub async fn import(
+    db: &DatabaseConnection,
+    imported: ImportedCredentials,
+) -> Result<(), AwsError> {
+    PasswordHash::new(&imported.password_hash).map_err(|_| AwsError::InvalidCredentials)?;
+
+    let existing = credential::Entity::find_by_id(imported.uid)
+        .one(db)
+        .await
+        .map_err(|_| AwsError::UnknownServerError)?;
+
+    match existing {
+        Some(user) if user.username == imported.username => Ok(()),
+        Some(_) => Err(AwsError::DuplicateUsername),
+        None => {
+            credential::ActiveModel {
+                id: ActiveValue::set(imported.uid),
+                username: ActiveValue::set(imported.username),
+                password_hash: ActiveValue::set(imported.password_hash),
+                created_at: ActiveValue::set(now_ms()),
+            }
+            .insert(db)
+            .await
+            .map_err(insert_error)?;
+
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{migrator::Migrator, sessions};
+    use sea_orm::Database;
+    use sea_orm_migration::MigratorTrait;
+
+    #[tokio::test]
+    async fn test_credentials_are_verified() {
+        let db = Database::connect("sqlite::memory:").await.unwrap();
+        Migrator::up(&db, None).await.unwrap();
+
+        assert!(matches!(
+            register(&db, "user", "Short1!").await,
+            Err(AwsError::PasswordTooShort)
+        ));
+        assert!(matches!(
+            register(&db, "user", "onlylowercaseletters").await,
+            Err(AwsError::PasswordTooWeak)
+        ));
+
+        let user = register(&db, "user", "Correct-Horse-1").await.unwrap();
+        assert_ne!(user.password_hash, "Correct-Horse-1");
+        assert!(matches!(
+            register(&db, "user", "Correct-Horse-2").await,
+            Err(AwsError::DuplicateUsername)
+        ));
+
+        assert_eq!(
+            verify(&db, "user", "Correct-Horse-1").await.unwrap().id,
+            user.id
+        );
+        assert!(matches!(
+            verify(&db, "user", "Correct-Horse-2").await,
+            Err(AwsError::InvalidCredentials)
+        ));
+        assert!(matches!(
+            verify(&db, "other", "Correct-Horse-1").await,
+            Err(AwsError::InvalidCredentials)
+        ));
+
+        let session = sessions::start(&db, user.id).await.unwrap();
+        delete(&db, user.id).await.unwrap();
+        assert!(!sessions::is_active(&db, user.id, &session.jti)
+            .await
+            .unwrap());
+        assert!(verify(&db, "user", "Correct-Horse-1").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_imported_credentials_keep_their_id() {
+        let db = Database::connect("sqlite::memory:").await.unwrap();
+        Migrator::up(&db, None).await.unwrap();
+
+        let salt = SaltString::generate(&mut OsRng);
+        let password_hash = Argon2::default()
+            .hash_password(b"Correct-Horse-1", &salt)
+            .unwrap()
+            .to_string();
+
+        let imported = |uid: i32, username: &str| ImportedCredentials {
+            uid,
+            username: username.to_string(),
+            password_hash: password_hash.clone(),
+        };
+
+        import(&db, imported(42, "user")).await.unwrap();
+        import(&db, imported(42, "user")).await.unwrap();
+        assert!(matches!(
+            import(&db, imported(42, "other")).await,
+            Err(AwsError::DuplicateUsername)
+        ));
+        assert!(import(
+            &db,
+            ImportedCredentials {
+                password_hash: "plaintext".to_string(),
+                ..imported(43, "other")
+            }
+        )
+        .await
+        .is_err());
+
+        assert_eq!(verify(&db, "user", "Correct-Horse-1").await.unwrap().id, 42);
+
+        // Users registered afterwards don't take imported ids
+        let registered = register(&db, "other", "Correct-Horse-1").await.unwrap();
+        assert!(registered.id > 42);
+    }
+}
@@ -0,0 +1,509 @@
+//! Guest ABI for values that don't fit in a wasm scalar.
+//!
+//! Besides `i32`, `i64`, `f32` and `f64`, signatures may use `string`,
+//! `bytes` and `json`. These are passed through the guest's exported
+//! `memory`:
+//!
+//! - a parameter is lowered to an `i32` pointer and `i32` length pair.
+//!   The host gets the buffer from the guest's exported
+//!   `alloc(len: i32) -> i32`, copies the value in and hands ownership
+//!   to the guest.
+//! - a result is returned as a single `i64` packing the pointer in its
+//!   high 32 bits and the length in its low 32 bits. The host copies it
+//!   out and frees it with the guest's exported `dealloc(ptr: i32, len: i32)`.
+//!
+//! Strings are UTF-8, bytes are base64 encoded in requests and
+//! responses and JSON documents are passed as their UTF-8 serialization.
+//!
+//! Guests declare these signatures in a custom section named
+//! `aws.signatures` holding a JSON object from export name to
+//! signature, e.g. `{"greet": "string->string"}`. Exports without a
+//! declaration get the signature of their wasm type.
+
+use std::{collections::HashMap, fmt, str::FromStr};
+
+use aws_common::api::{errors::AwsError, responses::scalar_to_json};
+use base64::Engine;
+use wasmer::{AsStoreMut, ExternType, FunctionType, Instance, Module, RuntimeError, Value};
+
+use crate::constants::ABI_RESULT_LIMIT;
+use crate::ffi::Type;
+
+pub const SIGNATURES_SECTION: &str = "aws.signatures";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AbiType {
+    Scalar(wasmer::Type),
+    String,
+    Bytes,
+    Json,
+}
+
+impl AbiType {
+    fn lowered_params(self) -> Vec<wasmer::Type> {
+        match self {
+            AbiType::Scalar(ty) => vec![ty],
+            _ => vec![wasmer::Type::I32, wasmer::Type::I32],
+        }
+    }
+
+    fn lowered_result(self) -> wasmer::Type {
+        match self {
+            AbiType::Scalar(ty) => ty,
+            _ => wasmer::Type::I64,
+        }
+    }
+
+    fn is_buffer(self) -> bool {
+        !matches!(self, AbiType::Scalar(_))
+    }
+}
+
+impl FromStr for AbiType {
+    type Err = AwsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "string" => Ok(AbiType::String),
+            "bytes" => Ok(AbiType::Bytes),
+            "json" => Ok(AbiType::Json),
+            scalar => Type::try_from(scalar).map(|ty| AbiType::Scalar(ty.0)),
+        }
+    }
+}
+
+impl fmt::Display for AbiType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            AbiType::Scalar(ty) => Type(*ty).try_into().map_err(|_| fmt::Error)?,
+            AbiType::String => "string",
+            AbiType::Bytes => "bytes",
+            AbiType::Json => "json",
+        };
+
+        f.write_str(name)
+    }
+}
+
+/// Signature of an exported function, as stored in `function.signature`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Signature {
+    pub params: Vec<AbiType>,
+    pub results: Vec<AbiType>,
+}
+
+impl Signature {
+    pub fn from_wasm(ty: &FunctionType) -> Result<Self, AwsError> {
+        let scalars = |types: &[wasmer::Type]| {
+            types
+                .iter()
+                .map(|ty| {
+                    // Fails on types the API can't represent
+                    let _: &str = Type(*ty).try_into()?;
+                    Ok(AbiType::Scalar(*ty))
+                })
+                .collect::<Result<Vec<_>, AwsError>>()
+        };
+
+        Ok(Self {
+            params: scalars(ty.params())?,
+            results: scalars(ty.results())?,
+        })
+    }
+
+    /// Wasm type of the export implementing the signature
+    pub fn lowered(&self) -> FunctionType {
+        FunctionType::new(
+            self.params
+                .iter()
+                .flat_map(|ty| ty.lowered_params())
+                .collect::<Vec<_>>(),
+            self.results
+                .iter()
+                .map(|ty| ty.lowered_result())
+                .collect::<Vec<_>>(),
+        )
+    }
+
+    fn uses_buffers(&self) -> bool {
+        self.params
+            .iter()
+            .chain(&self.results)
+            .any(|ty| ty.is_buffer())
+    }
+}
+
+impl FromStr for Signature {
+    type Err = AwsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (params, results) = s
+            .split_once("->")
+            .ok_or_else(|| AwsError::InvalidSignature(s.to_string()))?;
+
+        let types = |types: &str| {
+            types
+                .split(',')
+                .filter(|ty| !ty.is_empty())
+                .map(str::parse)
+                .collect::<Result<Vec<_>, _>>()
+        };
+
+        Ok(Self {
+            params: types(params)?,
+            results: types(results)?,
+        })
+    }
+}
+
+impl fmt::Display for Signature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let join = |types: &[AbiType]| {
+            types
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(",")
+        };
+
+        write!(f, "{}->{}", join(&self.params), join(&self.results))
+    }
+}
+
+/// Signatures of every exported function of `module`, checking the
+/// declared ones against the exports implementing them
+pub fn exported_signatures(module: &Module) -> Result<Vec<(String, Signature)>, AwsError> {
+    let mut declared = HashMap::new();
+
+    for section in module.custom_sections(SIGNATURES_SECTION) {
+        let signatures = serde_json::from_slice::<HashMap<String, String>>(&section)
+            .map_err(|e| AwsError::InvalidSignature(e.to_string()))?;
+
+        for (name, signature) in signatures {
+            let parsed = signature.parse::<Signature>()?;
+            declared.insert(name, (signature, parsed));
+        }
+    }
+
+    let mut signatures = Vec::new();
+
+    for export in module.exports() {
+        let ExternType::Function(ty) = export.ty() else {
+            continue;
+        };
+
+        let signature = match declared.remove(export.name()) {
+            Some((_, signature)) if signature.lowered() == *ty => signature,
+            Some((raw, _)) => return Err(AwsError::InvalidSignature(raw)),
+            None => Signature::from_wasm(ty)?,
+        };
+
+        signatures.push((export.name().to_string(), signature));
+    }
+
+    // Declarations must all refer to exported functions
+    if let Some((_, (raw, _))) = declared.into_iter().next() {
+        return Err(AwsError::InvalidSignature(raw));
+    }
+
+    if signatures.iter().any(|(_, s)| s.uses_buffers()) {
+        check_buffer_exports(module)?;
+    }
+
+    Ok(signatures)
+}
+
+fn check_buffer_exports(module: &Module) -> Result<(), AwsError> {
+    let find = |name: &str| {
+        module
+            .exports()
+            .find(|e| e.name() == name)
+            .map(|e| e.ty().clone())
+    };
+
+    let alloc = FunctionType::new([wasmer::Type::I32], [wasmer::Type::I32]);
+    let dealloc = FunctionType::new([wasmer::Type::I32, wasmer::Type::I32], []);
+
+    match (find("memory"), find("alloc"), find("dealloc")) {
+        (
+            Some(ExternType::Memory(_)),
+            Some(ExternType::Function(a)),
+            Some(ExternType::Function(d)),
+        ) if a == alloc && d == dealloc => Ok(()),
+        _ => Err(AwsError::InvalidGuestAbi(
+            "buffer types need exported memory, alloc(i32)->i32 and dealloc(i32,i32)".to_string(),
+        )),
+    }
+}
+
+/// Parameter value before it is copied into the guest
+#[derive(Clone, Debug, PartialEq)]
+pub enum AbiValue {
+    Scalar(Value),
+    Buffer(Vec<u8>),
+}
+
+impl AbiValue {
+    pub fn from_json(v: &serde_json::Value, ty: AbiType) -> Result<Self, AwsError> {
+        match (ty, v) {
+            (AbiType::Scalar(ty), v) => crate::ffi::to_wasm_value(v, ty).map(AbiValue::Scalar),
+            (AbiType::String, serde_json::Value::String(s)) => {
+                Ok(AbiValue::Buffer(s.as_bytes().to_vec()))
+            }
+            (AbiType::Bytes, serde_json::Value::String(s)) => {
+                base64::engine::general_purpose::STANDARD
+                    .decode(s)
+                    .map(AbiValue::Buffer)
+                    .map_err(|_| AwsError::WasmTypeConversionError)
+            }
+            (AbiType::Json, v) => serde_json::to_vec(v)
+                .map(AbiValue::Buffer)
+                .map_err(|_| AwsError::WasmTypeConversionError),
+            _ => Err(AwsError::WasmTypeConversionError),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum AbiError {
+    /// The guest trapped while running
+    Trap(RuntimeError),
+    /// The guest broke the ABI
+    Invalid(String),
+}
+
+impl From<RuntimeError> for AbiError {
+    fn from(e: RuntimeError) -> Self {
+        AbiError::Trap(e)
+    }
+}
+
+impl From<AbiError> for AwsError {
+    fn from(e: AbiError) -> Self {
+        match e {
+            AbiError::Trap(e) => {
+                tracing::error!("Guest trapped outside of the call {e:#?}");
+                AwsError::UnknownServerError
+            }
+            AbiError::Invalid(reason) => AwsError::InvalidGuestAbi(reason),
+        }
+    }
+}
+
+fn invalid(e: impl ToString) -> AbiError {
+    AbiError::Invalid(e.to_string())
+}
+
+/// Copies the parameters into the guest, returning the wasm values to
+/// call the function with
+pub fn lower(
+    store: &mut impl AsStoreMut,
+    instance: &Instance,
+    params: &[AbiValue],
+) -> Result<Vec<Value>, AbiError> {
+    let mut lowered = Vec::with_capacity(params.len());
+
+    for param in params {
+        match param {
+            AbiValue::Scalar(v) => lowered.push(v.clone()),
+            AbiValue::Buffer(buf) => {
+                let len = i32::try_from(buf.len()).map_err(invalid)?;
+
+                let ptr = instance
+                    .exports
+                    .get_typed_function::<i32, i32>(store, "alloc")
+                    .map_err(invalid)?
+                    .call(store, len)?;
+
+                instance
+                    .exports
+                    .get_memory("memory")
+                    .map_err(invalid)?
+                    .view(store)
+                    .write(ptr as u32 as u64, buf)
+                    .map_err(invalid)?;
+
+                lowered.extend([Value::I32(ptr), Value::I32(len)]);
+            }
+        }
+    }
+
+    Ok(lowered)
+}
+
+/// Copies the results out of the guest, releasing its buffers
+pub fn lift(
+    store: &mut impl AsStoreMut,
+    instance: &Instance,
+    types: &[AbiType],
+    results: &[Value],
+) -> Result<Vec<serde_json::Value>, AbiError> {
+    types
+        .iter()
+        .zip(results)
+        .map(|(ty, v)| {
+            let packed = match (ty, v) {
+                (AbiType::Scalar(_), v) => {
+                    return scalar_to_json(v)
+                        .map_err(|_| invalid(format!("unsupported {ty} result")))
+                }
+                (_, Value::I64(packed)) => *packed as u64,
+                _ => return Err(invalid(format!("expected a packed {ty} result"))),
+            };
+
+            let (ptr, len) = ((packed >> 32) as u32, packed as u32);
+            let memory = instance.exports.get_memory("memory").map_err(invalid)?;
+            let view = memory.view(store);
+
+            if len as usize > ABI_RESULT_LIMIT {
+                return Err(invalid(format!(
+                    "{ty} result over {ABI_RESULT_LIMIT} bytes"
+                )));
+            }
+            if ptr as u64 + len as u64 > view.data_size() {
+                return Err(invalid(format!("{ty} result out of bounds")));
+            }
+
+            let mut buf = vec![0; len as usize];
+            view.read(ptr as u64, &mut buf).map_err(invalid)?;
+
+            instance
+                .exports
+                .get_typed_function::<(i32, i32), ()>(store, "dealloc")
+                .map_err(invalid)?
+                .call(store, ptr as i32, len as i32)?;
+
+            match ty {
+                AbiType::String => String::from_utf8(buf)
+                    .map(serde_json::Value::String)
+                    .map_err(invalid),
+                AbiType::Bytes => Ok(serde_json::Value::String(
+                    base64::engine::general_purpose::STANDARD.encode(buf),
+                )),
+                _ => serde_json::from_slice(&buf).map_err(invalid),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::metered_store;
+    use wasmer::{imports, wat2wasm};
+    use wasmer_middlewares::metering::set_remaining_points;
+
+    // Bump allocator with a `reverse` function over bytes, declaring
+    // `reverse` as `string->string`
+    const GUEST: &str = r#"
+        (module
+            (memory (export "memory") 1)
+            (global $next (mut i32) (i32.const 1024))
+            (func (export "alloc") (param $len i32) (result i32)
+                (global.get $next)
+                (global.set $next (i32.add (global.get $next) (local.get $len))))
+            (func (export "dealloc") (param i32 i32))
+            (func (export "reverse") (param $ptr i32) (param $len i32) (result i64)
+                (local $out i32) (local $i i32)
+                (local.set $out (global.get $next))
+                (global.set $next (i32.add (global.get $next) (local.get $len)))
+                (block $done
+                    (loop $copy
+                        (br_if $done (i32.ge_u (local.get $i) (local.get $len)))
+                        (i32.store8
+                            (i32.add (local.get $out) (local.get $i))
+                            (i32.load8_u
+                                (i32.sub
+                                    (i32.add (local.get $ptr) (local.get $len))
+                                    (i32.add (local.get $i) (i32.const 1)))))
+                        (local.set $i (i32.add (local.get $i) (i32.const 1)))
+                        (br $copy)))
+                (i64.or
+                    (i64.shl (i64.extend_i32_u (local.get $out)) (i64.const 32))
+                    (i64.extend_i32_u (local.get $len))))
+            (@custom "aws.signatures" "{\"reverse\": \"string->string\"}"))
+    "#;
+
+    #[test]
+    fn test_signatures_round_trip() {
+        let signature = "i32,string,json->bytes,f64".parse::<Signature>().unwrap();
+
+        assert_eq!(signature.to_string(), "i32,string,json->bytes,f64");
+        assert_eq!(
+            signature.lowered().params(),
+            &[
+                wasmer::Type::I32,
+                wasmer::Type::I32,
+                wasmer::Type::I32,
+                wasmer::Type::I32,
+                wasmer::Type::I32
+            ]
+        );
+        assert_eq!(
+            signature.lowered().results(),
+            &[wasmer::Type::I64, wasmer::Type::F64]
+        );
+        assert!("i32,text->".parse::<Signature>().is_err());
+    }
+
+    #[test]
+    fn test_strings_are_passed_through_memory() {
+        let mut store = metered_store();
+        let module = Module::new(&store, wat2wasm(GUEST.as_bytes()).unwrap()).unwrap();
+
+        let signatures = exported_signatures(&module).unwrap();
+        let (_, signature) = signatures
+            .iter()
+            .find(|(name, _)| name == "reverse")
+            .unwrap();
+        assert_eq!(signature.to_string(), "string->string");
+
+        let instance = Instance::new(&mut store, &module, &imports! {}).unwrap();
+        set_remaining_points(&mut store, &instance, 1_000_000);
+
+        let param = AbiValue::from_json(&serde_json::json!("hello"), AbiType::String).unwrap();
+        let params = lower(&mut store, &instance, &[param]).unwrap();
+
+        let results = instance
+            .exports
+            .get_function("reverse")
+            .unwrap()
+            .call(&mut store, &params)
+            .unwrap();
+
+        let lifted = lift(&mut store, &instance, &signature.results, &results).unwrap();
+
+        assert_eq!(lifted, vec![serde_json::json!("olleh")]);
+    }
+
+    #[test]
+    fn test_results_outside_memory_are_rejected() {
+        let mut store = metered_store();
+        let module = Module::new(&store, wat2wasm(GUEST.as_bytes()).unwrap()).unwrap();
+        let instance = Instance::new(&mut store, &module, &imports! {}).unwrap();
+        set_remaining_points(&mut store, &instance, 1_000_000);
+
+        let packed = |ptr: u32, len: u32| Value::I64(((ptr as u64) << 32 | len as u64) as i64);
+
+        for results in [packed(0, u32::MAX), packed(65_530, 16)] {
+            assert!(matches!(
+                lift(&mut store, &instance, &[AbiType::Bytes], &[results]),
+                Err(AbiError::Invalid(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_declarations_must_match_exports() {
+        let store = metered_store();
+        let wat = r#"(module
+            (func (export "f") (param i32))
+            (@custom "aws.signatures" "{\"f\": \"string->\"}"))"#;
+        let module = Module::new(&store, wat2wasm(wat.as_bytes()).unwrap()).unwrap();
+
+        assert!(matches!(
+            exported_signatures(&module),
+            Err(AwsError::InvalidSignature(_))
+        ));
+    }
+}
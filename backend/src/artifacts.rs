@@ -0,0 +1,145 @@
+use aws_common::api::errors::AwsError;
+use bytes::Bytes;
+use sea_orm::{
+    sea_query::OnConflict, ActiveValue, ColumnTrait, ConnectionTrait, DatabaseConnection, DbErr,
+    EntityTrait, QueryFilter,
+};
+use wasmer::{Module, Store};
+
+use crate::{
+    cache::{CacheKey, ModuleCache},
+    entities::{self, module_artifact as Artifact},
+    pricing,
+    utils::METERED_ENGINE_VERSION,
+};
+
+/// Identifies the engine artifacts are compiled with, they can only be
+/// loaded back by the same wasmer version and metering configuration
+pub fn engine_id() -> String {
+    format!(
+        "wasmer-{}-metered-v{}-{:016x}",
+        wasmer::VERSION,
+        METERED_ENGINE_VERSION,
+        pricing::current().opcodes_id()
+    )
+}
+
+/// Compiles `code` with the engine of `store`, returning the module
+/// along with its serialized artifact
+pub fn compile(store: &Store, code: &[u8]) -> Result<(Module, Bytes), AwsError> {
+    let module = Module::from_binary(store, code).map_err(|_| AwsError::InvalidWasmModule)?;
+
+    let artifact = module
+        .serialize()
+        .map_err(|_| AwsError::UnknownServerError)?;
+
+    Ok((module, artifact))
+}
+
+pub async fn save_artifact<C: ConnectionTrait>(
+    conn: &C,
+    code_hash: String,
+    artifact: Vec<u8>,
+) -> Result<(), DbErr> {
+    Artifact::Entity::insert(Artifact::ActiveModel {
+        code_hash: ActiveValue::set(code_hash),
+        engine: ActiveValue::set(engine_id()),
+        artifact: ActiveValue::set(artifact),
+        ..Default::default()
+    })
+    .on_conflict(
+        OnConflict::columns([Artifact::Column::CodeHash, Artifact::Column::Engine])
+            .update_column(Artifact::Column::Artifact)
+            .to_owned(),
+    )
+    .exec(conn)
+    .await?;
+
+    Ok(())
+}
+
+/// Loads the compiled module for a call from the in-memory cache or the
+/// artifact persisted at deploy time, only compiling it as a last resort
+pub async fn load_module(
+    db: &DatabaseConnection,
+    cache: &ModuleCache,
+    store: &Store,
+    module: &entities::module::Model,
+) -> Result<Module, AwsError> {
+    let key = CacheKey {
+        module_id: module.id,
+        engine_version: METERED_ENGINE_VERSION,
+        opcodes_id: pricing::current().opcodes_id(),
+    };
+
+    if let Some(compiled) = cache.get(store, key).await {
+        return Ok(compiled);
+    }
+
+    let persisted = Artifact::Entity::find()
+        .filter(Artifact::Column::CodeHash.eq(&module.code_hash))
+        .filter(Artifact::Column::Engine.eq(engine_id()))
+        .one(db)
+        .await
+        .map_err(|_| AwsError::UnknownServerError)?;
+
+    if let Some(persisted) = persisted {
+        let artifact = Bytes::from(persisted.artifact);
+
+        // SAFETY: persisted artifacts are only written by `save_artifact`
+        // from modules compiled by an engine matching `engine_id`
+        match unsafe { Module::deserialize(store, artifact.clone()) } {
+            Ok(compiled) => {
+                cache.insert(key, artifact).await;
+                return Ok(compiled);
+            }
+            Err(e) => tracing::error!(
+                "Failed to deserialize artifact of {}: {e}",
+                module.code_hash
+            ),
+        }
+    }
+
+    // Modules deployed before artifacts were persisted, or whose artifact
+    // was compiled by another engine, are compiled once more here
+    let (compiled, artifact) = compile(store, &module.wasm_code)?;
+
+    if let Err(e) = save_artifact(db, module.code_hash.clone(), artifact.to_vec()).await {
+        tracing::error!("Failed to persist artifact of {}: {e}", module.code_hash);
+    }
+
+    cache.insert(key, artifact).await;
+
+    Ok(compiled)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{testing, utils::metered_store};
+
+    const MODULE: &str = r#"(module (func (export "one") (result i32) (i32.const 1)))"#;
+
+    #[tokio::test]
+    async fn test_module_is_compiled_once_and_persisted() {
+        let db = testing::database().await;
+        let wallet = testing::wallet(&db, "user", 0).await;
+        let module = testing::module(&db, wallet.user_id, MODULE).await;
+
+        load_module(&db, &ModuleCache::default(), &metered_store(), &module)
+            .await
+            .unwrap();
+
+        let persisted = Artifact::Entity::find().all(&db).await.unwrap();
+
+        assert_eq!(persisted.len(), 1);
+        assert_eq!(persisted[0].engine, engine_id());
+
+        // A fresh cache has to go through the persisted artifact
+        load_module(&db, &ModuleCache::default(), &metered_store(), &module)
+            .await
+            .unwrap();
+
+        assert_eq!(Artifact::Entity::find().all(&db).await.unwrap(), persisted);
+    }
+}
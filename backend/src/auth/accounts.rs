@@ -0,0 +1,265 @@
+//! Accounts of the backend are those of the auth service's users, under
+//! the same ids. The auth service owns credentials, accounts are created
+//! the first time a user registers or logs in.
+
+use aws_common::api::{auth::ImportedCredentials, errors::AwsError};
+use axum::http::{Method, StatusCode};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+use sea_orm::{
+    ActiveModelTrait, ActiveValue, ColumnTrait, DatabaseConnection, DbErr, EntityTrait,
+    QueryFilter, TransactionError, TransactionTrait,
+};
+
+use super::client;
+use crate::{
+    constants::{CREDENTIAL_EXPORT_RETRY_INTERVAL, INITIAL_WALLET_CREDITS},
+    entities,
+    ledger::{record, EntryKind},
+    metrics::ACTIVE_USERS,
+    utils::is_unique_violation,
+};
+
+/// Whether the account `uid` exists, which has to be the account of
+/// `username`
+async fn exists(db: &DatabaseConnection, uid: i32, username: &str) -> Result<bool, AwsError> {
+    let user = entities::user::Entity::find_by_id(uid)
+        .one(db)
+        .await
+        .map_err(|_| AwsError::UnknownServerError)?;
+
+    match user {
+        Some(user) if user.username != username => {
+            tracing::error!(
+                "Auth service user {uid} is {username}, the account is {}",
+                user.username
+            );
+
+            Err(AwsError::UnknownServerError)
+        }
+        user => Ok(user.is_some()),
+    }
+}
+
+/// Creates the account of the user along with their wallet, unless it
+/// exists
+pub async fn provision(db: &DatabaseConnection, uid: i32, username: &str) -> Result<(), AwsError> {
+    if exists(db, uid, username).await? {
+        return Ok(());
+    }
+
+    let name = username.to_string();
+
+    let res = db
+        .transaction::<_, _, DbErr>(|txn| {
+            Box::pin(async move {
+                entities::user::ActiveModel {
+                    id: ActiveValue::set(uid),
+                    username: ActiveValue::set(name),
+                    password: ActiveValue::set(String::new()),
+                    ..Default::default()
+                }
+                .insert(txn)
+                .await?;
+
+                entities::wallet::ActiveModel {
+                    user_id: ActiveValue::set(uid),
+                    credits: ActiveValue::set(INITIAL_WALLET_CREDITS),
+                    ..Default::default()
+                }
+                .insert(txn)
+                .await?;
+
+                record(txn, uid, EntryKind::Grant, INITIAL_WALLET_CREDITS, None).await?;
+
+                Ok(())
+            })
+        })
+        .await;
+
+    match res {
+        Ok(()) => {}
+        Err(TransactionError::Transaction(e)) if is_unique_violation(&e) => {
+            // A concurrent request of the same user created it first
+            return match exists(db, uid, username).await? {
+                true => Ok(()),
+                false => Err(AwsError::DuplicateUsername),
+            };
+        }
+        Err(e) => {
+            tracing::error!("Provisioning user {uid} {e:#?}");
+
+            return Err(AwsError::UnknownServerError);
+        }
+    }
+
+    ACTIVE_USERS.inc();
+
+    Ok(())
+}
+
+/// Hands the passwords the backend still stores over to the auth service,
+/// clearing each once taken. Returns how many were handed over
+pub async fn export_credentials(db: &DatabaseConnection) -> Result<usize, AwsError> {
+    let users = entities::user::Entity::find()
+        .filter(entities::user::Column::Password.ne(""))
+        .all(db)
+        .await
+        .map_err(|_| AwsError::UnknownServerError)?;
+
+    for user in &users {
+        client::send(
+            Method::POST,
+            "/api/v1/users/import",
+            &ImportedCredentials {
+                uid: user.id,
+                username: user.username.clone(),
+                password_hash: user.password.clone(),
+            },
+        )
+        .await?;
+
+        entities::user::Entity::update_many()
+            .col_expr(entities::user::Column::Password, "".into())
+            .filter(entities::user::Column::Id.eq(user.id))
+            .exec(db)
+            .await
+            .map_err(|_| AwsError::UnknownServerError)?;
+    }
+
+    Ok(users.len())
+}
+
+/// Whether the auth service holds every password yet. Until then it
+/// could number new users with ids of accounts it doesn't know, and
+/// doesn't know the passwords of those accounts, so registering and
+/// logging in wait for it
+#[derive(Clone, Default)]
+pub struct CredentialsExported(Arc<AtomicBool>);
+
+impl CredentialsExported {
+    pub fn check(&self) -> Result<(), AwsError> {
+        match self.0.load(Ordering::Acquire) {
+            true => Ok(()),
+            false => Err(AwsError::AuthServiceError {
+                status: StatusCode::SERVICE_UNAVAILABLE,
+                message: "accounts are being moved to the auth service, try again shortly"
+                    .to_string(),
+            }),
+        }
+    }
+
+    fn done(&self) {
+        self.0.store(true, Ordering::Release);
+    }
+}
+
+/// Hands the backend's passwords over to the auth service in the
+/// background, trying again until it succeeded
+pub struct CredentialExporter {
+    pub db: Arc<DatabaseConnection>,
+    pub exported: CredentialsExported,
+}
+
+impl CredentialExporter {
+    pub fn start(self) {
+        tokio::spawn(async move {
+            loop {
+                match export_credentials(&self.db).await {
+                    Ok(exported) => {
+                        if exported > 0 {
+                            tracing::info!("Handed {exported} credentials to the auth service");
+                        }
+
+                        self.exported.done();
+                        break;
+                    }
+                    Err(e) => tracing::error!("Handing credentials to the auth service {e:#?}"),
+                }
+
+                tokio::time::sleep(CREDENTIAL_EXPORT_RETRY_INTERVAL).await;
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ledger, testing};
+    use sea_orm::ConnectionTrait;
+
+    #[tokio::test]
+    async fn test_accounts_are_provisioned_once() {
+        let db = testing::database().await;
+
+        provision(&db, 42, "user").await.unwrap();
+        provision(&db, 42, "user").await.unwrap();
+
+        let user = entities::user::Entity::find_by_id(42)
+            .one(&db)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(user.username, "user");
+        assert!(user.password.is_empty());
+
+        let wallets = entities::wallet::Entity::find()
+            .filter(entities::wallet::Column::UserId.eq(42))
+            .all(&db)
+            .await
+            .unwrap();
+        assert_eq!(wallets.len(), 1);
+        assert_eq!(wallets[0].credits, INITIAL_WALLET_CREDITS);
+        assert_eq!(
+            ledger::balance(&db, 42).await.unwrap(),
+            INITIAL_WALLET_CREDITS
+        );
+
+        // Usernames stay unique across ids
+        assert!(matches!(
+            provision(&db, 43, "user").await,
+            Err(AwsError::DuplicateUsername)
+        ));
+
+        // Ids of the auth service reused for another account aren't taken
+        // for that account
+        assert!(matches!(
+            provision(&db, 42, "other").await,
+            Err(AwsError::UnknownServerError)
+        ));
+
+        // Other failures aren't taken for duplicates
+        db.execute_unprepared("DROP TABLE wallet").await.unwrap();
+        assert!(matches!(
+            provision(&db, 44, "other").await,
+            Err(AwsError::UnknownServerError)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_export_finishes_once_no_password_is_left() {
+        let db = testing::database().await;
+        provision(&db, 42, "user").await.unwrap();
+
+        let exported = CredentialsExported::default();
+        assert!(exported.check().is_err());
+
+        CredentialExporter {
+            db: Arc::new(db),
+            exported: exported.clone(),
+        }
+        .start();
+
+        tokio::time::timeout(std::time::Duration::from_secs(5), async {
+            while exported.check().is_err() {
+                tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .unwrap();
+    }
+}
@@ -0,0 +1,221 @@
+//! API keys let services call functions without a user's interactive
+//! session. They don't expire and only allow what their scopes name, the
+//! account itself is never managed through them.
+
+use std::fmt;
+
+use aws_common::api::{errors::AwsError, responses::ApiKeyResponse};
+use sea_orm::{
+    sea_query::Condition, ActiveModelTrait, ActiveValue, ColumnTrait, DatabaseConnection, DbErr,
+    EntityTrait, QueryFilter,
+};
+
+use super::{hash, random_token};
+use crate::{
+    constants::{API_KEY_LAST_USED_INTERVAL, API_KEY_NAME_MAX, API_KEY_PREFIX},
+    entities::{api_key, module},
+    utils::now_ms,
+};
+
+/// Characters of a key kept to tell it apart, past its prefix
+const KEY_PREFIX_SHOWN: usize = 6;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Scope {
+    /// Calling the functions of a module, and polling the jobs of its
+    /// functions
+    Invoke(i32),
+    /// Deploying new modules
+    Deploy,
+    /// Reading modules, jobs, credits and the ledger
+    Read,
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Scope::Invoke(module_id) => write!(f, "invoke:{module_id}"),
+            Scope::Deploy => f.write_str("deploy"),
+            Scope::Read => f.write_str("read"),
+        }
+    }
+}
+
+impl std::str::FromStr for Scope {
+    type Err = AwsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "deploy" => Ok(Scope::Deploy),
+            "read" => Ok(Scope::Read),
+            _ => s
+                .strip_prefix("invoke:")
+                .and_then(|module_id| module_id.parse().ok())
+                .map(Scope::Invoke)
+                .ok_or_else(|| AwsError::InvalidApiKey(format!("unknown scope {s}"))),
+        }
+    }
+}
+
+/// Scopes of the key, as stored
+pub fn scopes(key: &api_key::Model) -> Vec<Scope> {
+    key.scopes
+        .split_whitespace()
+        .filter_map(|scope| scope.parse().ok())
+        .collect()
+}
+
+impl From<api_key::Model> for ApiKeyResponse {
+    fn from(key: api_key::Model) -> Self {
+        Self {
+            id: key.id,
+            scopes: scopes(&key).iter().map(ToString::to_string).collect(),
+            name: key.name,
+            prefix: key.prefix,
+            created_at: key.created_at,
+            last_used_at: key.last_used_at,
+        }
+    }
+}
+
+/// Creates a key of the user, returning it along with what is stored
+/// of it. Modules the key invokes have to be the user's
+pub async fn create(
+    db: &DatabaseConnection,
+    user_id: i32,
+    name: &str,
+    scopes: &[String],
+) -> Result<(String, api_key::Model), AwsError> {
+    let name = name.trim();
+
+    if name.is_empty() || name.len() > API_KEY_NAME_MAX {
+        return Err(AwsError::InvalidApiKey(format!(
+            "name must be 1 to {API_KEY_NAME_MAX} bytes"
+        )));
+    }
+
+    if scopes.is_empty() {
+        return Err(AwsError::InvalidApiKey("no scopes".to_string()));
+    }
+
+    let mut parsed = Vec::with_capacity(scopes.len());
+
+    for scope in scopes {
+        let scope = scope.parse::<Scope>()?;
+
+        if let Scope::Invoke(module_id) = scope {
+            module::Entity::find_by_id(module_id)
+                .filter(module::Column::OwnerId.eq(user_id))
+                .one(db)
+                .await
+                .map_err(|_| AwsError::UnknownServerError)?
+                .ok_or(AwsError::EndpointNotFound(module_id))?;
+        }
+
+        if !parsed.contains(&scope) {
+            parsed.push(scope);
+        }
+    }
+
+    let key = format!("{API_KEY_PREFIX}{}", random_token());
+
+    let model = api_key::ActiveModel {
+        user_id: ActiveValue::set(user_id),
+        name: ActiveValue::set(name.to_string()),
+        prefix: ActiveValue::set(key[..API_KEY_PREFIX.len() + KEY_PREFIX_SHOWN].to_string()),
+        key_hash: ActiveValue::set(hash(&key)),
+        scopes: ActiveValue::set(
+            parsed
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(" "),
+        ),
+        created_at: ActiveValue::set(now_ms()),
+        ..Default::default()
+    }
+    .insert(db)
+    .await
+    .map_err(|_| AwsError::UnknownServerError)?;
+
+    Ok((key, model))
+}
+
+/// The stored key matching `key`, noting that it was used
+pub async fn authenticate(
+    db: &DatabaseConnection,
+    key: &str,
+) -> Result<Option<api_key::Model>, DbErr> {
+    let Some(model) = api_key::Entity::find()
+        .filter(api_key::Column::KeyHash.eq(hash(key)))
+        .one(db)
+        .await?
+    else {
+        return Ok(None);
+    };
+
+    let now = now_ms();
+    let stale = now - API_KEY_LAST_USED_INTERVAL.as_millis() as i64;
+
+    api_key::Entity::update_many()
+        .col_expr(api_key::Column::LastUsedAt, now.into())
+        .filter(api_key::Column::Id.eq(model.id))
+        .filter(
+            Condition::any()
+                .add(api_key::Column::LastUsedAt.is_null())
+                .add(api_key::Column::LastUsedAt.lt(stale)),
+        )
+        .exec(db)
+        .await?;
+
+    Ok(Some(model))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+
+    #[test]
+    fn test_scopes_parse() {
+        assert_eq!("invoke:12".parse::<Scope>().unwrap(), Scope::Invoke(12));
+        assert_eq!("read".parse::<Scope>().unwrap(), Scope::Read);
+        assert_eq!(Scope::Invoke(12).to_string(), "invoke:12");
+        assert!("invoke:".parse::<Scope>().is_err());
+        assert!("admin".parse::<Scope>().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_api_keys_are_stored_hashed() {
+        let db = testing::database().await;
+        let user_id = testing::wallet(&db, "user", 0).await.user_id;
+        let module = testing::module(&db, user_id, "(module)").await;
+
+        let invoke = format!("invoke:{}", module.id);
+        let (key, model) = create(&db, user_id, "ci", &[invoke.clone(), "read".to_string()])
+            .await
+            .unwrap();
+
+        assert!(key.starts_with(&model.prefix));
+        assert_ne!(model.key_hash, key);
+        assert_eq!(scopes(&model), vec![Scope::Invoke(module.id), Scope::Read]);
+
+        let used = authenticate(&db, &key).await.unwrap().unwrap();
+        assert_eq!(used.id, model.id);
+        assert!(authenticate(&db, "awsk_unknown").await.unwrap().is_none());
+
+        let used = api_key::Entity::find_by_id(model.id)
+            .one(&db)
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(used.last_used_at.is_some());
+
+        assert!(matches!(
+            create(&db, user_id, "ci", &["invoke:999".to_string()]).await,
+            Err(AwsError::EndpointNotFound(999))
+        ));
+        assert!(create(&db, user_id, " ", &[invoke]).await.is_err());
+        assert!(create(&db, user_id, "ci", &[]).await.is_err());
+    }
+}
@@ -0,0 +1,99 @@
+//! Requests to the auth service, which owns credentials and sessions.
+//! They are signed with the shared secret, along with their method,
+//! path and a random nonce the service accepts once.
+
+use aws_common::api::{
+    auth::{
+        request_nonce, request_signature, SignedRequest, NONCE_HEADER, SIGNATURE_HEADER,
+        TIMESTAMP_HEADER,
+    },
+    errors::AwsError,
+};
+use axum::http::{header::CONTENT_TYPE, Method, StatusCode};
+use lazy_static::lazy_static;
+use serde::{de::DeserializeOwned, Serialize};
+
+use super::keys::{AUTH_SHARED_SECRET, AUTH_URL};
+use crate::{constants::AUTH_REQUEST_TIMEOUT, utils::now_ms};
+
+lazy_static! {
+    static ref CLIENT: reqwest::Client = reqwest::Client::new();
+}
+
+async fn request(
+    method: Method,
+    path: &str,
+    body: &impl Serialize,
+) -> Result<reqwest::Response, AwsError> {
+    let body = serde_json::to_vec(body).map_err(|_| AwsError::UnknownServerError)?;
+    let timestamp = now_ms();
+    let nonce = request_nonce();
+    let signature = request_signature(
+        &AUTH_SHARED_SECRET,
+        &SignedRequest {
+            timestamp,
+            nonce: &nonce,
+            method: method.as_str(),
+            path,
+            body: &body,
+        },
+    );
+
+    let response = CLIENT
+        .request(method, format!("{}{path}", *AUTH_URL))
+        .timeout(AUTH_REQUEST_TIMEOUT)
+        .header(CONTENT_TYPE, "application/json")
+        .header(TIMESTAMP_HEADER, timestamp)
+        .header(NONCE_HEADER, nonce)
+        .header(SIGNATURE_HEADER, signature)
+        .body(body)
+        .send()
+        .await
+        .map_err(|e| {
+            tracing::error!("Auth service request {e:#?}");
+
+            AwsError::AuthServiceError {
+                status: StatusCode::BAD_GATEWAY,
+                message: "auth service unavailable".to_string(),
+            }
+        })?;
+
+    let status = response.status();
+
+    if status.is_success() {
+        return Ok(response);
+    }
+
+    // Errors of the service are the same errors the backend responds with
+    let message = response
+        .json::<serde_json::Value>()
+        .await
+        .ok()
+        .and_then(|body| Some(body.get("error")?.as_str()?.to_string()))
+        .unwrap_or_else(|| status.to_string());
+
+    Err(AwsError::AuthServiceError { status, message })
+}
+
+/// Sends the request, reading the JSON response
+pub async fn call<R: DeserializeOwned>(
+    method: Method,
+    path: &str,
+    body: &impl Serialize,
+) -> Result<R, AwsError> {
+    request(method, path, body)
+        .await?
+        .json()
+        .await
+        .map_err(|e| {
+            tracing::error!("Auth service response {e:#?}");
+            AwsError::UnknownServerError
+        })
+}
+
+/// Sends the request, ignoring what the response holds
+pub async fn send(method: Method, path: &str, body: &impl Serialize) -> Result<(), AwsError> {
+    request(method, path, body).await?;
+
+    Ok(())
+}
@@ -1,13 +1,124 @@
-use jsonwebtoken::DecodingKey;
+use std::{collections::HashMap, sync::Arc, time::Instant};
+
+use aws_common::api::errors::AwsError;
+use jsonwebtoken::{jwk::JwkSet, DecodingKey};
 use lazy_static::lazy_static;
+use tokio::sync::RwLock;
+
+use crate::constants::{JWKS_CACHE_TTL, JWKS_FETCH_TIMEOUT, JWKS_REFRESH_MIN_INTERVAL};
 
 lazy_static! {
-    pub static ref JWT_DECODING_KEY: DecodingKey = DecodingKey::from_ec_pem(
-        std::fs::read_to_string(
-            std::env::var("JWT_PUBLIC_KEY_PATH").expect("env var to be present")
-        )
-        .expect("to be able to read public key file")
-        .as_bytes()
+    /// Base URL of the auth service
+    pub static ref AUTH_URL: String = std::env::var("AUTH_URL")
+        .unwrap_or_else(|_| "http://auth:3000".to_string())
+        .trim_end_matches('/')
+        .to_string();
+
+    pub static ref JWKS: Jwks = Jwks::new(&format!("{}/.well-known/jwks.json", *AUTH_URL));
+
+    /// Authenticates the backend to the auth service
+    pub static ref AUTH_SHARED_SECRET: Vec<u8> = std::fs::read_to_string(
+        std::env::var("AUTH_SHARED_SECRET_PATH").expect("env var to be present")
     )
-    .expect("Public key to be valid");
+    .expect("to be able to read shared secret file")
+    .trim()
+    .as_bytes()
+    .to_vec();
+}
+
+#[derive(Default)]
+struct JwksState {
+    keys: HashMap<String, Arc<DecodingKey>>,
+    fetched_at: Option<Instant>,
+    attempted_at: Option<Instant>,
+}
+
+/// Keys published by the auth service, by `kid`. They are fetched again
+/// once stale, or when a token names a key they don't have, which is how
+/// rotated keys are picked up
+pub struct Jwks {
+    url: String,
+    state: RwLock<JwksState>,
+}
+
+impl Jwks {
+    pub fn new(url: &str) -> Self {
+        Self {
+            url: url.to_string(),
+            state: Default::default(),
+        }
+    }
+
+    /// Cache already holding `jwks`
+    pub fn with_keys(url: &str, jwks: &JwkSet) -> Self {
+        let now = Instant::now();
+
+        Self {
+            url: url.to_string(),
+            state: RwLock::new(JwksState {
+                keys: decoding_keys(jwks),
+                fetched_at: Some(now),
+                attempted_at: Some(now),
+            }),
+        }
+    }
+
+    pub async fn key(&self, kid: &str) -> Result<Arc<DecodingKey>, AwsError> {
+        {
+            let state = self.state.read().await;
+            let fresh = state
+                .fetched_at
+                .map_or(false, |at| at.elapsed() < JWKS_CACHE_TTL);
+
+            if let Some(key) = state.keys.get(kid).filter(|_| fresh) {
+                return Ok(key.clone());
+            }
+        }
+
+        let mut state = self.state.write().await;
+
+        // Requests naming unknown keys only fetch them so often, and
+        // requests waiting on the lock use what the first one fetched
+        let recent = state
+            .attempted_at
+            .map_or(false, |at| at.elapsed() < JWKS_REFRESH_MIN_INTERVAL);
+
+        if !recent {
+            state.attempted_at = Some(Instant::now());
+
+            match self.fetch().await {
+                Ok(jwks) => {
+                    state.keys = decoding_keys(&jwks);
+                    state.fetched_at = Some(Instant::now());
+                }
+                // Keeps verifying with the stale keys meanwhile
+                Err(e) => tracing::error!("Fetching JWKS {e:#?}"),
+            }
+        }
+
+        state.keys.get(kid).cloned().ok_or(AwsError::Unauthorized)
+    }
+
+    async fn fetch(&self) -> reqwest::Result<JwkSet> {
+        reqwest::Client::new()
+            .get(&self.url)
+            .timeout(JWKS_FETCH_TIMEOUT)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await
+    }
+}
+
+fn decoding_keys(jwks: &JwkSet) -> HashMap<String, Arc<DecodingKey>> {
+    jwks.keys
+        .iter()
+        .filter_map(|jwk| {
+            let kid = jwk.common.key_id.clone()?;
+            let key = DecodingKey::from_jwk(jwk).ok()?;
+
+            Some((kid, Arc::new(key)))
+        })
+        .collect()
 }
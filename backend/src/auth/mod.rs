@@ -1,3 +1,26 @@
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use sha2::{Digest, Sha256};
+
+pub mod accounts;
+pub mod api_keys;
+pub mod client;
 pub mod jwt;
 pub mod keys;
-pub mod requests;
+
+fn random_hex() -> String {
+    let mut bytes = [0u8; 16];
+    OsRng.fill_bytes(&mut bytes);
+
+    format!("{:032x}", u128::from_le_bytes(bytes))
+}
+
+/// 256 random bits, in hex
+fn random_token() -> String {
+    format!("{}{}", random_hex(), random_hex())
+}
+
+/// SHA-256 of a token, in hex. Tokens are random enough to not need a
+/// slow hash
+fn hash(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
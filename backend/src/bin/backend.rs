@@ -1,26 +1,52 @@
-use std::{net::SocketAddr, str::FromStr, sync::Arc};
+use std::{net::SocketAddr, str::FromStr, sync::Arc, time::Duration};
 
 use anyhow::anyhow;
 
 use aws_common::api::errors::AwsError;
 use axum::{
-    routing::{delete, get, post},
+    routing::{any, delete, get, post, put},
     Extension, Router,
 };
 
 use sea_orm::{ConnectOptions, Database};
 
 use aws_backend::{
+    auth::accounts,
     cache::ModuleCache,
+    constants::{JOB_WORKERS_DEFAULT, RESERVATION_GRACE, SESSION_MEMORY_PER_USER, SESSION_TTL},
+    credits::ReservationSweeper,
+    execution::{ExecutionLimits, Executor},
+    jobs::JobWorker,
+    ledger,
+    limits::ResourceLimits,
+    payments::{FakePaymentProvider, Payments},
+    pricing::PricingWatcher,
     routes::{modules::delete_module, user::delete_account},
+    scheduler::Scheduler,
+    sessions::SessionStore,
 };
 use aws_backend::{routes::metrics::get_metrics, utils::DbConn};
 use tower_http::cors;
 
 use aws_backend::routes::{
-    functions::call_function,
-    modules::{deploy_module, get_deployed_modules},
-    user::{get_remaining_credits, login_user, register_user},
+    api_keys::{create_api_key, delete_api_key, get_api_keys},
+    budgets::{delete_spending_limit, get_spending_limits, set_budget_webhook, set_spending_limit},
+    credits::{get_top_up, grant_credits, revoke_credits, top_up_credits, transfer_credits},
+    functions::{call_function, call_function_async, call_function_batch},
+    jobs::get_job,
+    modules::{deploy_module, get_deployed_modules, set_module_public},
+    pipelines::{create_pipeline, delete_pipeline, get_pipelines, run_pipeline},
+    run::run_handler,
+    schedules::{
+        create_schedule, delete_schedule, get_schedule_runs, get_schedules, resume_schedule,
+    },
+    sessions::{
+        call_session, create_session, delete_session, delete_snapshot, restore_session,
+        snapshot_session,
+    },
+    user::{
+        get_ledger, get_remaining_credits, login_user, logout_user, refresh_tokens, register_user,
+    },
 };
 
 #[tokio::main]
@@ -32,9 +58,108 @@ async fn main() -> anyhow::Result<()> {
     db_opts.sqlx_logging(false);
 
     let db = Database::connect(db_opts).await?;
-    let cache = ModuleCache::default();
+    let cache = match std::env::var("MODULE_CACHE_SIZE") {
+        Ok(size) => ModuleCache::new(size.parse().map_err(|_| anyhow!("Invalid cache size"))?),
+        Err(_) => ModuleCache::default(),
+    };
 
-    let db_conn = DbConn(Arc::new(db));
+    if let Ok(path) = std::env::var("PRICING_CONFIG") {
+        PricingWatcher { path: path.into() }.start()?;
+    }
+
+    let defaults = ExecutionLimits::default();
+    let limits = ExecutionLimits {
+        default_timeout: from_env("CALL_TIMEOUT_MS")?
+            .map(Duration::from_millis)
+            .unwrap_or(defaults.default_timeout),
+        max_timeout: from_env("CALL_TIMEOUT_MAX_MS")?
+            .map(Duration::from_millis)
+            .unwrap_or(defaults.max_timeout),
+        default_reservation: from_env("CALL_DEFAULT_RESERVATION")?
+            .unwrap_or(defaults.default_reservation),
+        resources: ResourceLimits {
+            memory_pages: from_env("MEMORY_LIMIT_PAGES")?
+                .unwrap_or(defaults.resources.memory_pages),
+            table_elements: from_env("TABLE_LIMIT_ELEMENTS")?
+                .unwrap_or(defaults.resources.table_elements),
+            instances: from_env("INSTANCE_LIMIT")?.unwrap_or(defaults.resources.instances),
+        },
+    };
+
+    let db = Arc::new(db);
+
+    ledger::reconcile(&db).await?;
+
+    // Until the auth service holds every password, it can't number new
+    // users without reusing ids of accounts it doesn't know. The backend
+    // serves everything but registering and logging in meanwhile, rather
+    // than waiting for the auth service to be up
+    let exported = accounts::CredentialsExported::default();
+
+    accounts::CredentialExporter {
+        db: db.clone(),
+        exported: exported.clone(),
+    }
+    .start();
+
+    ReservationSweeper {
+        db: db.clone(),
+        max_age: limits.max_timeout + RESERVATION_GRACE,
+    }
+    .start();
+
+    let executor = Executor {
+        db: db.clone(),
+        cache: cache.clone(),
+        limits,
+    };
+
+    JobWorker {
+        executor: executor.clone(),
+        workers: from_env("JOB_WORKERS")?.unwrap_or(JOB_WORKERS_DEFAULT),
+    }
+    .start()
+    .await?;
+
+    Scheduler {
+        executor: executor.clone(),
+    }
+    .start();
+
+    let sessions = SessionStore::new(
+        from_env("SESSION_TTL_MS")?
+            .map(Duration::from_millis)
+            .unwrap_or(SESSION_TTL),
+        from_env("SESSION_MEMORY_PER_USER")?.unwrap_or(SESSION_MEMORY_PER_USER),
+    );
+
+    sessions.clone().start();
+
+    // Only the fake provider exists so far. It charges nothing, so it
+    // has to be allowed explicitly and is meant for dev setups only
+    let payments = match std::env::var("PAYMENT_PROVIDER").as_deref() {
+        Ok("fake") => {
+            if !from_env("ALLOW_FAKE_PAYMENTS")?.unwrap_or(false) {
+                return Err(anyhow!(
+                    "The fake payment provider needs ALLOW_FAKE_PAYMENTS=true"
+                ));
+            }
+
+            let auto_settle = from_env("FAKE_PAYMENTS_AUTO_SETTLE")?.unwrap_or(false);
+
+            tracing::warn!("Using the fake payment provider, auto settling: {auto_settle}");
+
+            Payments(Some(Arc::new(FakePaymentProvider::new(auto_settle))))
+        }
+        Ok(provider) => return Err(anyhow!("Unknown payment provider {provider}")),
+        Err(_) => {
+            tracing::warn!("No PAYMENT_PROVIDER, top-ups are disabled");
+
+            Payments(None)
+        }
+    };
+
+    let db_conn = DbConn(db);
 
     let app = Router::new()
         .fallback(fallback)
@@ -46,30 +171,91 @@ async fn main() -> anyhow::Result<()> {
                     Router::new()
                         .route("/register", post(register_user))
                         .route("/login", post(login_user))
-                        .route("/delete", delete(delete_account)),
+                        .route("/refresh", post(refresh_tokens))
+                        .route("/logout", post(logout_user))
+                        .route("/delete", delete(delete_account))
+                        .layer(Extension(exported)),
                 )
                 .nest(
                     "/user",
                     Router::new()
                         .route("/currency", get(get_remaining_credits))
-                        .route("/modules", get(get_deployed_modules)),
+                        .route("/ledger", get(get_ledger))
+                        .route("/modules", get(get_deployed_modules))
+                        .route("/transfer", post(transfer_credits))
+                        .route("/top-up", post(top_up_credits))
+                        .route("/top-up/:id", get(get_top_up))
+                        .route("/limits", get(get_spending_limits).put(set_spending_limit))
+                        .route("/limits/:id", delete(delete_spending_limit))
+                        .route("/budget-webhook", put(set_budget_webhook))
+                        .route("/api-keys", get(get_api_keys).post(create_api_key))
+                        .route("/api-keys/:id", delete(delete_api_key))
+                        .layer(Extension(payments)),
+                )
+                .nest(
+                    "/admin",
+                    Router::new()
+                        .route("/users/:id/grant", post(grant_credits))
+                        .route("/users/:id/revoke", post(revoke_credits)),
                 )
                 .nest(
                     "/module",
-                    Router::new().route("/deploy", post(deploy_module)).nest(
-                        "/delete",
-                        Router::new()
-                            .route("/:id", delete(delete_module))
-                            .layer(Extension(cache.clone())),
-                    ),
+                    Router::new()
+                        .route("/deploy", post(deploy_module))
+                        .route("/:id/public", put(set_module_public))
+                        .nest(
+                            "/delete",
+                            Router::new()
+                                .route("/:id", delete(delete_module))
+                                .layer(Extension(cache.clone())),
+                        ),
                 )
                 .nest(
                     "/function",
                     Router::new()
                         .route("/call/:id/:func_name", post(call_function))
-                        .layer(Extension(cache)),
+                        .route("/call_async/:id/:func_name", post(call_function_async))
+                        .route("/call_batch/:id/:func_name", post(call_function_batch))
+                        .route("/schedule/:id/:func_name", post(create_schedule))
+                        .layer(Extension(executor.clone())),
+                )
+                .nest("/jobs", Router::new().route("/:id", get(get_job)))
+                .nest(
+                    "/schedules",
+                    Router::new()
+                        .route("/", get(get_schedules))
+                        .route("/:id", delete(delete_schedule))
+                        .route("/:id/runs", get(get_schedule_runs))
+                        .route("/:id/resume", post(resume_schedule)),
+                )
+                .nest(
+                    "/session",
+                    Router::new()
+                        .route("/create/:id", post(create_session))
+                        .route("/:id", delete(delete_session))
+                        .route("/:id/call/:func_name", post(call_session))
+                        .route("/:id/snapshot", post(snapshot_session))
+                        .route("/:id/snapshot/:snapshot_id", delete(delete_snapshot))
+                        .route("/:id/restore/:snapshot_id", post(restore_session))
+                        .layer(Extension(sessions))
+                        .layer(Extension(executor.clone())),
+                )
+                .nest(
+                    "/pipeline",
+                    Router::new()
+                        .route("/", get(get_pipelines).post(create_pipeline))
+                        .route("/:id", delete(delete_pipeline))
+                        .route("/:id/run", post(run_pipeline))
+                        .layer(Extension(executor.clone())),
                 ),
         )
+        .nest(
+            "/run",
+            Router::new()
+                .route("/:id", any(run_handler))
+                .route("/:id/*path", any(run_handler))
+                .layer(Extension(executor)),
+        )
         .layer(Extension(db_conn))
         .layer(cors::CorsLayer::very_permissive())
         .nest("/metrics", Router::new().route("/", get(get_metrics)));
@@ -87,6 +273,13 @@ async fn main() -> anyhow::Result<()> {
     Ok(())
 }
 
+fn from_env<T: FromStr>(var: &str) -> anyhow::Result<Option<T>> {
+    std::env::var(var)
+        .ok()
+        .map(|value| value.parse().map_err(|_| anyhow!("Invalid {var}")))
+        .transpose()
+}
+
 async fn fallback(uri: axum::http::Uri) -> AwsError {
     AwsError::NotFound(Box::new(uri))
 }
@@ -0,0 +1,478 @@
+//! Spending limits cap the credits a user spends per UTC day or month,
+//! on all of their calls or on the calls of one module. Calls only
+//! reserve what every limit applying to them still allows, and the user
+//! is alerted through their budget webhook once they spent a share of a
+//! limit.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use aws_common::api::{
+    errors::AwsError,
+    responses::{BudgetAlert, SpendingLimitResponse},
+};
+use chrono::{Datelike, NaiveDate, NaiveDateTime};
+use sea_orm::{
+    sea_query::Condition, ColumnTrait, ConnectionTrait, DatabaseConnection, DbErr, EntityTrait,
+    QueryFilter,
+};
+
+use crate::{
+    constants::BUDGET_WEBHOOK_TIMEOUT,
+    credits,
+    entities::{spending_limit, user},
+    ledger,
+    utils::now_ms,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Period {
+    Daily,
+    Monthly,
+}
+
+impl Period {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Period::Daily => "daily",
+            Period::Monthly => "monthly",
+        }
+    }
+
+    /// Start of the period `at` falls in, in Unix milliseconds
+    pub fn start(&self, at: i64) -> i64 {
+        let date = NaiveDateTime::from_timestamp_millis(at)
+            .unwrap_or_default()
+            .date();
+
+        let first = match self {
+            Period::Daily => Some(date),
+            Period::Monthly => NaiveDate::from_ymd_opt(date.year(), date.month(), 1),
+        };
+
+        first
+            .and_then(|first| first.and_hms_opt(0, 0, 0))
+            .map(|start| start.timestamp_millis())
+            .unwrap_or_default()
+    }
+}
+
+impl std::str::FromStr for Period {
+    type Err = AwsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "daily" => Ok(Period::Daily),
+            "monthly" => Ok(Period::Monthly),
+            _ => Err(AwsError::InvalidSpendingLimit(format!(
+                "unknown period {s}"
+            ))),
+        }
+    }
+}
+
+pub fn limit_response(limit: &spending_limit::Model, spent: u64) -> SpendingLimitResponse {
+    SpendingLimitResponse {
+        id: limit.id,
+        module_id: limit.module_id,
+        period: limit.period.clone(),
+        max_credits: limit.max_credits as u64,
+        alert_percent: limit.alert_percent.map(|percent| percent as u8),
+        spent,
+    }
+}
+
+/// Credits the limit's calls spent in its current period
+pub async fn spent(db: &impl ConnectionTrait, limit: &spending_limit::Model) -> Result<u64, DbErr> {
+    let period = limit.period.parse::<Period>().unwrap_or(Period::Daily);
+
+    ledger::spent(db, limit.user_id, limit.module_id, period.start(now_ms())).await
+}
+
+/// Limits of the user applying to calls of `modules`
+async fn applying(
+    db: &impl ConnectionTrait,
+    user_id: i32,
+    modules: &[i32],
+) -> Result<Vec<spending_limit::Model>, DbErr> {
+    spending_limit::Entity::find()
+        .filter(spending_limit::Column::UserId.eq(user_id))
+        .filter(
+            Condition::any()
+                .add(spending_limit::Column::ModuleId.is_null())
+                .add(spending_limit::Column::ModuleId.is_in(modules.iter().copied())),
+        )
+        .all(db)
+        .await
+}
+
+/// Credits calls of `modules` may still spend, minus what running calls
+/// hold, unbounded without any limit applying to them
+pub async fn allowance(
+    db: &impl ConnectionTrait,
+    user_id: i32,
+    modules: &[i32],
+) -> Result<Option<u64>, AwsError> {
+    let limits = applying(db, user_id, modules)
+        .await
+        .map_err(|_| AwsError::UnknownServerError)?;
+
+    if limits.is_empty() {
+        return Ok(None);
+    }
+
+    // Reservations don't tell which modules they are for, so the credits
+    // held by calls still running count against every limit
+    let reserved = credits::reserved(db, user_id)
+        .await
+        .map_err(|_| AwsError::UnknownServerError)?;
+
+    let mut allowance: Option<u64> = None;
+
+    for limit in limits {
+        let spent = spent(db, &limit)
+            .await
+            .map_err(|_| AwsError::UnknownServerError)?;
+
+        let left = (limit.max_credits as u64)
+            .saturating_sub(spent)
+            .saturating_sub(reserved);
+
+        if left == 0 {
+            return Err(AwsError::BudgetExceeded {
+                period: limit.period,
+                module_id: limit.module_id,
+            });
+        }
+
+        allowance = Some(allowance.map_or(left, |allowance| allowance.min(left)));
+    }
+
+    Ok(allowance)
+}
+
+/// Limits applying to calls of `modules` whose alert threshold was
+/// crossed and not yet alerted in their current period, marking them as
+/// alerted
+pub async fn crossed(
+    db: &DatabaseConnection,
+    user_id: i32,
+    modules: &[i32],
+) -> Result<Vec<SpendingLimitResponse>, DbErr> {
+    let mut crossed = Vec::new();
+
+    for limit in applying(db, user_id, modules).await? {
+        let Some(percent) = limit.alert_percent else {
+            continue;
+        };
+
+        let spent = spent(db, &limit).await?;
+
+        if (spent as u128) * 100 < (limit.max_credits as u128) * (percent as u128) {
+            continue;
+        }
+
+        let period = limit.period.parse::<Period>().unwrap_or(Period::Daily);
+
+        // Concurrent calls only alert once
+        let res = spending_limit::Entity::update_many()
+            .col_expr(spending_limit::Column::AlertedAt, now_ms().into())
+            .filter(spending_limit::Column::Id.eq(limit.id))
+            .filter(
+                Condition::any()
+                    .add(spending_limit::Column::AlertedAt.is_null())
+                    .add(spending_limit::Column::AlertedAt.lt(period.start(now_ms()))),
+            )
+            .exec(db)
+            .await?;
+
+        if res.rows_affected == 1 {
+            crossed.push(limit_response(&limit, spent));
+        }
+    }
+
+    Ok(crossed)
+}
+
+fn is_public_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, ..] = ip.octets();
+
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_broadcast()
+        || ip.is_documentation()
+        || ip.is_multicast()
+        // "This network", shared address space and reserved ranges
+        || a == 0
+        || (a == 100 && (64..128).contains(&b))
+        || a >= 240)
+}
+
+fn is_public_v6(ip: Ipv6Addr) -> bool {
+    if let Some(ip) = ip.to_ipv4_mapped() {
+        return is_public_v4(ip);
+    }
+
+    let first = ip.segments()[0];
+
+    !(ip.is_unspecified()
+        || ip.is_loopback()
+        || ip.is_multicast()
+        // Unique local and link-local ranges
+        || (first & 0xfe00) == 0xfc00
+        || (first & 0xffc0) == 0xfe80)
+}
+
+/// Whether `ip` can be reached from anywhere, rather than only from the
+/// backend's host or network
+pub fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_public_v4(ip),
+        IpAddr::V6(ip) => is_public_v6(ip),
+    }
+}
+
+/// Resolves the host of a budget webhook, failing unless it is an HTTP
+/// URL whose every address is public so alerts can't be pointed at the
+/// backend's own network
+pub async fn resolve_webhook(url: &str) -> Result<(String, SocketAddr), AwsError> {
+    let url = reqwest::Url::parse(url).map_err(|_| AwsError::InvalidWebhookUrl)?;
+
+    if url.scheme() != "http" && url.scheme() != "https" {
+        return Err(AwsError::InvalidWebhookUrl);
+    }
+
+    let (Some(host), Some(port)) = (url.host_str(), url.port_or_known_default()) else {
+        return Err(AwsError::InvalidWebhookUrl);
+    };
+
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+
+    let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host, port))
+        .await
+        .map_err(|_| AwsError::InvalidWebhookUrl)?
+        .collect();
+
+    match addrs.first() {
+        Some(addr) if addrs.iter().all(|addr| is_public(addr.ip())) => {
+            Ok((host.to_string(), *addr))
+        }
+        _ => Err(AwsError::InvalidWebhookUrl),
+    }
+}
+
+/// Calls the user's budget webhook for every limit of `modules` whose
+/// alert threshold was crossed
+pub async fn alert(db: &DatabaseConnection, user_id: i32, modules: &[i32]) -> Result<(), DbErr> {
+    let Some(url) = user::Entity::find_by_id(user_id)
+        .one(db)
+        .await?
+        .and_then(|user| user.budget_webhook_url)
+    else {
+        return Ok(());
+    };
+
+    let crossed = crossed(db, user_id, modules).await?;
+
+    if crossed.is_empty() {
+        return Ok(());
+    }
+
+    // The host is resolved again as it may have moved since the webhook
+    // was set, and requests go to the address that was checked
+    let client = match resolve_webhook(&url).await {
+        Ok((host, addr)) => reqwest::Client::builder()
+            .resolve(&host, addr)
+            .redirect(reqwest::redirect::Policy::none())
+            .build(),
+        Err(_) => {
+            tracing::error!("Budget webhook of user {user_id} doesn't resolve to a public address");
+            return Ok(());
+        }
+    };
+
+    let client = match client {
+        Ok(client) => client,
+        Err(e) => {
+            tracing::error!("Budget webhook of user {user_id} {e:#?}");
+            return Ok(());
+        }
+    };
+
+    for limit in crossed {
+        let res = client
+            .post(&url)
+            .timeout(BUDGET_WEBHOOK_TIMEOUT)
+            .json(&BudgetAlert { user_id, limit })
+            .send()
+            .await
+            .and_then(|response| response.error_for_status());
+
+        if let Err(e) = res {
+            tracing::error!("Budget webhook of user {user_id} {e:#?}");
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        credits::{reserve, reserve_within_limits, settle},
+        entities,
+        ledger::Debit,
+        testing,
+    };
+    use sea_orm::{ActiveModelTrait, ActiveValue};
+
+    #[test]
+    fn test_periods_start_at_midnight_utc() {
+        // 2026-10-18T13:45:00Z
+        let at = 1_792_331_100_000;
+
+        assert_eq!(Period::Daily.start(at), 1_792_281_600_000);
+        assert_eq!(Period::Monthly.start(at), 1_790_812_800_000);
+    }
+
+    #[test]
+    fn test_only_public_addresses_are_public() {
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "::1",
+            "::",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+        ] {
+            assert!(!is_public(ip.parse().unwrap()), "{ip}");
+        }
+
+        for ip in ["93.184.216.34", "2606:2800:220:1::1"] {
+            assert!(is_public(ip.parse().unwrap()), "{ip}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_webhooks_must_resolve_to_public_addresses() {
+        for url in [
+            "ftp://93.184.216.34/",
+            "http://127.0.0.1:8080/",
+            "http://169.254.169.254/latest/meta-data",
+            "http://[::1]/",
+            "http://localhost/",
+        ] {
+            assert!(
+                matches!(resolve_webhook(url).await, Err(AwsError::InvalidWebhookUrl)),
+                "{url}"
+            );
+        }
+
+        let (host, addr) = resolve_webhook("https://93.184.216.34/alerts")
+            .await
+            .unwrap();
+        assert_eq!(host, "93.184.216.34");
+        assert_eq!(addr, "93.184.216.34:443".parse::<SocketAddr>().unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_limits_cap_spending_and_alert_once() {
+        let db = testing::database().await;
+        let user_id = testing::wallet(&db, "user", 1_000).await.user_id;
+
+        for (module_id, max_credits) in [(None, 500), (Some(1), 100)] {
+            entities::spending_limit::ActiveModel {
+                user_id: ActiveValue::set(user_id),
+                module_id: ActiveValue::set(module_id),
+                period: ActiveValue::set("daily".to_string()),
+                max_credits: ActiveValue::set(max_credits),
+                alert_percent: ActiveValue::set(Some(80)),
+                ..Default::default()
+            }
+            .insert(&db)
+            .await
+            .unwrap();
+        }
+
+        assert_eq!(allowance(&db, user_id, &[1]).await.unwrap(), Some(100));
+        assert_eq!(allowance(&db, user_id, &[2]).await.unwrap(), Some(500));
+
+        let reservation = reserve(&db, user_id, Some(100)).await.unwrap();
+        assert_eq!(allowance(&db, user_id, &[2]).await.unwrap(), Some(400));
+        assert!(matches!(
+            allowance(&db, user_id, &[1]).await,
+            Err(AwsError::BudgetExceeded { .. })
+        ));
+
+        let debit = Debit {
+            module_id: Some(1),
+            function: Some("f".to_string()),
+            credits: 90,
+            duration_ms: 1,
+        };
+        settle(&db, reservation, &[debit]).await.unwrap();
+
+        assert_eq!(allowance(&db, user_id, &[1]).await.unwrap(), Some(10));
+        assert_eq!(allowance(&db, user_id, &[2]).await.unwrap(), Some(410));
+
+        let alerts = crossed(&db, user_id, &[1]).await.unwrap();
+        assert_eq!(alerts.len(), 1);
+        assert_eq!((alerts[0].module_id, alerts[0].spent), (Some(1), 90));
+        assert!(crossed(&db, user_id, &[1]).await.unwrap().is_empty());
+
+        let reservation = reserve(&db, user_id, Some(10)).await.unwrap();
+        let debit = Debit {
+            module_id: Some(1),
+            function: Some("f".to_string()),
+            credits: 10,
+            duration_ms: 1,
+        };
+        settle(&db, reservation, &[debit]).await.unwrap();
+
+        assert!(matches!(
+            allowance(&db, user_id, &[1]).await,
+            Err(AwsError::BudgetExceeded {
+                module_id: Some(1),
+                ..
+            })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_reservations_stay_within_limits() {
+        let db = testing::database().await;
+        let user_id = testing::wallet(&db, "user", 1_000).await.user_id;
+
+        entities::spending_limit::ActiveModel {
+            user_id: ActiveValue::set(user_id),
+            module_id: ActiveValue::set(None),
+            period: ActiveValue::set("daily".to_string()),
+            max_credits: ActiveValue::set(100),
+            alert_percent: ActiveValue::set(None),
+            ..Default::default()
+        }
+        .insert(&db)
+        .await
+        .unwrap();
+
+        let (first, second) = tokio::join!(
+            reserve_within_limits(&db, user_id, &[1], 80),
+            reserve_within_limits(&db, user_id, &[1], 80),
+        );
+
+        let reserved = [first, second]
+            .into_iter()
+            .filter_map(Result::ok)
+            .map(|reservation| reservation.amount)
+            .sum::<u64>();
+        assert_eq!(reserved, 100);
+    }
+}
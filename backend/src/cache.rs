@@ -1,45 +1,240 @@
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 
-use aws_common::api::errors::AwsError;
+use bytes::Bytes;
 use tokio::sync::RwLock;
 
 use wasmer::{Module, Store};
 
-#[derive(Clone, Default)]
-pub struct ModuleCache(Arc<RwLock<HashMap<i32, Vec<u8>>>>);
+use crate::{
+    constants::MODULE_CACHE_DEFAULT_SIZE,
+    metrics::{MODULE_CACHE_EVICTIONS, MODULE_CACHE_HITS, MODULE_CACHE_MISSES, MODULE_CACHE_SIZE},
+};
+
+/// Compiled artifacts depend on both the module and the engine
+/// configuration it was compiled with
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct CacheKey {
+    pub module_id: i32,
+    pub engine_version: u32,
+    pub opcodes_id: u64,
+}
+
+struct CacheEntry {
+    artifact: Bytes,
+    last_used: AtomicU64,
+}
+
+#[derive(Default)]
+struct CacheState {
+    entries: HashMap<CacheKey, CacheEntry>,
+    size: usize,
+}
+
+/// LRU cache of serialized compiled modules, bounded by the total
+/// size of the artifacts it holds
+#[derive(Clone)]
+pub struct ModuleCache {
+    state: Arc<RwLock<CacheState>>,
+    clock: Arc<AtomicU64>,
+    max_size: usize,
+}
+
+impl Default for ModuleCache {
+    fn default() -> Self {
+        Self::new(MODULE_CACHE_DEFAULT_SIZE)
+    }
+}
 
 impl ModuleCache {
-    pub async fn get_or_insert(
-        &self,
-        store: &Store,
-        id: i32,
-        wasm_code: &Vec<u8>,
-    ) -> Result<Vec<u8>, AwsError> {
-        {
-            let mod_cache = self.0.read().await;
-
-            if let Some(code) = mod_cache.get(&id) {
-                return Ok(code.clone());
+    pub fn new(max_size: usize) -> Self {
+        Self {
+            state: Default::default(),
+            clock: Default::default(),
+            max_size,
+        }
+    }
+
+    fn tick(&self) -> u64 {
+        self.clock.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Loads the module from its cached artifact, if there is one
+    pub async fn get(&self, store: &Store, key: CacheKey) -> Option<Module> {
+        let cached = {
+            let state = self.state.read().await;
+
+            state.entries.get(&key).map(|entry| {
+                entry.last_used.store(self.tick(), Ordering::Relaxed);
+                entry.artifact.clone()
+            })
+        };
+
+        if let Some(artifact) = cached {
+            // SAFETY: artifacts are produced by `Module::serialize` with the
+            // engine configuration identified by the key
+            match unsafe { Module::deserialize(store, artifact) } {
+                Ok(module) => {
+                    MODULE_CACHE_HITS.inc();
+                    return Some(module);
+                }
+                Err(e) => {
+                    tracing::error!("Failed to deserialize cached module {key:?}: {e}");
+                    self.remove_key(key).await;
+                }
             }
         }
 
-        let mut mod_cache = self.0.write().await;
-        let new_module = Module::new(store, wasm_code).map_err(|_| AwsError::InvalidWasmModule)?;
+        MODULE_CACHE_MISSES.inc();
 
-        let bytes = new_module
-            .serialize()
-            .map_err(|_| AwsError::UnknownServerError)?
-            .to_vec();
+        None
+    }
+
+    pub async fn insert(&self, key: CacheKey, artifact: Bytes) {
+        if artifact.len() > self.max_size {
+            return;
+        }
+
+        let mut state = self.state.write().await;
+
+        let entry = CacheEntry {
+            last_used: AtomicU64::new(self.tick()),
+            artifact,
+        };
+
+        state.size += entry.artifact.len();
+
+        if let Some(old) = state.entries.insert(key, entry) {
+            state.size -= old.artifact.len();
+        }
+
+        while state.size > self.max_size {
+            let Some(lru) = state
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.last_used.load(Ordering::Relaxed))
+                .map(|(key, _)| *key)
+            else {
+                break;
+            };
+
+            if let Some(evicted) = state.entries.remove(&lru) {
+                state.size -= evicted.artifact.len();
+                MODULE_CACHE_EVICTIONS.inc();
+            }
+        }
+
+        MODULE_CACHE_SIZE.set(state.size as f64);
+    }
+
+    async fn remove_key(&self, key: CacheKey) {
+        let mut state = self.state.write().await;
 
-        mod_cache.insert(id, bytes.clone());
+        if let Some(removed) = state.entries.remove(&key) {
+            state.size -= removed.artifact.len();
+        }
 
-        Ok(bytes)
+        MODULE_CACHE_SIZE.set(state.size as f64);
     }
 
+    /// Evicts every artifact of the module, whatever engine it was
+    /// compiled with
     pub async fn remove(&self, id: i32) {
-        let mut mod_cache = self.0.write().await;
+        let mut state = self.state.write().await;
+
+        let keys = state
+            .entries
+            .keys()
+            .filter(|key| key.module_id == id)
+            .copied()
+            .collect::<Vec<_>>();
+
+        for key in keys {
+            if let Some(removed) = state.entries.remove(&key) {
+                state.size -= removed.artifact.len();
+            }
+        }
+
+        MODULE_CACHE_SIZE.set(state.size as f64);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::metered_store;
+
+    const MODULE: &str = r#"(module (func (export "one") (result i32) (i32.const 1)))"#;
+
+    fn key(module_id: i32) -> CacheKey {
+        CacheKey {
+            module_id,
+            engine_version: 1,
+            opcodes_id: 0,
+        }
+    }
+
+    async fn cached_keys(cache: &ModuleCache) -> Vec<CacheKey> {
+        let mut keys = cache
+            .state
+            .read()
+            .await
+            .entries
+            .keys()
+            .copied()
+            .collect::<Vec<_>>();
+
+        keys.sort_by_key(|key| key.module_id);
+        keys
+    }
+
+    // A metered engine can only compile a single module, so every
+    // load gets its own store like calls do
+    async fn load(cache: &ModuleCache, key: CacheKey) {
+        let store = metered_store();
+
+        if cache.get(&store, key).await.is_none() {
+            let module = Module::new(&store, MODULE).unwrap();
+            cache.insert(key, module.serialize().unwrap()).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_least_recently_used_is_evicted() {
+        let artifact_size = Module::new(&metered_store(), MODULE)
+            .unwrap()
+            .serialize()
+            .unwrap()
+            .len();
+        let cache = ModuleCache::new(artifact_size * 2);
+
+        load(&cache, key(1)).await;
+        load(&cache, key(2)).await;
+        load(&cache, key(1)).await;
+        load(&cache, key(3)).await;
+
+        assert_eq!(cached_keys(&cache).await, vec![key(1), key(3)]);
+        assert_eq!(cache.state.read().await.size, artifact_size * 2);
+    }
+
+    #[tokio::test]
+    async fn test_remove_evicts_every_engine_version() {
+        let cache = ModuleCache::default();
+
+        for engine_version in [1, 2] {
+            let key = CacheKey {
+                module_id: 1,
+                engine_version,
+                opcodes_id: 0,
+            };
+
+            load(&cache, key).await;
+        }
+
+        load(&cache, key(2)).await;
+        cache.remove(1).await;
 
-        mod_cache.remove(&id);
+        assert_eq!(cached_keys(&cache).await, vec![key(2)]);
     }
 }
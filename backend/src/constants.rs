@@ -1,3 +1,69 @@
-pub const JWT_TOKEN_VALIDITY: std::time::Duration = std::time::Duration::from_secs(60 * 60 * 48);
-pub const INITIAL_WALLET_CREDITS: i32 = 1_000_000;
-pub const MINIMUM_PASSWORD_LENGTH: usize = 12;
+/// Age past which the keys of the auth service are fetched again
+pub const JWKS_CACHE_TTL: std::time::Duration = std::time::Duration::from_secs(5 * 60);
+/// Least time between fetches of the keys, whatever tokens name
+pub const JWKS_REFRESH_MIN_INTERVAL: std::time::Duration = std::time::Duration::from_secs(10);
+pub const JWKS_FETCH_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+pub const AUTH_REQUEST_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+/// Bearer tokens starting with it are API keys rather than JWTs
+pub const API_KEY_PREFIX: &str = "awsk_";
+pub const API_KEY_NAME_MAX: usize = 64;
+/// How stale the last use of an API key may get, sparing a write per call
+pub const API_KEY_LAST_USED_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+pub const INITIAL_WALLET_CREDITS: i64 = 1_000_000;
+pub const MODULE_CACHE_DEFAULT_SIZE: usize = 256 * 1024 * 1024;
+pub const WASI_SYSCALL_COST: u64 = 100;
+pub const WASI_OUTPUT_LIMIT: usize = 64 * 1024;
+pub const CALL_TIMEOUT_DEFAULT: std::time::Duration = std::time::Duration::from_secs(10);
+pub const CALL_TIMEOUT_MAX: std::time::Duration = std::time::Duration::from_secs(60);
+pub const CALL_DEFAULT_RESERVATION: u64 = 10_000_000;
+/// Metering points a guest runs through in a millisecond, roughly, which
+/// caps the points of a call so it can't compute past its deadline
+pub const CALL_POINTS_PER_MS: u64 = 500_000;
+pub const MEMORY_LIMIT_PAGES: u32 = 4096;
+pub const TABLE_LIMIT_ELEMENTS: u32 = 100_000;
+pub const INSTANCE_LIMIT: u32 = 4;
+pub const FREE_TIER: &str = "free";
+pub const FREE_TIER_MEMORY_LIMIT_PAGES: u32 = 1024;
+pub const FREE_TIER_TABLE_LIMIT_ELEMENTS: u32 = 10_000;
+pub const FREE_TIER_INSTANCE_LIMIT: u32 = 1;
+pub const HTTP_HANDLER: &str = "http_handler";
+pub const HOST_CALL_COST: u64 = 100;
+pub const HOST_CALL_BYTE_COST: u64 = 1;
+pub const CALL_BASE_FEE: u64 = 0;
+pub const MEMORY_PAGE_SECOND_COST: u64 = 0;
+pub const PRICING_RELOAD_INTERVAL: std::time::Duration = std::time::Duration::from_secs(5);
+pub const ABI_RESULT_LIMIT: usize = 16 * 1024 * 1024;
+pub const HOST_LOG_LIMIT: usize = 64 * 1024;
+pub const HOST_RANDOM_LIMIT: usize = 64 * 1024;
+pub const KV_KEY_LIMIT: usize = 255;
+pub const KV_VALUE_LIMIT: usize = 64 * 1024;
+pub const KV_MODULE_KEYS_LIMIT: u64 = 1_000;
+pub const KV_MODULE_BYTES_LIMIT: u64 = 1024 * 1024;
+pub const JOB_WORKERS_DEFAULT: usize = 4;
+pub const JOB_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(500);
+pub const JOB_MAX_ATTEMPTS: i32 = 3;
+pub const JOB_RETRY_BACKOFF: std::time::Duration = std::time::Duration::from_secs(5);
+pub const SCHEDULER_INTERVAL: std::time::Duration = std::time::Duration::from_secs(5);
+pub const SCHEDULE_RUNS_LISTED: u64 = 50;
+pub const PIPELINE_MAX_STEPS: usize = 32;
+pub const BATCH_MAX_ITEMS: usize = 1_000;
+/// Calls of a parallel batch running at once
+pub const BATCH_PARALLELISM: usize = 8;
+pub const SESSION_TTL: std::time::Duration = std::time::Duration::from_secs(10 * 60);
+pub const SESSION_SWEEP_INTERVAL: std::time::Duration = std::time::Duration::from_secs(30);
+/// Memory held by the sessions of a user, snapshots included
+pub const SESSION_MEMORY_PER_USER: u64 = 256 * 1024 * 1024;
+pub const SESSION_MAX_SNAPSHOTS: usize = 8;
+pub const RESERVATION_SWEEP_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+/// Age past the longest call after which a reservation is stale
+pub const RESERVATION_GRACE: std::time::Duration = std::time::Duration::from_secs(60);
+/// Delay before handing credentials to the auth service again after it
+/// failed
+pub const CREDENTIAL_EXPORT_RETRY_INTERVAL: std::time::Duration =
+    std::time::Duration::from_secs(10);
+pub const LEDGER_PAGE_DEFAULT: u64 = 50;
+pub const LEDGER_PAGE_MAX: u64 = 500;
+/// Most credits moved by a single grant, transfer or top-up
+pub const CREDITS_MOVE_MAX: u64 = 1_000_000_000;
+pub const BUDGET_ALERT_PERCENT_DEFAULT: u8 = 80;
+pub const BUDGET_WEBHOOK_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
@@ -0,0 +1,485 @@
+//! Credits are reserved out of the wallet before a call runs and the
+//! part the call didn't use is given back once it's done, so concurrent
+//! calls can't spend the same credits. Reservations are recorded so the
+//! credits of calls that never settled, e.g. because the backend went
+//! down, are given back too.
+
+use std::{sync::Arc, time::Duration};
+
+use aws_common::api::errors::AwsError;
+use sea_orm::{
+    ActiveModelTrait, ActiveValue, ColumnTrait, ConnectionTrait, DatabaseConnection, DbErr,
+    EntityTrait, QueryFilter, QuerySelect, TransactionError, TransactionTrait,
+};
+use sea_query::{Expr, Query};
+
+use crate::{
+    budgets,
+    constants::{CREDITS_MOVE_MAX, RESERVATION_SWEEP_INTERVAL},
+    entities::{credit_reservation, user, wallet},
+    ledger::{record, record_debits, Debit, EntryKind},
+    migrator::m20230329_000003_wallets_table::Wallet,
+    utils::now_ms,
+};
+
+/// Credits held for a call until it is settled
+#[derive(Debug)]
+pub struct Reservation {
+    pub id: i32,
+    pub user_id: i32,
+    pub amount: u64,
+}
+
+const INSUFFICIENT_CREDITS: &str = "insufficient credits";
+
+/// Adds `amount` to the wallet, taking it away when negative. Nothing
+/// is taken from wallets holding less than that
+async fn update_wallet(txn: &impl ConnectionTrait, user_id: i32, amount: i64) -> Result<(), DbErr> {
+    let mut update_wallet_query = Query::update();
+
+    update_wallet_query
+        .table(Wallet::Table)
+        .value(Wallet::Credits, Expr::col(Wallet::Credits).add(amount))
+        .and_where(Expr::col(Wallet::Credits).gte(-amount.min(0)))
+        .and_where(Expr::col(Wallet::UserId).eq(user_id));
+
+    let builder = txn.get_database_backend();
+    let res = txn.execute(builder.build(&update_wallet_query)).await?;
+
+    match res.rows_affected() {
+        1 => Ok(()),
+        _ => Err(DbErr::Custom(INSUFFICIENT_CREDITS.to_string())),
+    }
+}
+
+/// Takes `amount` from the wallet even when it holds less, for credits
+/// already used whose reservation is gone
+async fn charge_wallet(txn: &impl ConnectionTrait, user_id: i32, amount: i64) -> Result<(), DbErr> {
+    let mut charge_wallet_query = Query::update();
+
+    charge_wallet_query
+        .table(Wallet::Table)
+        .value(Wallet::Credits, Expr::col(Wallet::Credits).sub(amount))
+        .and_where(Expr::col(Wallet::UserId).eq(user_id));
+
+    let builder = txn.get_database_backend();
+    txn.execute(builder.build(&charge_wallet_query)).await?;
+
+    Ok(())
+}
+
+pub(crate) fn map_transaction_error(e: TransactionError<DbErr>) -> AwsError {
+    match e {
+        TransactionError::Transaction(DbErr::Custom(reason)) if reason == INSUFFICIENT_CREDITS => {
+            AwsError::InsufficientCredits
+        }
+        e => {
+            tracing::error!("Credits transaction {e:#?}");
+            AwsError::UnknownServerError
+        }
+    }
+}
+
+/// Adds `amount` credits to the wallet, taking them away when negative,
+/// and records it in the ledger
+pub async fn apply(
+    txn: &impl ConnectionTrait,
+    user_id: i32,
+    kind: EntryKind,
+    amount: i64,
+    counterparty_id: Option<i32>,
+) -> Result<(), DbErr> {
+    update_wallet(txn, user_id, amount).await?;
+    record(txn, user_id, kind, amount, counterparty_id).await
+}
+
+/// Checks the credits moved at once are within bounds
+pub fn check_amount(amount: u64) -> Result<i64, AwsError> {
+    match amount {
+        1..=CREDITS_MOVE_MAX => Ok(amount as i64),
+        _ => Err(AwsError::InvalidAmount(CREDITS_MOVE_MAX)),
+    }
+}
+
+/// Gives `amount` credits to the user, or takes them away when
+/// `revoke`, as long as the wallet holds them
+pub async fn grant(
+    db: &DatabaseConnection,
+    user_id: i32,
+    amount: u64,
+    revoke: bool,
+) -> Result<(), AwsError> {
+    let amount = check_amount(amount)?;
+    let (kind, amount) = match revoke {
+        true => (EntryKind::Revoke, -amount),
+        false => (EntryKind::Grant, amount),
+    };
+
+    db.transaction(|txn| Box::pin(async move { apply(txn, user_id, kind, amount, None).await }))
+        .await
+        .map_err(map_transaction_error)
+}
+
+/// Moves `amount` credits from one user to the other, recording both
+/// sides or neither
+pub async fn transfer(
+    db: &DatabaseConnection,
+    from: i32,
+    to: &str,
+    amount: u64,
+) -> Result<(), AwsError> {
+    let amount = check_amount(amount)?;
+
+    let recipient = user::Entity::find()
+        .filter(user::Column::Username.eq(to))
+        .one(db)
+        .await
+        .map_err(|_| AwsError::UnknownServerError)?
+        .ok_or_else(|| AwsError::UserNotFound(to.to_string()))?;
+
+    if recipient.id == from {
+        return Err(AwsError::InvalidTransfer(
+            "cannot transfer to yourself".to_string(),
+        ));
+    }
+
+    db.transaction(|txn| {
+        Box::pin(async move {
+            apply(
+                txn,
+                from,
+                EntryKind::TransferOut,
+                -amount,
+                Some(recipient.id),
+            )
+            .await?;
+            apply(txn, recipient.id, EntryKind::TransferIn, amount, Some(from)).await
+        })
+    })
+    .await
+    .map_err(map_transaction_error)
+}
+
+/// Reserves at most `max` credits of the user, the whole balance
+/// without a cap. Fails if there is nothing to reserve, or if a
+/// concurrent call reserved the credits first
+pub async fn reserve(
+    db: &DatabaseConnection,
+    user_id: i32,
+    max: Option<u64>,
+) -> Result<Reservation, AwsError> {
+    db.transaction(|txn| Box::pin(async move { hold(txn, user_id, max).await }))
+        .await
+        .map_err(map_transaction_error)
+}
+
+/// Reserves at most `max` credits for calls of `modules`, within what
+/// the spending limits of the user still allow. The wallet stays locked
+/// from checking the limits until the credits are held, so concurrent
+/// calls can't both reserve what's left of a limit
+pub async fn reserve_within_limits(
+    db: &DatabaseConnection,
+    user_id: i32,
+    modules: &[i32],
+    max: u64,
+) -> Result<Reservation, AwsError> {
+    let db_error = |e| map_transaction_error(TransactionError::Transaction(e));
+
+    let txn = db.begin().await.map_err(db_error)?;
+
+    wallet::Entity::find()
+        .filter(wallet::Column::UserId.eq(user_id))
+        .lock_exclusive()
+        .one(&txn)
+        .await
+        .map_err(db_error)?;
+
+    let max = match budgets::allowance(&txn, user_id, modules).await? {
+        Some(allowance) => max.min(allowance),
+        None => max,
+    };
+
+    let reservation = hold(&txn, user_id, Some(max)).await.map_err(db_error)?;
+
+    txn.commit().await.map_err(db_error)?;
+
+    Ok(reservation)
+}
+
+async fn hold(
+    txn: &impl ConnectionTrait,
+    user_id: i32,
+    max: Option<u64>,
+) -> Result<Reservation, DbErr> {
+    let balance = wallet::Entity::find()
+        .filter(wallet::Column::UserId.eq(user_id))
+        .one(txn)
+        .await?
+        .map(|wallet| wallet.credits.max(0) as u64)
+        .unwrap_or_default();
+
+    let amount = max.map_or(balance, |max| max.min(balance));
+
+    if amount == 0 {
+        return Err(DbErr::Custom(INSUFFICIENT_CREDITS.to_string()));
+    }
+
+    update_wallet(txn, user_id, -(amount as i64)).await?;
+
+    let reservation = credit_reservation::ActiveModel {
+        user_id: ActiveValue::set(user_id),
+        amount: ActiveValue::set(amount as i64),
+        created_at: ActiveValue::set(now_ms()),
+        ..Default::default()
+    }
+    .insert(txn)
+    .await?;
+
+    Ok(Reservation {
+        id: reservation.id,
+        user_id,
+        amount,
+    })
+}
+
+/// Credits held by the user's reservations that weren't settled yet
+pub async fn reserved(db: &impl ConnectionTrait, user_id: i32) -> Result<u64, DbErr> {
+    Ok(credit_reservation::Entity::find()
+        .filter(credit_reservation::Column::UserId.eq(user_id))
+        .all(db)
+        .await?
+        .iter()
+        .map(|reservation| reservation.amount.max(0) as u64)
+        .sum())
+}
+
+/// Charges the credits of `debits` out of the reservation, recording
+/// them in the ledger, and gives back the rest
+pub async fn settle(
+    db: &DatabaseConnection,
+    reservation: Reservation,
+    debits: &[Debit],
+) -> Result<(), AwsError> {
+    let used = debits.iter().map(|debit| debit.credits).sum::<u64>();
+    let debits = debits.to_vec();
+
+    tracing::info!("used credits {used:#?}");
+
+    let refund = reservation.amount.saturating_sub(used) as i64;
+
+    db.transaction(|txn| {
+        Box::pin(async move {
+            let res = credit_reservation::Entity::delete_by_id(reservation.id)
+                .exec(txn)
+                .await?;
+
+            // Already given back by the sweeper, the credits the call
+            // used are charged to the wallet directly
+            if res.rows_affected == 0 {
+                tracing::error!("Reservation {} settled after expiring", reservation.id);
+                charge_wallet(txn, reservation.user_id, used as i64).await?;
+            } else {
+                update_wallet(txn, reservation.user_id, refund).await?;
+            }
+
+            record_debits(txn, reservation.user_id, &debits).await
+        })
+    })
+    .await
+    .map_err(map_transaction_error)
+}
+
+/// Gives back the credits of reservations older than `max_age`, which
+/// has to be longer than any call may run
+#[derive(Clone)]
+pub struct ReservationSweeper {
+    pub db: Arc<DatabaseConnection>,
+    pub max_age: Duration,
+}
+
+impl ReservationSweeper {
+    pub fn start(self) {
+        tokio::spawn(async move {
+            loop {
+                if let Err(e) = self.sweep().await {
+                    tracing::error!("Reservation sweeper {e:#?}");
+                }
+
+                tokio::time::sleep(RESERVATION_SWEEP_INTERVAL).await;
+            }
+        });
+    }
+
+    pub async fn sweep(&self) -> Result<(), AwsError> {
+        let stale = credit_reservation::Entity::find()
+            .filter(
+                credit_reservation::Column::CreatedAt
+                    .lt(now_ms() - self.max_age.as_millis() as i64),
+            )
+            .all(&*self.db)
+            .await
+            .map_err(|_| AwsError::UnknownServerError)?;
+
+        for reservation in stale {
+            tracing::info!("Releasing stale reservation {}", reservation.id);
+
+            let reservation = Reservation {
+                id: reservation.id,
+                user_id: reservation.user_id,
+                amount: reservation.amount as u64,
+            };
+
+            settle(&self.db, reservation, &[]).await?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{entities::ledger_entry, testing};
+
+    async fn wallet(credits: i64) -> (DatabaseConnection, i32) {
+        let db = testing::database().await;
+        let wallet = testing::wallet(&db, "user", credits).await;
+
+        (db, wallet.user_id)
+    }
+
+    async fn balance(db: &DatabaseConnection) -> i64 {
+        wallet::Entity::find()
+            .one(db)
+            .await
+            .unwrap()
+            .unwrap()
+            .credits
+    }
+
+    #[tokio::test]
+    async fn test_reserved_credits_are_settled() {
+        let (db, user_id) = wallet(100).await;
+
+        let capped = reserve(&db, user_id, Some(30)).await.unwrap();
+        assert_eq!(capped.amount, 30);
+
+        // What is left is reserved by the next call, leaving nothing
+        let rest = reserve(&db, user_id, None).await.unwrap();
+        assert_eq!(rest.amount, 70);
+        assert!(matches!(
+            reserve(&db, user_id, None).await,
+            Err(AwsError::InsufficientCredits)
+        ));
+
+        let used = Debit {
+            module_id: None,
+            function: None,
+            credits: 10,
+            duration_ms: 0,
+        };
+
+        settle(&db, capped, &[used]).await.unwrap();
+        settle(&db, rest, &[]).await.unwrap();
+
+        assert_eq!(balance(&db).await, 90);
+    }
+
+    #[tokio::test]
+    async fn test_stale_reservations_are_released() {
+        let (db, user_id) = wallet(100).await;
+        let db = Arc::new(db);
+
+        reserve(&db, user_id, Some(40)).await.unwrap();
+
+        let sweeper = ReservationSweeper {
+            db: db.clone(),
+            max_age: Duration::from_secs(60),
+        };
+
+        sweeper.sweep().await.unwrap();
+        assert_eq!(balance(&db).await, 60);
+
+        let sweeper = ReservationSweeper {
+            max_age: Duration::ZERO,
+            ..sweeper
+        };
+
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        sweeper.sweep().await.unwrap();
+        assert_eq!(balance(&db).await, 100);
+    }
+
+    #[tokio::test]
+    async fn test_expired_reservations_are_still_charged() {
+        let (db, user_id) = wallet(100).await;
+        let db = Arc::new(db);
+
+        let reservation = reserve(&db, user_id, Some(40)).await.unwrap();
+
+        let sweeper = ReservationSweeper {
+            db: db.clone(),
+            max_age: Duration::ZERO,
+        };
+
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        sweeper.sweep().await.unwrap();
+        assert_eq!(balance(&db).await, 100);
+
+        // The credits given back are spent before the call settles
+        reserve(&db, user_id, Some(90)).await.unwrap();
+
+        let used = Debit {
+            module_id: None,
+            function: None,
+            credits: 25,
+            duration_ms: 0,
+        };
+
+        settle(&db, reservation, &[used]).await.unwrap();
+        assert_eq!(balance(&db).await, -15);
+
+        let entries = ledger_entry::Entity::find().all(db.as_ref()).await.unwrap();
+        assert!(entries.iter().any(|entry| entry.amount == -25));
+    }
+
+    #[tokio::test]
+    async fn test_transfers_move_credits_atomically() {
+        let (db, from) = wallet(100).await;
+
+        let to = testing::wallet(&db, "recipient", 0).await.user_id;
+
+        transfer(&db, from, "recipient", 60).await.unwrap();
+
+        // Nothing moves when the sender can't cover it
+        assert!(matches!(
+            transfer(&db, from, "recipient", 60).await,
+            Err(AwsError::InsufficientCredits)
+        ));
+        assert!(matches!(
+            transfer(&db, from, "user", 10).await,
+            Err(AwsError::InvalidTransfer(_))
+        ));
+
+        let wallets = wallet::Entity::find().all(&db).await.unwrap();
+        assert_eq!(
+            wallets.iter().map(|w| w.credits).collect::<Vec<_>>(),
+            vec![40, 60]
+        );
+
+        let entries = crate::entities::ledger_entry::Entity::find()
+            .all(&db)
+            .await
+            .unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].amount, -60);
+        assert_eq!(entries[0].counterparty_id, Some(to));
+        assert_eq!(entries[1].kind, "transfer_in");
+
+        grant(&db, to, 60, true).await.unwrap();
+        assert!(matches!(
+            grant(&db, to, 1, true).await,
+            Err(AwsError::InsufficientCredits)
+        ));
+    }
+}
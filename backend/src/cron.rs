@@ -0,0 +1,213 @@
+//! Cron expressions with the usual five fields, evaluated in UTC:
+//! minute, hour, day of month, month and day of week (0 or 7 is
+//! Sunday). Fields accept `*`, values, ranges, lists and steps, e.g.
+//! `*/15 9-17 * * 1-5`. Like most crons, a day matches when either of
+//! the day fields matches if both are restricted.
+
+use std::{fmt, str::FromStr};
+
+use aws_common::api::errors::AwsError;
+use chrono::{DateTime, Datelike, Duration, NaiveDate, NaiveDateTime, Timelike, Utc};
+
+/// Years to look ahead for a matching time before giving up, enough
+/// for any date that exists
+const SEARCH_YEARS: i32 = 8;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CronSchedule {
+    source: String,
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    days_restricted: bool,
+    weekdays_restricted: bool,
+}
+
+fn parse_field(field: &str, min: u32, max: u32) -> Option<u64> {
+    let mut bits = 0;
+
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, Some(step.parse::<u32>().ok()?)),
+            None => (part, None),
+        };
+
+        let (lo, hi) = match range.split_once('-') {
+            _ if range == "*" => (min, max),
+            Some((lo, hi)) => (lo.parse().ok()?, hi.parse().ok()?),
+            // `5/10` runs from 5 to the end of the range
+            None if step.is_some() => (range.parse().ok()?, max),
+            None => {
+                let v = range.parse().ok()?;
+                (v, v)
+            }
+        };
+
+        let step = step.unwrap_or(1);
+
+        if step == 0 || lo < min || hi > max || lo > hi {
+            return None;
+        }
+
+        for v in (lo..=hi).step_by(step as usize) {
+            bits |= 1 << v;
+        }
+    }
+
+    Some(bits)
+}
+
+impl FromStr for CronSchedule {
+    type Err = AwsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || AwsError::InvalidCronExpression(s.to_string());
+
+        let [minutes, hours, days, months, weekdays] = s
+            .split_whitespace()
+            .collect::<Vec<_>>()
+            .try_into()
+            .map_err(|_| invalid())?;
+
+        let mut weekdays_bits = parse_field(weekdays, 0, 7).ok_or_else(invalid)?;
+
+        // Both 0 and 7 are Sunday
+        if weekdays_bits & (1 << 7) != 0 {
+            weekdays_bits = (weekdays_bits | 1) & !(1 << 7);
+        }
+
+        Ok(Self {
+            source: s.split_whitespace().collect::<Vec<_>>().join(" "),
+            minutes: parse_field(minutes, 0, 59).ok_or_else(invalid)?,
+            hours: parse_field(hours, 0, 23).ok_or_else(invalid)?,
+            days: parse_field(days, 1, 31).ok_or_else(invalid)?,
+            months: parse_field(months, 1, 12).ok_or_else(invalid)?,
+            weekdays: weekdays_bits,
+            days_restricted: !days.starts_with('*'),
+            weekdays_restricted: !weekdays.starts_with('*'),
+        })
+    }
+}
+
+impl fmt::Display for CronSchedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+fn has(bits: u64, v: u32) -> bool {
+    bits & (1 << v) != 0
+}
+
+impl CronSchedule {
+    fn matches_day(&self, date: NaiveDate) -> bool {
+        let day = has(self.days, date.day());
+        let weekday = has(self.weekdays, date.weekday().num_days_from_sunday());
+
+        match (self.days_restricted, self.weekdays_restricted) {
+            (true, true) => day || weekday,
+            _ => day && weekday,
+        }
+    }
+
+    /// First matching time strictly after `after`
+    pub fn next_after(&self, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let after = after.naive_utc();
+        let mut t =
+            after.date().and_hms_opt(after.hour(), after.minute(), 0)? + Duration::minutes(1);
+        let last_year = t.year() + SEARCH_YEARS;
+
+        while t.year() <= last_year {
+            let date = t.date();
+
+            if !has(self.months, date.month()) {
+                let (year, month) = match date.month() {
+                    12 => (date.year() + 1, 1),
+                    month => (date.year(), month + 1),
+                };
+                t = NaiveDate::from_ymd_opt(year, month, 1)?.and_hms_opt(0, 0, 0)?;
+            } else if !self.matches_day(date) {
+                t = date.succ_opt()?.and_hms_opt(0, 0, 0)?;
+            } else if !has(self.hours, t.hour()) {
+                t = date.and_hms_opt(t.hour(), 0, 0)? + Duration::hours(1);
+            } else if !has(self.minutes, t.minute()) {
+                t += Duration::minutes(1);
+            } else {
+                return Some(DateTime::from_utc(t, Utc));
+            }
+        }
+
+        None
+    }
+}
+
+/// Unix time in milliseconds of the first matching time after `after_ms`
+pub fn next_run_ms(schedule: &CronSchedule, after_ms: i64) -> Option<i64> {
+    let after = NaiveDateTime::from_timestamp_millis(after_ms)?;
+
+    schedule
+        .next_after(DateTime::from_utc(after, Utc))
+        .map(|next| next.timestamp_millis())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn at(y: i32, mo: u32, d: u32, h: u32, mi: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(y, mo, d, h, mi, 0).unwrap()
+    }
+
+    #[test]
+    fn test_next_matching_time() {
+        let every_quarter = "*/15 9-17 * * 1-5".parse::<CronSchedule>().unwrap();
+
+        // Friday evening to Monday morning
+        assert_eq!(
+            every_quarter.next_after(at(2026, 10, 16, 17, 45)),
+            Some(at(2026, 10, 19, 9, 0))
+        );
+        assert_eq!(
+            every_quarter.next_after(at(2026, 10, 19, 9, 0)),
+            Some(at(2026, 10, 19, 9, 15))
+        );
+
+        let leap_day = "0 0 29 2 *".parse::<CronSchedule>().unwrap();
+
+        assert_eq!(
+            leap_day.next_after(at(2026, 10, 18, 0, 0)),
+            Some(at(2028, 2, 29, 0, 0))
+        );
+
+        // Either the 1st or a Sunday
+        let either = "30 6 1 * 7".parse::<CronSchedule>().unwrap();
+
+        assert_eq!(
+            either.next_after(at(2026, 10, 18, 7, 0)),
+            Some(at(2026, 10, 25, 6, 30))
+        );
+        assert_eq!(either.to_string(), "30 6 1 * 7");
+    }
+
+    #[test]
+    fn test_invalid_expressions_are_rejected() {
+        for expr in [
+            "* * * *",
+            "60 * * * *",
+            "* * 0 * *",
+            "*/0 * * * *",
+            "5-1 * * * *",
+        ] {
+            assert!(expr.parse::<CronSchedule>().is_err(), "{expr}");
+        }
+
+        assert!("0 0 31 2 *"
+            .parse::<CronSchedule>()
+            .unwrap()
+            .next_after(at(2026, 1, 1, 0, 0))
+            .is_none());
+    }
+}
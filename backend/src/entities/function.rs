@@ -14,6 +14,8 @@ pub struct Model {
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
 pub enum Relation {
+    #[sea_orm(has_many = "super::job::Entity")]
+    Job,
     #[sea_orm(
         belongs_to = "super::module::Entity",
         from = "Column::ModuleId",
@@ -22,6 +24,14 @@ pub enum Relation {
         on_delete = "NoAction"
     )]
     Module,
+    #[sea_orm(has_many = "super::schedule::Entity")]
+    Schedule,
+}
+
+impl Related<super::job::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Job.def()
+    }
 }
 
 impl Related<super::module::Entity> for Entity {
@@ -30,4 +40,10 @@ impl Related<super::module::Entity> for Entity {
     }
 }
 
+impl Related<super::schedule::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Schedule.def()
+    }
+}
+
 impl ActiveModelBehavior for ActiveModel {}
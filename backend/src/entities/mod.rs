@@ -2,7 +2,18 @@
 
 pub mod prelude;
 
+pub mod api_key;
+pub mod credit_reservation;
 pub mod function;
+pub mod job;
+pub mod ledger_entry;
 pub mod module;
+pub mod module_artifact;
+pub mod module_kv;
+pub mod pipeline;
+pub mod schedule;
+pub mod schedule_run;
+pub mod spending_limit;
+pub mod top_up;
 pub mod user;
 pub mod wallet;
@@ -10,12 +10,17 @@ pub struct Model {
     pub owner_id: i32,
     pub code_hash: String,
     pub wasm_code: Vec<u8>,
+    pub public_call_credits: Option<i64>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
 pub enum Relation {
     #[sea_orm(has_many = "super::function::Entity")]
     Function,
+    #[sea_orm(has_many = "super::module_artifact::Entity")]
+    ModuleArtifact,
+    #[sea_orm(has_many = "super::module_kv::Entity")]
+    ModuleKv,
     #[sea_orm(
         belongs_to = "super::user::Entity",
         from = "Column::OwnerId",
@@ -32,6 +37,18 @@ impl Related<super::function::Entity> for Entity {
     }
 }
 
+impl Related<super::module_artifact::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::ModuleArtifact.def()
+    }
+}
+
+impl Related<super::module_kv::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::ModuleKv.def()
+    }
+}
+
 impl Related<super::user::Entity> for Entity {
     fn to() -> RelationDef {
         Relation::User.def()
@@ -1,6 +1,17 @@
 //! `SeaORM` Entity. Generated by sea-orm-codegen 0.11.0
 
+pub use super::api_key::Entity as ApiKey;
+pub use super::credit_reservation::Entity as CreditReservation;
 pub use super::function::Entity as Function;
+pub use super::job::Entity as Job;
+pub use super::ledger_entry::Entity as LedgerEntry;
 pub use super::module::Entity as Module;
+pub use super::module_artifact::Entity as ModuleArtifact;
+pub use super::module_kv::Entity as ModuleKv;
+pub use super::pipeline::Entity as Pipeline;
+pub use super::schedule::Entity as Schedule;
+pub use super::schedule_run::Entity as ScheduleRun;
+pub use super::spending_limit::Entity as SpendingLimit;
+pub use super::top_up::Entity as TopUp;
 pub use super::user::Entity as User;
 pub use super::wallet::Entity as Wallet;
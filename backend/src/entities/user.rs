@@ -9,22 +9,89 @@ pub struct Model {
     pub id: i32,
     pub username: String,
     pub password: String,
+    pub tier: String,
+    pub admin: bool,
+    pub budget_webhook_url: Option<String>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
 pub enum Relation {
+    #[sea_orm(has_many = "super::api_key::Entity")]
+    ApiKey,
+    #[sea_orm(has_many = "super::credit_reservation::Entity")]
+    CreditReservation,
+    #[sea_orm(has_many = "super::job::Entity")]
+    Job,
+    #[sea_orm(has_many = "super::ledger_entry::Entity")]
+    LedgerEntry,
     #[sea_orm(has_many = "super::module::Entity")]
     Module,
+    #[sea_orm(has_many = "super::pipeline::Entity")]
+    Pipeline,
+    #[sea_orm(has_many = "super::schedule::Entity")]
+    Schedule,
+    #[sea_orm(has_many = "super::spending_limit::Entity")]
+    SpendingLimit,
+    #[sea_orm(has_many = "super::top_up::Entity")]
+    TopUp,
     #[sea_orm(has_many = "super::wallet::Entity")]
     Wallet,
 }
 
+impl Related<super::api_key::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::ApiKey.def()
+    }
+}
+
+impl Related<super::credit_reservation::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::CreditReservation.def()
+    }
+}
+
+impl Related<super::job::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Job.def()
+    }
+}
+
+impl Related<super::ledger_entry::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::LedgerEntry.def()
+    }
+}
+
 impl Related<super::module::Entity> for Entity {
     fn to() -> RelationDef {
         Relation::Module.def()
     }
 }
 
+impl Related<super::pipeline::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Pipeline.def()
+    }
+}
+
+impl Related<super::schedule::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Schedule.def()
+    }
+}
+
+impl Related<super::spending_limit::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::SpendingLimit.def()
+    }
+}
+
+impl Related<super::top_up::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::TopUp.def()
+    }
+}
+
 impl Related<super::wallet::Entity> for Entity {
     fn to() -> RelationDef {
         Relation::Wallet.def()
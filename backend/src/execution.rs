@@ -0,0 +1,979 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use aws_common::api::{
+    errors::AwsError,
+    requests::WasiOptions,
+    responses::{CallFunctionResponse, LogEntry, WasiOutput},
+};
+use axum::{body::HttpBody, http::StatusCode, response::IntoResponse};
+use sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter};
+use wasmer::{Imports, Instance, Module, Store};
+use wasmer_middlewares::metering::{get_remaining_points, set_remaining_points, MeteringPoints};
+use wasmer_types::TrapCode;
+use wasmer_wasi::WasiError;
+
+use crate::{
+    abi::{self, AbiError, AbiValue},
+    artifacts::load_module,
+    budgets,
+    cache::ModuleCache,
+    constants::{
+        BATCH_PARALLELISM, CALL_DEFAULT_RESERVATION, CALL_TIMEOUT_DEFAULT, CALL_TIMEOUT_MAX,
+    },
+    credits::{reserve_within_limits, settle, Reservation},
+    entities::{self, function},
+    ffi::WasmFFIConverter,
+    host::{HostCallError, HostContext, KvStore},
+    interrupt::{Interrupt, Interrupted},
+    ledger::Debit,
+    limits::{ResourceLimits, ResourceUsage},
+    metrics::{FUNCTION_CALLS, FUNCTION_CALL_PEAK_MEMORY},
+    pricing,
+    utils::{limited_store, limited_store_with},
+    wasi::{CreditsExhausted, WasiContext},
+};
+
+/// Server wide limits of function calls
+#[derive(Clone, Copy, Debug)]
+pub struct ExecutionLimits {
+    pub default_timeout: Duration,
+    pub max_timeout: Duration,
+    /// Credits reserved for a call that doesn't cap them, so that one
+    /// call doesn't hold the whole wallet
+    pub default_reservation: u64,
+    pub resources: ResourceLimits,
+}
+
+impl Default for ExecutionLimits {
+    fn default() -> Self {
+        Self {
+            default_timeout: CALL_TIMEOUT_DEFAULT,
+            max_timeout: CALL_TIMEOUT_MAX,
+            default_reservation: CALL_DEFAULT_RESERVATION,
+            resources: ResourceLimits::default(),
+        }
+    }
+}
+
+impl ExecutionLimits {
+    /// Deadline of a call, the requested one can only tighten the server's
+    pub fn timeout(&self, requested_ms: Option<u64>) -> Duration {
+        requested_ms
+            .map(Duration::from_millis)
+            .unwrap_or(self.default_timeout)
+            .min(self.max_timeout)
+    }
+
+    /// Credits to reserve for `calls` calls, the requested amount or the
+    /// default reservation of each call
+    pub fn reservation(&self, requested: Option<u64>, calls: usize) -> u64 {
+        requested.unwrap_or_else(|| self.default_reservation.saturating_mul(calls as u64))
+    }
+}
+
+/// A function call on behalf of the owner of `wallet`
+pub struct Call {
+    pub wallet: entities::wallet::Model,
+    pub module: entities::module::Model,
+    pub function: function::Model,
+    pub params: Vec<AbiValue>,
+    pub wasi: Option<WasiOptions>,
+    pub timeout_ms: Option<u64>,
+    /// Most credits the call may use, the default reservation otherwise
+    pub max_credits: Option<u64>,
+}
+
+/// Call stored by a job or schedule, with its parameters and WASI
+/// options serialized as JSON
+pub struct StoredCall {
+    pub owner_id: i32,
+    pub function_id: i32,
+    pub params: String,
+    pub wasi: Option<String>,
+    pub timeout_ms: Option<i64>,
+    pub max_credits: Option<i64>,
+}
+
+/// Calls of the same function over many params, charged at once
+pub struct BatchCall {
+    pub wallet: entities::wallet::Model,
+    pub module: entities::module::Model,
+    pub function: function::Model,
+    pub items: Vec<Vec<serde_json::Value>>,
+    pub parallel: bool,
+    pub timeout_ms: Option<u64>,
+    pub max_credits: Option<u64>,
+}
+
+/// Runs function calls and charges their owners, shared by every way
+/// a function can be called
+#[derive(Clone)]
+pub struct Executor {
+    pub db: Arc<DatabaseConnection>,
+    pub cache: ModuleCache,
+    pub limits: ExecutionLimits,
+}
+
+impl Executor {
+    pub async fn call(&self, call: Call) -> Result<Execution, ExecutionFailure> {
+        let timeout = self.limits.timeout(call.timeout_ms);
+        let max = self.limits.reservation(call.max_credits, 1);
+        let user_id = call.wallet.user_id;
+        let module_id = call.module.id;
+        let function = call.function.clone();
+
+        // Nothing is reserved for modules that don't load
+        let mut invocation = self.prepare(call).await?;
+
+        let reservation = self.reserve(user_id, &[module_id], max).await?;
+        invocation.credits = reservation.amount;
+        let started = Instant::now();
+
+        let outcome = self.run_prepared(invocation, timeout).await;
+
+        self.settle(reservation, &function, started, outcome).await
+    }
+
+    /// Reserves at most `max` credits for calls of `modules`, within
+    /// what the spending limits of the user still allow
+    pub async fn reserve(
+        &self,
+        user_id: i32,
+        modules: &[i32],
+        max: u64,
+    ) -> Result<Reservation, AwsError> {
+        reserve_within_limits(&self.db, user_id, modules, max).await
+    }
+
+    /// Charges `debits` out of the reservation, then alerts the user of
+    /// the spending limits they crossed
+    pub async fn settle_debits(
+        &self,
+        reservation: Reservation,
+        debits: &[Debit],
+    ) -> Result<(), AwsError> {
+        let user_id = reservation.user_id;
+
+        settle(&self.db, reservation, debits).await?;
+
+        let modules = debits
+            .iter()
+            .filter(|debit| debit.credits > 0)
+            .filter_map(|debit| debit.module_id)
+            .collect::<Vec<_>>();
+
+        if !modules.is_empty() {
+            let db = self.db.clone();
+
+            tokio::spawn(async move {
+                if let Err(e) = budgets::alert(&db, user_id, &modules).await {
+                    tracing::error!("Budget alerts {e:#?}");
+                }
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Charges the outcome of a call of `function` started at `started`
+    /// out of its reservation
+    pub async fn settle(
+        &self,
+        reservation: Reservation,
+        function: &function::Model,
+        started: Instant,
+        outcome: Result<Execution, ExecutionFailure>,
+    ) -> Result<Execution, ExecutionFailure> {
+        // Calls failing before their guest ran used nothing
+        let used_credits = match &outcome {
+            Ok(execution) => execution.used_credits,
+            Err(failure) => failure.used_credits,
+        };
+
+        let debit = Debit::new(function, used_credits, started);
+        self.settle_debits(reservation, &[debit]).await?;
+
+        outcome
+    }
+
+    /// Runs the call with at most `credits` and until `timeout`, leaving
+    /// charging them to the caller. `call.timeout_ms` and
+    /// `call.max_credits` are ignored
+    pub async fn run(
+        &self,
+        call: Call,
+        credits: u64,
+        timeout: Duration,
+    ) -> Result<Execution, ExecutionFailure> {
+        let invocation = Invocation {
+            credits,
+            ..self.prepare(call).await?
+        };
+
+        self.run_prepared(invocation, timeout).await
+    }
+
+    /// Loads the module of the call into a store limited by the tier of
+    /// its owner, leaving the credits of the invocation at zero
+    pub async fn prepare(&self, call: Call) -> Result<Invocation, AwsError> {
+        let Call {
+            wallet,
+            module,
+            function,
+            params,
+            wasi,
+            ..
+        } = call;
+
+        let user = entities::user::Entity::find_by_id(wallet.user_id)
+            .one(&*self.db)
+            .await
+            .map_err(|_| AwsError::UnknownServerError)?
+            .ok_or(AwsError::Unauthorized)?;
+
+        let (store, usage) = limited_store(self.limits.resources.for_tier(&user.tier));
+
+        let kv = KvStore {
+            db: self.db.clone(),
+            module_id: module.id,
+            runtime: tokio::runtime::Handle::current(),
+        };
+
+        let module = load_module(&self.db, &self.cache, &store, &module).await?;
+
+        Ok(Invocation {
+            store,
+            usage,
+            module,
+            function,
+            params,
+            wasi,
+            kv: Some(kv),
+            credits: 0,
+        })
+    }
+
+    /// Runs a prepared invocation until `timeout`
+    pub async fn run_prepared(
+        &self,
+        invocation: Invocation,
+        timeout: Duration,
+    ) -> Result<Execution, ExecutionFailure> {
+        let execution = execute(invocation, timeout).await?;
+
+        FUNCTION_CALLS.inc();
+
+        Ok(execution)
+    }
+
+    /// Runs a stored call, returning the body a synchronous call would
+    /// have responded with along with the credits it used, which failed
+    /// calls were charged too
+    pub async fn call_stored(
+        &self,
+        call: StoredCall,
+    ) -> Result<(serde_json::Value, u64), ExecutionFailure> {
+        let db = &*self.db;
+        let server_error = |_| AwsError::UnknownServerError;
+
+        let function = entities::function::Entity::find_by_id(call.function_id)
+            .one(db)
+            .await
+            .map_err(server_error)?
+            .ok_or_else(|| AwsError::FunctionNotFound(call.function_id.to_string()))?;
+
+        let module = entities::module::Entity::find_by_id(function.module_id)
+            .one(db)
+            .await
+            .map_err(server_error)?
+            .ok_or(AwsError::EndpointNotFound(function.module_id))?;
+
+        let wallet = entities::wallet::Entity::find()
+            .filter(entities::wallet::Column::UserId.eq(call.owner_id))
+            .one(db)
+            .await
+            .map_err(server_error)?
+            .ok_or(AwsError::UnknownServerError)?;
+
+        let params = serde_json::from_str::<Vec<serde_json::Value>>(&call.params)
+            .map_err(|_| AwsError::UnknownServerError)?;
+        let params = function.to_abi_params(&params)?;
+
+        let wasi = call
+            .wasi
+            .map(|wasi| serde_json::from_str(&wasi))
+            .transpose()
+            .map_err(|_| AwsError::UnknownServerError)?;
+
+        let execution = self
+            .call(Call {
+                wallet,
+                module,
+                function,
+                params,
+                wasi,
+                timeout_ms: call.timeout_ms.map(|ms| ms as u64),
+                max_credits: call.max_credits.map(|credits| credits as u64),
+            })
+            .await?;
+
+        let used_credits = execution.used_credits;
+        let response = CallFunctionResponse {
+            return_value: execution.return_value,
+            wasi: execution.wasi,
+            logs: execution.logs,
+        };
+
+        Ok((response.into_json(), used_credits))
+    }
+
+    /// Runs every item of the batch within a single deadline, each in
+    /// its own instance of a module compiled once. Parallel batches run
+    /// `BATCH_PARALLELISM` items at once, splitting the credits left
+    /// between them. What every item used is settled at once
+    pub async fn call_batch(
+        &self,
+        batch: BatchCall,
+    ) -> Result<(Vec<Result<Execution, ExecutionFailure>>, u64), AwsError> {
+        let BatchCall {
+            wallet,
+            module,
+            function,
+            items,
+            parallel,
+            timeout_ms,
+            max_credits,
+        } = batch;
+
+        let user = entities::user::Entity::find_by_id(wallet.user_id)
+            .one(&*self.db)
+            .await
+            .map_err(|_| AwsError::UnknownServerError)?
+            .ok_or(AwsError::Unauthorized)?;
+
+        let limits = self.limits.resources.for_tier(&user.tier);
+        let (store, _) = limited_store(limits);
+        let engine = store.engine().clone();
+        let compiled = load_module(&self.db, &self.cache, &store, &module).await?;
+        let started = Instant::now();
+        let deadline = started + self.limits.timeout(timeout_ms);
+        let reservation = self
+            .reserve(
+                wallet.user_id,
+                &[module.id],
+                self.limits.reservation(max_credits, items.len()),
+            )
+            .await?;
+
+        let width = if parallel { BATCH_PARALLELISM } else { 1 };
+        let mut results = Vec::with_capacity(items.len());
+        let mut used_credits = 0u64;
+
+        for chunk in items.chunks(width) {
+            let credits = reservation.amount.saturating_sub(used_credits) / chunk.len() as u64;
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            let mut running = Vec::with_capacity(chunk.len());
+
+            for params in chunk {
+                let params = match function.to_abi_params(params) {
+                    Ok(_) if remaining.is_zero() => Err(AwsError::ExecutionTimeout),
+                    params => params,
+                };
+
+                running.push(params.map(|params| {
+                    let (store, usage) = limited_store_with(engine.clone(), limits);
+
+                    let invocation = Invocation {
+                        store,
+                        usage,
+                        module: compiled.clone(),
+                        function: function.clone(),
+                        params,
+                        wasi: None,
+                        kv: Some(KvStore {
+                            db: self.db.clone(),
+                            module_id: module.id,
+                            runtime: tokio::runtime::Handle::current(),
+                        }),
+                        credits,
+                    };
+
+                    tokio::spawn(execute(invocation, remaining))
+                }));
+            }
+
+            for call in running {
+                let outcome = match call {
+                    Ok(handle) => handle.await.unwrap_or_else(|e| {
+                        tracing::error!("Batch call panicked {e:#?}");
+                        Err(AwsError::UnknownServerError.into())
+                    }),
+                    Err(error) => Err(error.into()),
+                };
+
+                used_credits += match &outcome {
+                    Ok(execution) => {
+                        FUNCTION_CALLS.inc();
+                        execution.used_credits
+                    }
+                    Err(failure) => failure.used_credits,
+                };
+
+                results.push(outcome);
+            }
+        }
+
+        let debit = Debit::new(&function, used_credits, started);
+        self.settle_debits(reservation, &[debit]).await?;
+
+        Ok((results, used_credits))
+    }
+}
+
+/// Everything needed to run a function call away from the async runtime
+pub struct Invocation {
+    /// Store created by `limited_store`, along with its usage
+    pub store: Store,
+    pub usage: ResourceUsage,
+    pub module: Module,
+    pub function: function::Model,
+    pub params: Vec<AbiValue>,
+    pub wasi: Option<WasiOptions>,
+    pub kv: Option<KvStore>,
+    pub credits: u64,
+}
+
+pub struct Execution {
+    pub return_value: Vec<serde_json::Value>,
+    pub wasi: Option<WasiOutput>,
+    pub logs: Vec<LogEntry>,
+    pub used_credits: u64,
+}
+
+/// A failed call, along with the credits it consumed before failing
+#[derive(Debug)]
+pub struct ExecutionFailure {
+    pub error: AwsError,
+    pub used_credits: u64,
+}
+
+impl From<AwsError> for ExecutionFailure {
+    fn from(error: AwsError) -> Self {
+        Self {
+            error,
+            used_credits: 0,
+        }
+    }
+}
+
+impl From<ExecutionFailure> for AwsError {
+    fn from(failure: ExecutionFailure) -> Self {
+        failure.error
+    }
+}
+
+/// Status and message the error is responded with, for calls whose
+/// outcome is recorded rather than responded
+pub async fn describe(error: AwsError) -> (StatusCode, String) {
+    let response = error.into_response();
+    let status = response.status();
+
+    let mut body = response.into_body();
+    let mut bytes = Vec::new();
+
+    while let Some(Ok(chunk)) = body.data().await {
+        bytes.extend_from_slice(&chunk);
+    }
+
+    let message = serde_json::from_slice::<serde_json::Value>(&bytes)
+        .ok()
+        .and_then(|body| body["error"].as_str().map(str::to_string))
+        .unwrap_or_else(|| "server error".to_string());
+
+    (status, message)
+}
+
+/// Runs the call on the blocking pool, interrupting it once `timeout`
+/// has elapsed
+pub async fn execute(
+    invocation: Invocation,
+    timeout: Duration,
+) -> Result<Execution, ExecutionFailure> {
+    let interrupt = Interrupt::new(timeout);
+
+    tokio::task::spawn_blocking(move || run(invocation, interrupt))
+        .await
+        .map_err(|e| {
+            tracing::error!("Func call panicked {e:#?}");
+            ExecutionFailure::from(AwsError::UnknownServerError)
+        })?
+}
+
+fn run(invocation: Invocation, interrupt: Interrupt) -> Result<Execution, ExecutionFailure> {
+    let Invocation {
+        mut store,
+        usage,
+        module,
+        function,
+        params,
+        wasi,
+        kv,
+        credits,
+    } = invocation;
+
+    let mut wasi = wasi
+        .map(|options| WasiContext::new(&mut store, &function.name, &options))
+        .transpose()?;
+
+    let host = HostContext::new(&mut store, kv);
+
+    let mut imports = match &wasi {
+        Some(wasi) => wasi.imports(&mut store, &module)?,
+        None => Imports::new(),
+    };
+
+    imports.extend(&host.imports(&mut store));
+
+    let instance = instantiate(&mut store, &usage, &module, &imports)?;
+
+    if let Some(wasi) = &mut wasi {
+        wasi.initialize(&mut store, &instance)?;
+        wasi.arm(&mut store, interrupt);
+    }
+
+    host.initialize(&mut store, &instance);
+    host.arm(&mut store, interrupt);
+
+    let (return_value, exit_code, used_credits) = invoke(
+        &mut store, &instance, &usage, &function, &params, credits, &interrupt,
+    )?;
+
+    Ok(Execution {
+        return_value,
+        wasi: wasi.map(|wasi| wasi.output(exit_code)),
+        logs: host.logs(&mut store),
+        used_credits,
+    })
+}
+
+fn instantiate(
+    store: &mut Store,
+    usage: &ResourceUsage,
+    module: &Module,
+    imports: &Imports,
+) -> Result<Instance, AwsError> {
+    Instance::new(store, module, imports).map_err(|e| {
+        if usage.exceeded() {
+            AwsError::MemoryLimitExceeded
+        } else {
+            AwsError::WasmInstanceError(Box::new(e))
+        }
+    })
+}
+
+/// Calls the function on an instance with at most `credits`, returning
+/// its results, its exit code if it exited and the credits it used
+fn invoke(
+    store: &mut Store,
+    instance: &Instance,
+    usage: &ResourceUsage,
+    function: &function::Model,
+    params: &[AbiValue],
+    credits: u64,
+    interrupt: &Interrupt,
+) -> Result<(Vec<serde_json::Value>, Option<u32>, u64), ExecutionFailure> {
+    let pricing = pricing::current();
+
+    // The base fee is taken out of the points the guest may use
+    let affordable = credits
+        .checked_sub(pricing.call_base_fee)
+        .ok_or(AwsError::InsufficientCredits)?;
+
+    // A guest that never calls the host can only be stopped by running
+    // out of points, so it gets no more than it can use until the deadline
+    let points = affordable.min(interrupt.points());
+    let capped = points < affordable;
+
+    set_remaining_points(store, instance, points);
+    let started = Instant::now();
+
+    let func = instance
+        .exports
+        .get_function(&function.name)
+        .map_err(|_| AwsError::FunctionNotFound(function.name.clone()))?;
+
+    let ret_types = function.get_ret_types()?;
+
+    let result = abi::lower(store, instance, params).and_then(|lowered| {
+        let results = func.call(store, &lowered)?;
+        abi::lift(store, instance, &ret_types, &results)
+    });
+
+    FUNCTION_CALL_PEAK_MEMORY.observe(usage.memory_bytes() as f64);
+
+    let remaining = match get_remaining_points(store, instance) {
+        MeteringPoints::Remaining(x) => x,
+        MeteringPoints::Exhausted => 0,
+    };
+
+    // Memory held is charged on top, as far as the credits go
+    let memory_cost = pricing.memory_cost(usage.memory_bytes(), started.elapsed());
+    let used_credits = pricing
+        .call_base_fee
+        .saturating_add(points.saturating_sub(remaining))
+        .saturating_add(memory_cost)
+        .min(credits);
+
+    // Out of points that were capped by the deadline, the guest ran out
+    // of time rather than credits
+    let exhausted = if capped {
+        AwsError::ExecutionTimeout
+    } else {
+        AwsError::InsufficientCredits
+    };
+
+    // The guest ran, so what it used is charged however it failed
+    let failed = |error: AwsError| ExecutionFailure {
+        error,
+        used_credits,
+    };
+
+    let (return_value, exit_code) = match result {
+        Ok(result) => (result, None),
+        Err(AbiError::Invalid(reason)) => return Err(failed(AwsError::InvalidGuestAbi(reason))),
+        Err(AbiError::Trap(e)) => match e.downcast::<WasiError>() {
+            // A guest calling `proc_exit` is a normal way for it to finish
+            Ok(WasiError::Exit(code)) => (Vec::new(), Some(code)),
+            Ok(e) => {
+                tracing::error!("Func call {e:#?}");
+                return Err(failed(AwsError::UnknownServerError));
+            }
+            Err(e) => {
+                tracing::error!("Func call {e:#?}");
+
+                if e.is::<Interrupted>() {
+                    return Err(failed(AwsError::ExecutionTimeout));
+                }
+
+                let e = match e.downcast::<HostCallError>() {
+                    Ok(HostCallError(reason)) => {
+                        return Err(failed(AwsError::InvalidGuestAbi(reason)))
+                    }
+                    Err(e) => e,
+                };
+
+                if e.is::<CreditsExhausted>() {
+                    return Err(failed(exhausted));
+                }
+
+                if let MeteringPoints::Exhausted = get_remaining_points(store, instance) {
+                    return Err(failed(exhausted));
+                }
+
+                // Guests abort when their memory can't grow, a guest
+                // trapping otherwise coped with the failed growth
+                let aborted = e.to_trap() == Some(TrapCode::UnreachableCodeReached);
+
+                return Err(failed(if aborted && usage.growth_failed() {
+                    AwsError::MemoryLimitExceeded
+                } else {
+                    AwsError::UnknownServerError
+                }));
+            }
+        },
+    };
+
+    Ok((return_value, exit_code, used_credits))
+}
+
+/// Instance kept alive between calls, with the host functions but
+/// without WASI
+pub struct LiveInstance {
+    pub store: Store,
+    pub usage: ResourceUsage,
+    pub instance: Instance,
+    host: HostContext,
+}
+
+impl LiveInstance {
+    pub fn new(
+        mut store: Store,
+        usage: ResourceUsage,
+        module: &Module,
+        kv: Option<KvStore>,
+    ) -> Result<Self, AwsError> {
+        let host = HostContext::new(&mut store, kv);
+        let imports = host.imports(&mut store);
+        let instance = instantiate(&mut store, &usage, module, &imports)?;
+
+        host.initialize(&mut store, &instance);
+
+        Ok(Self {
+            store,
+            usage,
+            instance,
+            host,
+        })
+    }
+}
+
+/// Runs the call on a live instance on the blocking pool, interrupting
+/// it once `timeout` has elapsed. Calls on the same instance wait for
+/// each other
+pub async fn execute_live(
+    live: Arc<Mutex<LiveInstance>>,
+    function: function::Model,
+    params: Vec<AbiValue>,
+    credits: u64,
+    timeout: Duration,
+) -> Result<Execution, ExecutionFailure> {
+    let interrupt = Interrupt::new(timeout);
+
+    tokio::task::spawn_blocking(move || {
+        let mut live = live.lock().map_err(|_| AwsError::UnknownServerError)?;
+        let LiveInstance {
+            store,
+            usage,
+            instance,
+            host,
+        } = &mut *live;
+
+        host.arm(store, interrupt);
+
+        let result = invoke(
+            store, instance, usage, &function, &params, credits, &interrupt,
+        );
+        let logs = host.logs(store);
+
+        result.map(|(return_value, _, used_credits)| Execution {
+            return_value,
+            wasi: None,
+            logs,
+            used_credits,
+        })
+    })
+    .await
+    .map_err(|e| {
+        tracing::error!("Func call panicked {e:#?}");
+        ExecutionFailure::from(AwsError::UnknownServerError)
+    })?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::limited_store;
+    use wasmer::Value;
+
+    const MODULE: &str = r#"
+        (module
+            (memory 1)
+            (func (export "spin") (loop $l (br $l)))
+            (func (export "oom")
+                (loop $l (br_if $l (i32.ne (memory.grow (i32.const 1)) (i32.const -1))))
+                (unreachable))
+            (func (export "coped")
+                (drop (memory.grow (i32.const 100000)))
+                (drop (i32.load (i32.const -4))))
+            (func (export "add") (param i32 i32) (result i32)
+                (i32.add (local.get 0) (local.get 1))))
+    "#;
+
+    fn invocation(name: &str, signature: &str, params: Vec<AbiValue>) -> Invocation {
+        let (store, usage) = limited_store(ResourceLimits::default());
+        let module = Module::new(&store, MODULE).unwrap();
+
+        Invocation {
+            store,
+            usage,
+            module,
+            function: function::Model {
+                id: 1,
+                module_id: 1,
+                name: name.to_string(),
+                signature: signature.to_string(),
+            },
+            params,
+            wasi: None,
+            kv: None,
+            credits: u64::MAX / 2,
+        }
+    }
+
+    #[test]
+    fn test_requested_timeout_is_capped() {
+        let limits = ExecutionLimits::default();
+
+        assert_eq!(limits.timeout(None), CALL_TIMEOUT_DEFAULT);
+        assert_eq!(limits.timeout(Some(5)), Duration::from_millis(5));
+        assert_eq!(limits.timeout(Some(u64::MAX)), CALL_TIMEOUT_MAX);
+    }
+
+    #[test]
+    fn test_uncapped_calls_reserve_the_default() {
+        let limits = ExecutionLimits::default();
+
+        assert_eq!(limits.reservation(Some(5), 1), 5);
+        assert_eq!(limits.reservation(None, 1), CALL_DEFAULT_RESERVATION);
+        assert_eq!(limits.reservation(None, 3), 3 * CALL_DEFAULT_RESERVATION);
+        assert_eq!(limits.reservation(None, usize::MAX), u64::MAX);
+    }
+
+    #[tokio::test]
+    async fn test_error_is_described() {
+        assert_eq!(
+            describe(AwsError::ExecutionTimeout).await,
+            (
+                StatusCode::GATEWAY_TIMEOUT,
+                "execution timed out".to_string()
+            )
+        );
+        assert_eq!(
+            describe(AwsError::InsufficientCredits).await,
+            (
+                StatusCode::PAYMENT_REQUIRED,
+                "insufficient credits".to_string()
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn test_call_is_interrupted_at_deadline() {
+        let failure = execute(invocation("spin", "->", vec![]), Duration::from_millis(50))
+            .await
+            .err()
+            .unwrap();
+
+        assert!(matches!(failure.error, AwsError::ExecutionTimeout));
+        assert!(failure.used_credits > 0);
+    }
+
+    #[tokio::test]
+    async fn test_call_within_deadline_succeeds() {
+        let params = vec![
+            AbiValue::Scalar(Value::I32(1)),
+            AbiValue::Scalar(Value::I32(2)),
+        ];
+        let execution = execute(
+            invocation("add", "i32,i32->i32", params),
+            CALL_TIMEOUT_DEFAULT,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(execution.return_value, vec![serde_json::json!(3)]);
+        assert!(execution.used_credits > 0);
+    }
+
+    #[tokio::test]
+    async fn test_growing_past_the_limit_fails() {
+        let failure = execute(invocation("oom", "->", vec![]), CALL_TIMEOUT_DEFAULT)
+            .await
+            .err()
+            .unwrap();
+
+        assert!(matches!(failure.error, AwsError::MemoryLimitExceeded));
+
+        let failure = execute(invocation("coped", "->", vec![]), CALL_TIMEOUT_DEFAULT)
+            .await
+            .err()
+            .unwrap();
+
+        assert!(!matches!(failure.error, AwsError::MemoryLimitExceeded));
+    }
+
+    #[tokio::test]
+    async fn test_batch_is_charged_once() {
+        use crate::testing;
+
+        let db = testing::database().await;
+        let wallet = testing::wallet(&db, "user", 1_000_000).await;
+
+        let module = testing::module(&db, wallet.user_id, MODULE).await;
+
+        let function = testing::function(&db, module.id, "add", "i32,i32->i32").await;
+
+        let executor = Executor {
+            db: Arc::new(db),
+            cache: ModuleCache::default(),
+            limits: ExecutionLimits::default(),
+        };
+
+        let mut items = (0..20)
+            .map(|i| vec![serde_json::json!(i), serde_json::json!(1)])
+            .collect::<Vec<_>>();
+        items.push(vec![serde_json::json!("nan"), serde_json::json!(1)]);
+
+        for parallel in [false, true] {
+            let (results, used_credits) = executor
+                .call_batch(BatchCall {
+                    wallet: wallet.clone(),
+                    module: module.clone(),
+                    function: function.clone(),
+                    items: items.clone(),
+                    parallel,
+                    timeout_ms: None,
+                    max_credits: None,
+                })
+                .await
+                .unwrap();
+
+            assert_eq!(results.len(), 21);
+            assert_eq!(
+                results[19].as_ref().unwrap().return_value,
+                vec![serde_json::json!(20)]
+            );
+            assert!(results[20].is_err());
+            assert!(used_credits > 0);
+        }
+
+        let credits = entities::wallet::Entity::find()
+            .one(&*executor.db)
+            .await
+            .unwrap()
+            .unwrap()
+            .credits;
+
+        assert!(credits < 1_000_000);
+    }
+
+    #[tokio::test]
+    async fn test_modules_are_loaded_before_reserving() {
+        use crate::testing;
+
+        let db = testing::database().await;
+        let wallet = testing::wallet(&db, "user", 0).await;
+
+        let mut module = testing::module(&db, wallet.user_id, MODULE).await;
+        module.wasm_code = b"not wasm".to_vec();
+        module.code_hash = "broken".to_string();
+
+        let function = testing::function(&db, module.id, "add", "i32,i32->i32").await;
+
+        let executor = Executor {
+            db: Arc::new(db),
+            cache: ModuleCache::default(),
+            limits: ExecutionLimits::default(),
+        };
+
+        // An empty wallet isn't what the call fails on
+        let failure = executor
+            .call(Call {
+                wallet,
+                module,
+                function,
+                params: vec![],
+                wasi: None,
+                timeout_ms: None,
+                max_credits: None,
+            })
+            .await
+            .err()
+            .unwrap();
+
+        assert!(matches!(failure.error, AwsError::InvalidWasmModule));
+    }
+}
@@ -1,7 +1,8 @@
 use aws_common::api::errors::AwsError;
 use axum::{
     async_trait,
-    extract::{FromRequestParts, Path},
+    extract::{FromRequestParts, Path, TypedHeader},
+    headers::{authorization::Bearer, Authorization},
     http::request::Parts,
     Extension,
 };
@@ -9,7 +10,15 @@ use axum::{
 use sea_orm::{ColumnTrait, EntityTrait, QueryFilter};
 use serde::Deserialize;
 
-use crate::{auth::jwt::AwsClaims, entities, utils::DbConn};
+use crate::{
+    auth::{
+        api_keys::{self, Scope},
+        jwt::ClaimsExtract,
+    },
+    constants::API_KEY_PREFIX,
+    entities,
+    utils::DbConn,
+};
 
 #[derive(Deserialize)]
 pub struct ModuleHashPathParam {
@@ -21,6 +30,7 @@ pub struct FuncNamePathParam {
     pub func_name: String,
 }
 
+/// A module of the caller, which their API key has to be allowed to invoke
 pub struct ModuleExtractor(pub entities::module::Model);
 
 pub struct ModuleFunctionExtract {
@@ -30,8 +40,38 @@ pub struct ModuleFunctionExtract {
 
 pub struct WalletExtract(pub entities::wallet::Model);
 
+/// The user making the request, through their JWT or one of their API
+/// keys
+#[derive(Clone, Debug)]
+pub struct Caller {
+    pub uid: i32,
+    /// Scopes of the API key, JWTs allowing everything
+    pub scopes: Option<Vec<Scope>>,
+}
+
+impl Caller {
+    pub fn require(&self, scope: Scope) -> Result<(), AwsError> {
+        match &self.scopes {
+            Some(scopes) if !scopes.contains(&scope) => Err(AwsError::Forbidden),
+            _ => Ok(()),
+        }
+    }
+
+    /// Fails for API keys, whatever their scopes, for what only the
+    /// user's own session may do
+    pub fn require_session(&self) -> Result<(), AwsError> {
+        match self.scopes {
+            Some(_) => Err(AwsError::Forbidden),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The user making the request, who has to be an admin
+pub struct AdminExtract(pub entities::user::Model);
+
 #[async_trait]
-impl<S> FromRequestParts<S> for WalletExtract
+impl<S> FromRequestParts<S> for AdminExtract
 where
     S: Send + Sync,
 {
@@ -39,20 +79,102 @@ where
 
     async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
         use axum::RequestPartsExt;
-        use entities::wallet as Wal;
 
         let Extension(DbConn(db)) = parts
             .extract::<Extension<DbConn>>()
             .await
             .map_err(|_| AwsError::UnknownServerError)?;
 
-        let user_claims = AwsClaims::from_request_parts(parts, state)
+        let ClaimsExtract(user_claims) = ClaimsExtract::from_request_parts(parts, state)
+            .await
+            .map_err(|_| AwsError::Unauthorized)?;
+
+        let user = entities::user::Entity::find_by_id(user_claims.uid)
+            .one(&*db)
+            .await
+            .map_err(|_| AwsError::UnknownServerError)?
+            .ok_or(AwsError::Unauthorized)?;
+
+        match user.admin {
+            true => Ok(Self(user)),
+            false => Err(AwsError::Forbidden),
+        }
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for Caller
+where
+    S: Send + Sync,
+{
+    type Rejection = AwsError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        use axum::RequestPartsExt;
+
+        // Extractors of the same request share the caller
+        if let Some(caller) = parts.extensions.get::<Caller>() {
+            return Ok(caller.clone());
+        }
+
+        let bearer = parts
+            .extract::<TypedHeader<Authorization<Bearer>>>()
             .await
             .map_err(|_| AwsError::Unauthorized)?;
 
+        let caller = if bearer.token().starts_with(API_KEY_PREFIX) {
+            let Extension(DbConn(db)) = parts
+                .extract::<Extension<DbConn>>()
+                .await
+                .map_err(|_| AwsError::UnknownServerError)?;
+
+            let key = api_keys::authenticate(&db, bearer.token())
+                .await
+                .map_err(|_| AwsError::UnknownServerError)?
+                .ok_or(AwsError::Unauthorized)?;
+
+            Caller {
+                uid: key.user_id,
+                scopes: Some(api_keys::scopes(&key)),
+            }
+        } else {
+            let ClaimsExtract(claims) = ClaimsExtract::from_request_parts(parts, state).await?;
+
+            Caller {
+                uid: claims.uid,
+                scopes: None,
+            }
+        };
+
+        parts.extensions.insert(caller.clone());
+
+        Ok(caller)
+    }
+}
+
+/// Wallet of the caller, whatever the scopes of their API key. Handlers
+/// check the scopes the request needs
+#[async_trait]
+impl<S> FromRequestParts<S> for WalletExtract
+where
+    S: Send + Sync,
+{
+    type Rejection = AwsError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        use axum::RequestPartsExt;
+        use entities::wallet as Wal;
+
+        let Extension(DbConn(db)) = parts
+            .extract::<Extension<DbConn>>()
+            .await
+            .map_err(|_| AwsError::UnknownServerError)?;
+
+        let caller = Caller::from_request_parts(parts, state).await?;
+
         Ok(Self(
             Wal::Entity::find()
-                .filter(Wal::Column::UserId.eq(user_claims.uid))
+                .filter(Wal::Column::UserId.eq(caller.uid))
                 .one(&*db)
                 .await
                 .map_err(|_| AwsError::UnknownServerError)?
@@ -118,13 +240,12 @@ where
                 .await
                 .map_err(|_| AwsError::Unauthorized)?;
 
-        let user_claims = AwsClaims::from_request_parts(parts, state)
-            .await
-            .map_err(|_| AwsError::Unauthorized)?;
+        let caller = Caller::from_request_parts(parts, state).await?;
+        caller.require(Scope::Invoke(id))?;
 
         Ok(ModuleExtractor(
             Endp::Entity::find()
-                .filter(Endp::Column::OwnerId.eq(user_claims.uid))
+                .filter(Endp::Column::OwnerId.eq(caller.uid))
                 .filter(Endp::Column::Id.eq(id))
                 .one(&*db)
                 .await
@@ -1,7 +1,10 @@
 use aws_common::api::errors::AwsError;
 use std::ops::{Deref, DerefMut};
 
-use crate::entities;
+use crate::{
+    abi::{AbiType, AbiValue, Signature},
+    entities,
+};
 
 #[derive(Debug)]
 pub struct Type(pub wasmer::Type);
@@ -38,96 +41,93 @@ impl DerefMut for Value {
 }
 
 pub trait WasmFFIConverter {
-    fn get_param_types(&self) -> Result<Vec<Type>, AwsError>;
-    fn get_ret_types(&self) -> Result<Vec<Type>, AwsError>;
-    fn to_wasm_params(&self, params: &[serde_json::Value]) -> Result<Vec<Value>, AwsError>;
+    fn get_param_types(&self) -> Result<Vec<AbiType>, AwsError>;
+    fn get_ret_types(&self) -> Result<Vec<AbiType>, AwsError>;
+    fn to_abi_params(&self, params: &[serde_json::Value]) -> Result<Vec<AbiValue>, AwsError>;
 }
 
 impl WasmFFIConverter for entities::function::Model {
-    fn get_param_types(&self) -> Result<Vec<Type>, AwsError> {
-        let (params, _) = self
-            .signature
-            .split_once("->")
-            .ok_or_else(|| AwsError::InvalidSignature(self.signature.to_string()))?;
-
-        params
-            .split(',')
-            .filter(|p| !p.is_empty())
-            .map(|x| x.try_into())
-            .collect::<Result<Vec<_>, _>>()
+    fn get_param_types(&self) -> Result<Vec<AbiType>, AwsError> {
+        Ok(self.signature.parse::<Signature>()?.params)
     }
 
-    fn get_ret_types(&self) -> Result<Vec<Type>, AwsError> {
-        let (_, ret) = self
-            .signature
-            .split_once("->")
-            .ok_or_else(|| AwsError::InvalidSignature(self.signature.to_string()))?;
-
-        ret.split(',')
-            .filter(|p| !p.is_empty())
-            .map(|x| x.try_into())
-            .collect::<Result<Vec<_>, _>>()
+    fn get_ret_types(&self) -> Result<Vec<AbiType>, AwsError> {
+        Ok(self.signature.parse::<Signature>()?.results)
     }
 
-    fn to_wasm_params(&self, params: &[serde_json::Value]) -> Result<Vec<Value>, AwsError> {
+    fn to_abi_params(&self, params: &[serde_json::Value]) -> Result<Vec<AbiValue>, AwsError> {
         let param_types = self.get_param_types()?;
 
-        let params: Vec<Value> = params
+        params
             .iter()
-            .zip(param_types.iter())
-            .map(|(v, t)| match v {
-                serde_json::Value::Number(inner) => {
-                    let v = if inner.is_i64() {
-                        wasmer::Value::from(
-                            i32::try_from(
-                                inner
-                                    .as_i64()
-                                    .ok_or_else(|| AwsError::UnimplementedWasmType)?,
-                            )
-                            .map_err(|_| AwsError::WasmTypeConversionError)?,
-                        )
-                    } else if inner.is_u64() {
-                        wasmer::Value::from(
-                            i32::try_from(
-                                inner
-                                    .as_u64()
-                                    .ok_or_else(|| AwsError::UnimplementedWasmType)?,
-                            )
-                            .map_err(|_| AwsError::WasmTypeConversionError)?,
-                        )
-                    } else if inner.is_f64() {
-                        wasmer::Value::from(
-                            inner
-                                .as_f64()
-                                .ok_or_else(|| AwsError::UnimplementedWasmType)?
-                                as f32,
-                        )
-                    } else {
-                        return Err(AwsError::UnimplementedWasmType);
-                    };
-
-                    // Check if the type we got is the type the function expects
-                    if v.ty() != t.0 {
-                        return Err(AwsError::WasmWrongParameterType((t.0, v.ty())));
-                    }
-
-                    Ok(Value(v))
-                }
-                _ => Err(AwsError::UnimplementedWasmType),
-            })
-            .collect::<Result<Vec<_>, _>>()?;
-
-        Ok(params)
+            .zip(param_types)
+            .map(|(v, t)| AbiValue::from_json(v, t))
+            .collect()
     }
 }
 
+/// Converts a JSON value to a wasm value of type `ty`.
+///
+/// Integers are range checked instead of truncated, floats accept
+/// integral JSON numbers and `i64` parameters may also be passed as
+/// decimal strings since JSON numbers can't represent the whole range
+/// without losing precision.
+pub(crate) fn to_wasm_value(
+    v: &serde_json::Value,
+    ty: wasmer::Type,
+) -> Result<wasmer::Value, AwsError> {
+    let number = match v {
+        serde_json::Value::Number(inner) => inner,
+        serde_json::Value::String(inner) if ty == wasmer::Type::I64 => {
+            return inner
+                .parse::<i64>()
+                .map(wasmer::Value::I64)
+                .map_err(|_| AwsError::WasmTypeConversionError);
+        }
+        _ => return Err(AwsError::UnimplementedWasmType),
+    };
+
+    let integer = if let Some(x) = number.as_i64() {
+        Some(x)
+    } else if let Some(x) = number.as_u64() {
+        Some(i64::try_from(x).map_err(|_| AwsError::WasmTypeConversionError)?)
+    } else {
+        None
+    };
+
+    let v = match (ty, integer) {
+        (wasmer::Type::I32, Some(x)) => {
+            wasmer::Value::I32(i32::try_from(x).map_err(|_| AwsError::WasmTypeConversionError)?)
+        }
+        (wasmer::Type::I64, Some(x)) => wasmer::Value::I64(x),
+        (wasmer::Type::I32 | wasmer::Type::I64, None) => {
+            return Err(AwsError::WasmWrongParameterType((ty, wasmer::Type::F64)))
+        }
+        (wasmer::Type::F32, _) => wasmer::Value::F32(
+            number
+                .as_f64()
+                .ok_or_else(|| AwsError::UnimplementedWasmType)? as f32,
+        ),
+        (wasmer::Type::F64, _) => wasmer::Value::F64(
+            number
+                .as_f64()
+                .ok_or_else(|| AwsError::UnimplementedWasmType)?,
+        ),
+        _ => return Err(AwsError::UnimplementedWasmType),
+    };
+
+    Ok(v)
+}
+
 impl TryInto<&str> for Type {
     type Error = AwsError;
 
     fn try_into(self) -> Result<&'static str, Self::Error> {
         match self.0 {
             wasmer::Type::I32 => Ok("i32"),
+            wasmer::Type::I64 => Ok("i64"),
             wasmer::Type::F32 => Ok("f32"),
+            wasmer::Type::F64 => Ok("f64"),
             _ => Err(AwsError::UnimplementedWasmType),
         }
     }
@@ -139,8 +139,99 @@ impl TryFrom<&str> for Type {
     fn try_from(value: &str) -> Result<Self, Self::Error> {
         match value {
             "i32" => Ok(Type(wasmer::Type::I32)),
+            "i64" => Ok(Type(wasmer::Type::I64)),
             "f32" => Ok(Type(wasmer::Type::F32)),
+            "f64" => Ok(Type(wasmer::Type::F64)),
             _ => Err(AwsError::UnimplementedWasmType),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn function(signature: &str) -> entities::function::Model {
+        entities::function::Model {
+            id: 0,
+            module_id: 0,
+            name: "f".to_string(),
+            signature: signature.to_string(),
+        }
+    }
+
+    fn params(signature: &str, params: serde_json::Value) -> Result<Vec<wasmer::Value>, AwsError> {
+        let params = params.as_array().unwrap();
+
+        function(signature)
+            .to_abi_params(params)?
+            .into_iter()
+            .map(|v| match v {
+                AbiValue::Scalar(v) => Ok(v),
+                AbiValue::Buffer(_) => Err(AwsError::UnimplementedWasmType),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_64_bit_params() {
+        let values = params(
+            "i32,i64,f32,f64->",
+            serde_json::json!([1, 9_007_199_254_740_993i64, 1.5, 0.1]),
+        )
+        .unwrap();
+
+        assert_eq!(
+            values,
+            vec![
+                wasmer::Value::I32(1),
+                wasmer::Value::I64(9_007_199_254_740_993),
+                wasmer::Value::F32(1.5),
+                wasmer::Value::F64(0.1),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_i64_string_params() {
+        let values = params("i64->", serde_json::json!(["-9223372036854775808"])).unwrap();
+
+        assert_eq!(values, vec![wasmer::Value::I64(i64::MIN)]);
+        assert!(params("i32->", serde_json::json!(["1"])).is_err());
+        assert!(params("i64->", serde_json::json!(["1.5"])).is_err());
+    }
+
+    #[test]
+    fn test_integer_range_checks() {
+        assert!(params("i32->", serde_json::json!([i64::from(i32::MAX) + 1])).is_err());
+        assert!(params("i64->", serde_json::json!([u64::MAX])).is_err());
+        assert!(params("i64->", serde_json::json!([1.5])).is_err());
+        assert_eq!(
+            params("f64->", serde_json::json!([2])).unwrap(),
+            vec![wasmer::Value::F64(2.0)]
+        );
+    }
+
+    #[test]
+    fn test_buffer_params() {
+        let values = function("string,bytes,json->")
+            .to_abi_params(&[
+                serde_json::json!("hi"),
+                serde_json::json!("AAE="),
+                serde_json::json!({"a": [1]}),
+            ])
+            .unwrap();
+
+        assert_eq!(
+            values,
+            vec![
+                AbiValue::Buffer(b"hi".to_vec()),
+                AbiValue::Buffer(vec![0, 1]),
+                AbiValue::Buffer(br#"{"a":[1]}"#.to_vec()),
+            ]
+        );
+        assert!(function("string->")
+            .to_abi_params(&[serde_json::json!(1)])
+            .is_err());
+    }
+}
@@ -0,0 +1,747 @@
+//! Functions the host provides to guests under the `aws_host_v1`
+//! namespace. The namespace is versioned so that changes to these
+//! signatures don't break modules built against a previous version.
+//!
+//! Buffers are passed as `i32` pointer and length pairs into the guest's
+//! exported `memory`. Functions that can fail return a negative
+//! `HostStatus`, misusing them (e.g. an out of bounds buffer) traps.
+//!
+//! - `log(level: i32, ptr: i32, len: i32)` logs a UTF-8 message, from
+//!   0 (trace) to 4 (error). Messages longer than `HOST_LOG_LIMIT` are
+//!   dropped unread
+//! - `monotonic_ns() -> i64` nanoseconds since the call started
+//! - `wall_clock_ms() -> i64` milliseconds since the Unix epoch
+//! - `random_bytes(ptr: i32, len: i32) -> i32` fills the buffer with
+//!   cryptographically secure random bytes
+//! - `kv_get(key_ptr: i32, key_len: i32) -> i64` returns the value as a
+//!   packed pointer and length like buffer results of the guest ABI,
+//!   allocated with the guest's `alloc`
+//! - `kv_set(key_ptr: i32, key_len: i32, value_ptr: i32, value_len: i32) -> i32`
+//! - `kv_delete(key_ptr: i32, key_len: i32) -> i32`
+//!
+//! Keys are UTF-8 strings, and the store is shared by every call to
+//! the same module. A module stores at most `KV_MODULE_KEYS_LIMIT` keys
+//! and `KV_MODULE_BYTES_LIMIT` bytes of values, `kv_set` returning
+//! `QuotaExceeded` past them. Each host call costs the `host_call` points of the
+//! pricing plus `host_call_byte` per byte it moves.
+//!
+//! Host functions called past the call's deadline trap, and queries of
+//! the key-value store give up at the deadline.
+
+use std::{
+    future::Future,
+    sync::Arc,
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use aws_common::api::responses::LogEntry;
+use sea_orm::{
+    sea_query::{Alias, Expr, Func, OnConflict, SimpleExpr},
+    ActiveValue, ColumnTrait, ConnectionTrait, DatabaseBackend, DatabaseConnection, DbErr,
+    EntityTrait, QueryFilter, QuerySelect, TransactionTrait,
+};
+use tokio::runtime::Handle;
+use wasmer::{
+    AsStoreMut, AsStoreRef, Function, FunctionEnv, FunctionEnvMut, Imports, Instance, Memory,
+    RuntimeError,
+};
+
+use crate::{
+    constants::{
+        HOST_LOG_LIMIT, HOST_RANDOM_LIMIT, KV_KEY_LIMIT, KV_MODULE_BYTES_LIMIT,
+        KV_MODULE_KEYS_LIMIT, KV_VALUE_LIMIT,
+    },
+    entities::module_kv,
+    interrupt::Interrupt,
+    pricing,
+    wasi::charge_points,
+};
+
+pub const HOST_NAMESPACE: &str = "aws_host_v1";
+
+/// Failures a guest is expected to handle, returned as negative values
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(i32)]
+pub enum HostStatus {
+    Ok = 0,
+    NotFound = -1,
+    /// The key or value is too large, or the key isn't UTF-8
+    Invalid = -2,
+    /// The store couldn't be reached
+    Unavailable = -3,
+    /// The module would store too many keys or bytes
+    QuotaExceeded = -4,
+}
+
+/// Trap raised when a guest misuses a host function
+#[derive(Debug)]
+pub struct HostCallError(pub String);
+
+impl std::fmt::Display for HostCallError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "host call error: {}", self.0)
+    }
+}
+
+impl std::error::Error for HostCallError {}
+
+fn misuse(reason: impl ToString) -> RuntimeError {
+    RuntimeError::user(Box::new(HostCallError(reason.to_string())))
+}
+
+/// Key-value store of a module, persisted in the database
+#[derive(Clone)]
+pub struct KvStore {
+    pub db: Arc<DatabaseConnection>,
+    pub module_id: i32,
+    /// Host functions run on the blocking pool, queries are run on the
+    /// async runtime through this handle
+    pub runtime: Handle,
+}
+
+impl KvStore {
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, DbErr> {
+        Ok(module_kv::Entity::find()
+            .filter(module_kv::Column::ModuleId.eq(self.module_id))
+            .filter(module_kv::Column::Key.eq(key))
+            .one(&*self.db)
+            .await?
+            .map(|entry| entry.value))
+    }
+
+    /// Keys and bytes of values the module stores, besides `key`
+    async fn usage(&self, db: &impl ConnectionTrait, key: &str) -> Result<(u64, u64), DbErr> {
+        // SUM of integers isn't an integer on every backend
+        let integer = match db.get_database_backend() {
+            DatabaseBackend::MySql => "SIGNED",
+            _ => "BIGINT",
+        };
+
+        let length = Func::cust(Alias::new("LENGTH")).arg(Expr::col(module_kv::Column::Value));
+
+        let usage = module_kv::Entity::find()
+            .filter(module_kv::Column::ModuleId.eq(self.module_id))
+            .filter(module_kv::Column::Key.ne(key))
+            .select_only()
+            .column_as(module_kv::Column::Id.count(), "keys")
+            .column_as(
+                SimpleExpr::from(Func::coalesce([
+                    Func::cast_as(Func::sum(length), Alias::new(integer)).into(),
+                    Expr::val(0).into(),
+                ])),
+                "bytes",
+            )
+            .into_tuple::<(i64, i64)>()
+            .one(db)
+            .await?
+            .unwrap_or_default();
+
+        Ok((usage.0 as u64, usage.1 as u64))
+    }
+
+    /// Stores the value, unless the module would go over its quota
+    async fn set(&self, key: &str, value: Vec<u8>) -> Result<HostStatus, DbErr> {
+        let value_len = value.len() as u64;
+        let entry = module_kv::ActiveModel {
+            module_id: ActiveValue::set(self.module_id),
+            key: ActiveValue::set(key.to_string()),
+            value: ActiveValue::set(value),
+            ..Default::default()
+        };
+
+        let txn = self.db.begin().await?;
+
+        let (keys, bytes) = self.usage(&txn, key).await?;
+
+        if keys >= KV_MODULE_KEYS_LIMIT || bytes + value_len > KV_MODULE_BYTES_LIMIT {
+            return Ok(HostStatus::QuotaExceeded);
+        }
+
+        module_kv::Entity::insert(entry)
+            .on_conflict(
+                OnConflict::columns([module_kv::Column::ModuleId, module_kv::Column::Key])
+                    .update_column(module_kv::Column::Value)
+                    .to_owned(),
+            )
+            .exec(&txn)
+            .await?;
+
+        txn.commit().await?;
+
+        Ok(HostStatus::Ok)
+    }
+
+    async fn delete(&self, key: &str) -> Result<bool, DbErr> {
+        let res = module_kv::Entity::delete_many()
+            .filter(module_kv::Column::ModuleId.eq(self.module_id))
+            .filter(module_kv::Column::Key.eq(key))
+            .exec(&*self.db)
+            .await?;
+
+        Ok(res.rows_affected > 0)
+    }
+}
+
+struct HostEnv {
+    /// Set once the module is instantiated
+    instance: Option<Instance>,
+    started: Instant,
+    logs: Vec<LogEntry>,
+    logged_bytes: usize,
+    kv: Option<KvStore>,
+    /// Deadline of the running call
+    interrupt: Option<Interrupt>,
+}
+
+impl HostEnv {
+    fn instance(&self) -> Result<Instance, RuntimeError> {
+        self.instance
+            .clone()
+            .ok_or_else(|| misuse("called before the module was instantiated"))
+    }
+
+    /// Runs a query of the key-value store until the call's deadline
+    fn block_on<T>(&self, kv: &KvStore, query: impl Future<Output = T>) -> Result<T, RuntimeError> {
+        match &self.interrupt {
+            Some(interrupt) => interrupt.block_on(&kv.runtime, query),
+            None => Ok(kv.runtime.block_on(query)),
+        }
+    }
+}
+
+fn memory(instance: &Instance) -> Result<Memory, RuntimeError> {
+    instance
+        .exports
+        .get_memory("memory")
+        .cloned()
+        .map_err(misuse)
+}
+
+fn read(
+    store: &impl AsStoreRef,
+    instance: &Instance,
+    ptr: i32,
+    len: i32,
+) -> Result<Vec<u8>, RuntimeError> {
+    let len = usize::try_from(len).map_err(misuse)?;
+    let memory = memory(instance)?;
+    let view = memory.view(store);
+
+    // Checked before allocating, the guest picks the length
+    if (ptr as u32 as u64).saturating_add(len as u64) > view.data_size() {
+        return Err(misuse("buffer out of bounds"));
+    }
+
+    let mut buf = vec![0; len];
+    view.read(ptr as u32 as u64, &mut buf).map_err(misuse)?;
+
+    Ok(buf)
+}
+
+fn write(
+    store: &impl AsStoreRef,
+    instance: &Instance,
+    ptr: i32,
+    buf: &[u8],
+) -> Result<(), RuntimeError> {
+    memory(instance)?
+        .view(store)
+        .write(ptr as u32 as u64, buf)
+        .map_err(misuse)
+}
+
+/// Charges a host call moving `bytes` bytes, returning its instance.
+/// Traps once the call is past its deadline
+fn charge(env: &mut FunctionEnvMut<HostEnv>, bytes: usize) -> Result<Instance, RuntimeError> {
+    if let Some(interrupt) = &env.data().interrupt {
+        interrupt.check()?;
+    }
+
+    let instance = env.data().instance()?;
+    let pricing = pricing::current();
+    let cost = pricing
+        .host_call
+        .saturating_add((bytes as u64).saturating_mul(pricing.host_call_byte));
+
+    charge_points(env, &instance, cost)?;
+
+    Ok(instance)
+}
+
+fn log(
+    mut env: FunctionEnvMut<HostEnv>,
+    level: i32,
+    ptr: i32,
+    len: i32,
+) -> Result<(), RuntimeError> {
+    let level = match level {
+        0 => "trace",
+        1 => "debug",
+        2 => "info",
+        3 => "warn",
+        4 => "error",
+        _ => return Err(misuse(format!("unknown log level {level}"))),
+    };
+
+    if len > HOST_LOG_LIMIT as i32 {
+        charge(&mut env, 0)?;
+        return Ok(());
+    }
+
+    let instance = charge(&mut env, len.max(0) as usize)?;
+    let message = String::from_utf8(read(&env, &instance, ptr, len)?).map_err(misuse)?;
+
+    tracing::debug!(target: "guest", level, "{message}");
+
+    let env = env.data_mut();
+
+    // Messages past the limit are dropped like WASI output is
+    if env.logged_bytes + message.len() <= HOST_LOG_LIMIT {
+        env.logged_bytes += message.len();
+        env.logs.push(LogEntry {
+            level: level.to_string(),
+            message,
+        });
+    }
+
+    Ok(())
+}
+
+fn monotonic_ns(mut env: FunctionEnvMut<HostEnv>) -> Result<i64, RuntimeError> {
+    charge(&mut env, 0)?;
+
+    Ok(env.data().started.elapsed().as_nanos() as i64)
+}
+
+fn wall_clock_ms(mut env: FunctionEnvMut<HostEnv>) -> Result<i64, RuntimeError> {
+    charge(&mut env, 0)?;
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|_| misuse("clock is before the epoch"))?;
+
+    Ok(now.as_millis() as i64)
+}
+
+fn random_bytes(mut env: FunctionEnvMut<HostEnv>, ptr: i32, len: i32) -> Result<i32, RuntimeError> {
+    let len = usize::try_from(len).map_err(misuse)?;
+
+    if len > HOST_RANDOM_LIMIT {
+        return Ok(HostStatus::Invalid as i32);
+    }
+
+    let instance = charge(&mut env, len)?;
+    let mut buf = vec![0; len];
+
+    OsRng
+        .try_fill_bytes(&mut buf)
+        .map_err(|e| misuse(format!("random bytes unavailable: {e}")))?;
+
+    write(&env, &instance, ptr, &buf)?;
+
+    Ok(HostStatus::Ok as i32)
+}
+
+/// Reads a key, or the status to return when it isn't a valid one
+fn read_key(
+    env: &FunctionEnvMut<HostEnv>,
+    instance: &Instance,
+    ptr: i32,
+    len: i32,
+) -> Result<Result<String, HostStatus>, RuntimeError> {
+    if len as u32 as usize > KV_KEY_LIMIT {
+        return Ok(Err(HostStatus::Invalid));
+    }
+
+    Ok(String::from_utf8(read(env, instance, ptr, len)?).map_err(|_| HostStatus::Invalid))
+}
+
+fn kv(env: &FunctionEnvMut<HostEnv>) -> Result<KvStore, HostStatus> {
+    env.data().kv.clone().ok_or(HostStatus::Unavailable)
+}
+
+fn unavailable(e: DbErr) -> HostStatus {
+    tracing::error!("Key-value store {e:#?}");
+    HostStatus::Unavailable
+}
+
+fn kv_get(mut env: FunctionEnvMut<HostEnv>, ptr: i32, len: i32) -> Result<i64, RuntimeError> {
+    let instance = charge(&mut env, len.max(0) as usize)?;
+
+    let value = match (read_key(&env, &instance, ptr, len)?, kv(&env)) {
+        (Ok(key), Ok(kv)) => env.data().block_on(&kv, kv.get(&key))?.map_err(unavailable),
+        (Err(status), _) | (_, Err(status)) => Err(status),
+    };
+
+    let value = match value {
+        Ok(Some(value)) => value,
+        Ok(None) => return Ok(HostStatus::NotFound as i64),
+        Err(status) => return Ok(status as i64),
+    };
+
+    charge_points(
+        &mut env,
+        &instance,
+        (value.len() as u64).saturating_mul(pricing::current().host_call_byte),
+    )?;
+
+    let value_len = i32::try_from(value.len()).map_err(misuse)?;
+    let value_ptr = instance
+        .exports
+        .get_typed_function::<i32, i32>(&env, "alloc")
+        .map_err(misuse)?
+        .call(&mut env, value_len)?;
+
+    write(&env, &instance, value_ptr, &value)?;
+
+    Ok(((value_ptr as u32 as i64) << 32) | value_len as u32 as i64)
+}
+
+fn kv_set(
+    mut env: FunctionEnvMut<HostEnv>,
+    key_ptr: i32,
+    key_len: i32,
+    value_ptr: i32,
+    value_len: i32,
+) -> Result<i32, RuntimeError> {
+    let instance = charge(
+        &mut env,
+        key_len.max(0) as usize + value_len.max(0) as usize,
+    )?;
+
+    if value_len as u32 as usize > KV_VALUE_LIMIT {
+        return Ok(HostStatus::Invalid as i32);
+    }
+
+    let key = match read_key(&env, &instance, key_ptr, key_len)? {
+        Ok(key) => key,
+        Err(status) => return Ok(status as i32),
+    };
+    let value = read(&env, &instance, value_ptr, value_len)?;
+
+    let status = match kv(&env) {
+        Ok(kv) => env
+            .data()
+            .block_on(&kv, kv.set(&key, value))?
+            .unwrap_or_else(unavailable),
+        Err(status) => status,
+    };
+
+    Ok(status as i32)
+}
+
+fn kv_delete(mut env: FunctionEnvMut<HostEnv>, ptr: i32, len: i32) -> Result<i32, RuntimeError> {
+    let instance = charge(&mut env, len.max(0) as usize)?;
+
+    let key = match read_key(&env, &instance, ptr, len)? {
+        Ok(key) => key,
+        Err(status) => return Ok(status as i32),
+    };
+
+    let deleted = match kv(&env) {
+        Ok(kv) => env
+            .data()
+            .block_on(&kv, kv.delete(&key))?
+            .map_err(unavailable),
+        Err(status) => Err(status),
+    };
+
+    let status = match deleted {
+        Ok(true) => HostStatus::Ok,
+        Ok(false) => HostStatus::NotFound,
+        Err(status) => status,
+    };
+
+    Ok(status as i32)
+}
+
+/// Host functions of a single call
+pub struct HostContext {
+    env: FunctionEnv<HostEnv>,
+}
+
+impl HostContext {
+    /// Without a `kv` store, key-value calls return `Unavailable`
+    pub fn new(store: &mut impl AsStoreMut, kv: Option<KvStore>) -> Self {
+        Self {
+            env: FunctionEnv::new(
+                store,
+                HostEnv {
+                    instance: None,
+                    started: Instant::now(),
+                    logs: Vec::new(),
+                    logged_bytes: 0,
+                    kv,
+                    interrupt: None,
+                },
+            ),
+        }
+    }
+
+    pub fn imports(&self, store: &mut impl AsStoreMut) -> Imports {
+        let env = &self.env;
+        let mut imports = Imports::new();
+
+        let functions = [
+            ("log", Function::new_typed_with_env(store, env, log)),
+            (
+                "monotonic_ns",
+                Function::new_typed_with_env(store, env, monotonic_ns),
+            ),
+            (
+                "wall_clock_ms",
+                Function::new_typed_with_env(store, env, wall_clock_ms),
+            ),
+            (
+                "random_bytes",
+                Function::new_typed_with_env(store, env, random_bytes),
+            ),
+            ("kv_get", Function::new_typed_with_env(store, env, kv_get)),
+            ("kv_set", Function::new_typed_with_env(store, env, kv_set)),
+            (
+                "kv_delete",
+                Function::new_typed_with_env(store, env, kv_delete),
+            ),
+        ];
+
+        for (name, function) in functions {
+            imports.define(HOST_NAMESPACE, name, function);
+        }
+
+        imports
+    }
+
+    pub fn initialize(&self, store: &mut impl AsStoreMut, instance: &Instance) {
+        self.env.as_mut(store).instance = Some(instance.clone());
+    }
+
+    /// Sets the deadline of the call about to run
+    pub fn arm(&self, store: &mut impl AsStoreMut, interrupt: Interrupt) {
+        self.env.as_mut(store).interrupt = Some(interrupt);
+    }
+
+    /// Takes the logs written so far, starting over the log limit
+    pub fn logs(&self, store: &mut impl AsStoreMut) -> Vec<LogEntry> {
+        let env = self.env.as_mut(store);
+        env.logged_bytes = 0;
+
+        std::mem::take(&mut env.logs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        constants::HOST_CALL_COST,
+        entities::function,
+        execution::{execute, Invocation},
+        limits::ResourceLimits,
+        testing,
+        utils::limited_store,
+    };
+    use aws_common::api::errors::AwsError;
+    use wasmer::Module;
+
+    const GUEST: &str = r#"
+        (module
+            (import "aws_host_v1" "log" (func $log (param i32 i32 i32)))
+            (import "aws_host_v1" "kv_set" (func $kv_set (param i32 i32 i32 i32) (result i32)))
+            (import "aws_host_v1" "kv_get" (func $kv_get (param i32 i32) (result i64)))
+            (import "aws_host_v1" "monotonic_ns" (func $monotonic_ns (result i64)))
+            (memory (export "memory") 1)
+            (data (i32.const 0) "hello")
+            (data (i32.const 16) "key")
+            (data (i32.const 32) "value")
+            (global $next (mut i32) (i32.const 1024))
+            (func (export "alloc") (param $len i32) (result i32)
+                (global.get $next)
+                (global.set $next (i32.add (global.get $next) (local.get $len))))
+            (func (export "dealloc") (param i32 i32))
+            (func (export "store") (result i32)
+                (call $log (i32.const 2) (i32.const 0) (i32.const 5))
+                (call $kv_set (i32.const 16) (i32.const 3) (i32.const 32) (i32.const 5)))
+            (func (export "load") (result i64)
+                (call $kv_get (i32.const 16) (i32.const 3)))
+            (func (export "bad_log")
+                (call $log (i32.const 9) (i32.const 0) (i32.const 5)))
+            (func (export "huge_log")
+                (call $log (i32.const 2) (i32.const 0) (i32.const 0x7fffffff)))
+            (func (export "oob_log")
+                (call $log (i32.const 2) (i32.const 65500) (i32.const 100)))
+            (func (export "poll")
+                (loop $l (drop (call $monotonic_ns)) (br $l))))
+    "#;
+
+    fn invocation(name: &str, signature: &str, kv: Option<KvStore>) -> Invocation {
+        let (store, usage) = limited_store(ResourceLimits::default());
+        let module = Module::new(&store, GUEST).unwrap();
+
+        Invocation {
+            store,
+            usage,
+            module,
+            function: function::Model {
+                id: 1,
+                module_id: 1,
+                name: name.to_string(),
+                signature: signature.to_string(),
+            },
+            params: vec![],
+            wasi: None,
+            kv,
+            credits: 1_000_000,
+        }
+    }
+
+    /// Store of a module of its own
+    async fn kv_store() -> KvStore {
+        let db = testing::database().await;
+        let wallet = testing::wallet(&db, "user", 0).await;
+        let module = testing::module(&db, wallet.user_id, "(module)").await;
+
+        KvStore {
+            db: Arc::new(db),
+            module_id: module.id,
+            runtime: Handle::current(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_logs_and_kv_are_persisted_across_calls() {
+        let kv = kv_store().await;
+
+        let stored = execute(
+            invocation("store", "->i32", Some(kv.clone())),
+            std::time::Duration::from_secs(10),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(stored.return_value, vec![serde_json::json!(0)]);
+        assert_eq!(
+            stored.logs,
+            vec![LogEntry {
+                level: "info".to_string(),
+                message: "hello".to_string(),
+            }]
+        );
+        assert!(stored.used_credits > 2 * HOST_CALL_COST);
+
+        let loaded = execute(
+            invocation("load", "->string", Some(kv)),
+            std::time::Duration::from_secs(10),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(loaded.return_value, vec![serde_json::json!("value")]);
+    }
+
+    #[tokio::test]
+    async fn test_kv_quota_is_enforced_per_module() {
+        let kv = kv_store().await;
+
+        let set = |key: String, len: usize| {
+            let kv = kv.clone();
+            async move { kv.set(&key, vec![0; len]).await }
+        };
+
+        let values = (KV_MODULE_BYTES_LIMIT as usize).div_ceil(KV_VALUE_LIMIT);
+        for i in 0..values {
+            assert_eq!(
+                set(format!("value-{i}"), KV_VALUE_LIMIT).await.unwrap(),
+                HostStatus::Ok
+            );
+        }
+        assert_eq!(
+            set("over".to_string(), 1).await.unwrap(),
+            HostStatus::QuotaExceeded
+        );
+        // Replacing a value only counts its new size
+        assert_eq!(set("value-0".to_string(), 1).await.unwrap(), HostStatus::Ok);
+
+        let keys = (values as u64..KV_MODULE_KEYS_LIMIT).map(|i| module_kv::ActiveModel {
+            module_id: ActiveValue::set(kv.module_id),
+            key: ActiveValue::set(format!("key-{i}")),
+            value: ActiveValue::set(Vec::new()),
+            ..Default::default()
+        });
+        module_kv::Entity::insert_many(keys)
+            .exec(&*kv.db)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            set("over".to_string(), 0).await.unwrap(),
+            HostStatus::QuotaExceeded
+        );
+        assert_eq!(set("key-999".to_string(), 1).await.unwrap(), HostStatus::Ok);
+    }
+
+    #[tokio::test]
+    async fn test_guest_looping_on_host_calls_is_interrupted() {
+        let started = Instant::now();
+
+        let failure = execute(
+            Invocation {
+                credits: u64::MAX / 2,
+                ..invocation("poll", "->", None)
+            },
+            std::time::Duration::from_millis(50),
+        )
+        .await
+        .err()
+        .unwrap();
+
+        assert!(matches!(failure.error, AwsError::ExecutionTimeout));
+        assert!(failure.used_credits > 0);
+        assert!(started.elapsed() < std::time::Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn test_misuse_traps_and_missing_store_is_unavailable() {
+        let failure = execute(
+            invocation("bad_log", "->", None),
+            std::time::Duration::from_secs(10),
+        )
+        .await
+        .err()
+        .unwrap();
+
+        assert!(matches!(failure.error, AwsError::InvalidGuestAbi(_)));
+
+        let failure = execute(
+            invocation("oob_log", "->", None),
+            std::time::Duration::from_secs(10),
+        )
+        .await
+        .err()
+        .unwrap();
+
+        assert!(matches!(failure.error, AwsError::InvalidGuestAbi(_)));
+
+        // Dropped without reading the 2 GiB the guest claims to log
+        let logged = execute(
+            invocation("huge_log", "->", None),
+            std::time::Duration::from_secs(10),
+        )
+        .await
+        .unwrap();
+
+        assert!(logged.logs.is_empty());
+
+        let stored = execute(
+            invocation("store", "->i32", None),
+            std::time::Duration::from_secs(10),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            stored.return_value,
+            vec![serde_json::json!(HostStatus::Unavailable as i32)]
+        );
+    }
+}
@@ -0,0 +1,102 @@
+//! Stopping a running guest once its call is past its deadline.
+//!
+//! wasmer 3.1 can't stop a guest from another thread, so calls are
+//! stopped where the thread running them gets control back. The points
+//! a call is metered with are capped by how much the guest can run
+//! before the deadline, and host functions check the deadline on entry,
+//! trapping with `Interrupted` once it passed. Host functions waiting on
+//! the database give up at the deadline too.
+
+use std::{
+    future::Future,
+    time::{Duration, Instant},
+};
+
+use wasmer::RuntimeError;
+
+use crate::constants::CALL_POINTS_PER_MS;
+
+/// Trap raised in a host function called past the deadline
+#[derive(Debug)]
+pub struct Interrupted;
+
+impl std::fmt::Display for Interrupted {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "call interrupted at its deadline")
+    }
+}
+
+impl std::error::Error for Interrupted {}
+
+/// Deadline of a call
+#[derive(Clone, Copy, Debug)]
+pub struct Interrupt {
+    deadline: Instant,
+}
+
+impl Interrupt {
+    pub fn new(timeout: Duration) -> Self {
+        Self {
+            deadline: Instant::now() + timeout,
+        }
+    }
+
+    pub fn is_due(&self) -> bool {
+        Instant::now() >= self.deadline
+    }
+
+    /// Traps once the call is past its deadline
+    pub fn check(&self) -> Result<(), RuntimeError> {
+        match self.is_due() {
+            true => Err(RuntimeError::user(Box::new(Interrupted))),
+            false => Ok(()),
+        }
+    }
+
+    /// Points the guest can use until the deadline
+    pub fn points(&self) -> u64 {
+        let left = self.deadline.saturating_duration_since(Instant::now());
+
+        u64::try_from(left.as_millis())
+            .unwrap_or(u64::MAX)
+            .saturating_mul(CALL_POINTS_PER_MS)
+    }
+
+    /// Runs `future` on `runtime` from the thread running the call,
+    /// trapping if it isn't done by the deadline
+    pub fn block_on<T>(
+        &self,
+        runtime: &tokio::runtime::Handle,
+        future: impl Future<Output = T>,
+    ) -> Result<T, RuntimeError> {
+        runtime
+            .block_on(tokio::time::timeout_at(self.deadline.into(), future))
+            .map_err(|_| RuntimeError::user(Box::new(Interrupted)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_waits_end_at_the_deadline() {
+        let interrupt = Interrupt::new(Duration::from_millis(20));
+        assert!(interrupt.check().is_ok());
+        assert!(interrupt.points() > 0);
+
+        let runtime = tokio::runtime::Handle::current();
+        let started = Instant::now();
+
+        let waited = tokio::task::spawn_blocking(move || {
+            interrupt.block_on(&runtime, tokio::time::sleep(Duration::from_secs(10)))
+        })
+        .await
+        .unwrap();
+
+        assert!(waited.unwrap_err().downcast::<Interrupted>().is_ok());
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert!(interrupt.check().is_err());
+        assert_eq!(interrupt.points(), 0);
+    }
+}
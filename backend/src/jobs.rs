@@ -0,0 +1,374 @@
+//! Function calls run in the background. Jobs are queued in the `job`
+//! table and claimed by workers polling it, so they survive restarts.
+//! Jobs failing because of the infrastructure are retried with an
+//! exponential backoff, and dead-lettered once they ran out of attempts.
+//! Failures of the guest itself would fail the same way again, so they
+//! fail the job right away.
+
+use std::time::Duration;
+
+use aws_common::api::{errors::AwsError, requests::WasiOptions, responses::JobResponse};
+use sea_orm::{
+    ActiveModelTrait, ActiveValue, ColumnTrait, DatabaseConnection, DbErr, EntityTrait,
+    QueryFilter, QueryOrder,
+};
+use sea_query::Expr;
+
+use crate::{
+    constants::{JOB_MAX_ATTEMPTS, JOB_POLL_INTERVAL, JOB_RETRY_BACKOFF},
+    entities::job,
+    execution::{describe, ExecutionFailure, Executor, StoredCall},
+    metrics::JOBS_DEAD_LETTERED,
+    utils::now_ms,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum JobStatus {
+    Queued,
+    Running,
+    Succeeded,
+    /// Failed with an error retrying can't fix
+    Failed,
+    /// Failed every one of its attempts
+    Dead,
+}
+
+impl JobStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            JobStatus::Queued => "queued",
+            JobStatus::Running => "running",
+            JobStatus::Succeeded => "succeeded",
+            JobStatus::Failed => "failed",
+            JobStatus::Dead => "dead",
+        }
+    }
+}
+
+/// Call to run in the background on behalf of `owner_id`
+pub struct NewJob {
+    pub owner_id: i32,
+    pub function_id: i32,
+    pub params: Vec<serde_json::Value>,
+    pub wasi: Option<WasiOptions>,
+    pub timeout_ms: Option<u64>,
+    pub max_credits: Option<u64>,
+}
+
+pub async fn enqueue(db: &DatabaseConnection, job: NewJob) -> Result<i32, AwsError> {
+    let encode = |v: serde_json::Result<String>| v.map_err(|_| AwsError::UnknownServerError);
+    let now = now_ms();
+
+    let job = job::ActiveModel {
+        owner_id: ActiveValue::set(job.owner_id),
+        function_id: ActiveValue::set(job.function_id),
+        params: ActiveValue::set(encode(serde_json::to_string(&job.params))?),
+        wasi: ActiveValue::set(
+            job.wasi
+                .map(|wasi| encode(serde_json::to_string(&wasi)))
+                .transpose()?,
+        ),
+        timeout_ms: ActiveValue::set(job.timeout_ms.map(|ms| ms.min(i64::MAX as u64) as i64)),
+        max_credits: ActiveValue::set(
+            job.max_credits
+                .map(|credits| credits.min(i64::MAX as u64) as i64),
+        ),
+        status: ActiveValue::set(JobStatus::Queued.as_str().to_string()),
+        attempts: ActiveValue::set(0),
+        used_credits: ActiveValue::set(0),
+        run_at: ActiveValue::set(now),
+        created_at: ActiveValue::set(now),
+        ..Default::default()
+    }
+    .insert(db)
+    .await
+    .map_err(|e| {
+        tracing::error!("Failed to enqueue job {e:#?}");
+        AwsError::UnknownServerError
+    })?;
+
+    Ok(job.id)
+}
+
+impl From<job::Model> for JobResponse {
+    fn from(job: job::Model) -> Self {
+        Self {
+            id: job.id,
+            status: job.status,
+            attempts: job.attempts,
+            result: job.result.and_then(|r| serde_json::from_str(&r).ok()),
+            error: job.error,
+            used_credits: job.used_credits as u64,
+        }
+    }
+}
+
+/// Pool of workers running queued jobs with the executor
+#[derive(Clone)]
+pub struct JobWorker {
+    pub executor: Executor,
+    pub workers: usize,
+}
+
+impl JobWorker {
+    /// Requeues the jobs interrupted by the last shutdown and spawns
+    /// the workers. Running several backends against the same database
+    /// would requeue jobs still running on the others
+    pub async fn start(self) -> Result<(), DbErr> {
+        job::Entity::update_many()
+            .col_expr(job::Column::Status, Expr::value(JobStatus::Queued.as_str()))
+            .filter(job::Column::Status.eq(JobStatus::Running.as_str()))
+            .exec(&*self.executor.db)
+            .await?;
+
+        for _ in 0..self.workers {
+            tokio::spawn(self.clone().work());
+        }
+
+        Ok(())
+    }
+
+    async fn work(self) {
+        loop {
+            match self.run_next().await {
+                Ok(true) => continue,
+                Ok(false) => (),
+                Err(e) => tracing::error!("Job worker {e:#?}"),
+            }
+
+            tokio::time::sleep(JOB_POLL_INTERVAL).await;
+        }
+    }
+
+    /// Runs the next due job, if any
+    pub async fn run_next(&self) -> Result<bool, DbErr> {
+        let Some(job) = self.claim().await? else {
+            return Ok(false);
+        };
+
+        let id = job.id;
+        let attempts = job.attempts;
+        let charged = job.used_credits;
+        let mut update = job::ActiveModel {
+            id: ActiveValue::unchanged(id),
+            ..Default::default()
+        };
+
+        match self.run(job).await {
+            Ok((result, used_credits)) => {
+                update.status = ActiveValue::set(JobStatus::Succeeded.as_str().to_string());
+                update.result = ActiveValue::set(Some(result.to_string()));
+                update.error = ActiveValue::set(None);
+                update.used_credits = ActiveValue::set(charged + used_credits as i64);
+            }
+            Err(ExecutionFailure {
+                error,
+                used_credits,
+            }) => {
+                let retryable = is_transient(&error);
+                let (_, message) = describe(error).await;
+
+                tracing::info!("Job {id} attempt {attempts} failed: {message}");
+
+                let status = match retry_delay(attempts, retryable) {
+                    Some(delay) => {
+                        update.run_at = ActiveValue::set(now_ms() + delay.as_millis() as i64);
+                        JobStatus::Queued
+                    }
+                    None if retryable => {
+                        JOBS_DEAD_LETTERED.inc();
+                        JobStatus::Dead
+                    }
+                    None => JobStatus::Failed,
+                };
+
+                update.status = ActiveValue::set(status.as_str().to_string());
+                update.error = ActiveValue::set(Some(message));
+                update.used_credits = ActiveValue::set(charged + used_credits as i64);
+            }
+        }
+
+        update.update(&*self.executor.db).await?;
+
+        Ok(true)
+    }
+
+    /// Marks the next due job as running. Workers race for jobs, so
+    /// the job is only claimed if it is still queued
+    async fn claim(&self) -> Result<Option<job::Model>, DbErr> {
+        let db = &*self.executor.db;
+
+        let Some(mut job) = job::Entity::find()
+            .filter(job::Column::Status.eq(JobStatus::Queued.as_str()))
+            .filter(job::Column::RunAt.lte(now_ms()))
+            .order_by_asc(job::Column::RunAt)
+            .one(db)
+            .await?
+        else {
+            return Ok(None);
+        };
+
+        let res = job::Entity::update_many()
+            .col_expr(
+                job::Column::Status,
+                Expr::value(JobStatus::Running.as_str()),
+            )
+            .col_expr(
+                job::Column::Attempts,
+                Expr::col(job::Column::Attempts).add(1),
+            )
+            .filter(job::Column::Id.eq(job.id))
+            .filter(job::Column::Status.eq(JobStatus::Queued.as_str()))
+            .exec(db)
+            .await?;
+
+        if res.rows_affected != 1 {
+            return Ok(None);
+        }
+
+        job.status = JobStatus::Running.as_str().to_string();
+        job.attempts += 1;
+
+        Ok(Some(job))
+    }
+
+    async fn run(&self, job: job::Model) -> Result<(serde_json::Value, u64), ExecutionFailure> {
+        self.executor
+            .call_stored(StoredCall {
+                owner_id: job.owner_id,
+                function_id: job.function_id,
+                params: job.params,
+                wasi: job.wasi,
+                timeout_ms: job.timeout_ms,
+                max_credits: job.max_credits,
+            })
+            .await
+    }
+}
+
+/// Delay before the next attempt of a job that failed its `attempts`th
+/// attempt, `None` when it shouldn't be retried
+fn retry_delay(attempts: i32, retryable: bool) -> Option<Duration> {
+    if !retryable || attempts >= JOB_MAX_ATTEMPTS {
+        return None;
+    }
+
+    Some(JOB_RETRY_BACKOFF * 2u32.saturating_pow(attempts.max(1) as u32 - 1))
+}
+
+/// Whether the call failed because of the infrastructure rather than
+/// the guest, which would time out, run out of memory or misuse the ABI
+/// the same way on every attempt
+fn is_transient(error: &AwsError) -> bool {
+    matches!(error, AwsError::UnknownServerError)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cache::ModuleCache, execution::ExecutionLimits, testing};
+    use std::sync::Arc;
+
+    const MODULE: &str = r#"
+        (module
+            (func (export "add") (param i32 i32) (result i32)
+                (i32.add (local.get 0) (local.get 1)))
+            (func (export "spin")
+                (loop $forever (br $forever))))
+    "#;
+
+    #[test]
+    fn test_server_errors_are_retried_then_dead_lettered() {
+        assert_eq!(retry_delay(1, true), Some(JOB_RETRY_BACKOFF));
+        assert_eq!(retry_delay(2, true), Some(JOB_RETRY_BACKOFF * 2));
+        assert_eq!(retry_delay(JOB_MAX_ATTEMPTS, true), None);
+        assert_eq!(retry_delay(1, false), None);
+
+        assert!(is_transient(&AwsError::UnknownServerError));
+        assert!(!is_transient(&AwsError::ExecutionTimeout));
+        assert!(!is_transient(&AwsError::MemoryLimitExceeded));
+        assert!(!is_transient(&AwsError::InvalidGuestAbi(String::new())));
+    }
+
+    #[tokio::test]
+    async fn test_queued_job_is_run_once() {
+        let db = testing::database().await;
+        let user_id = testing::wallet(&db, "user", 1_000_000_000_000)
+            .await
+            .user_id;
+
+        let module = testing::module(&db, user_id, MODULE).await;
+
+        let function = testing::function(&db, module.id, "add", "i32,i32->i32").await;
+
+        let db = Arc::new(db);
+        let id = enqueue(
+            &db,
+            NewJob {
+                owner_id: user_id,
+                function_id: function.id,
+                params: vec![serde_json::json!(1), serde_json::json!(2)],
+                wasi: None,
+                timeout_ms: None,
+                max_credits: None,
+            },
+        )
+        .await
+        .unwrap();
+
+        let worker = JobWorker {
+            executor: Executor {
+                db: db.clone(),
+                cache: ModuleCache::default(),
+                limits: ExecutionLimits::default(),
+            },
+            workers: 1,
+        };
+
+        assert!(worker.run_next().await.unwrap());
+        assert!(!worker.run_next().await.unwrap());
+
+        let job = JobResponse::from(
+            job::Entity::find_by_id(id)
+                .one(&*db)
+                .await
+                .unwrap()
+                .unwrap(),
+        );
+
+        assert_eq!(job.status, "succeeded");
+        assert_eq!(job.attempts, 1);
+        assert_eq!(job.result, Some(serde_json::json!({ "return_value": [3] })));
+        assert!(job.used_credits > 0);
+
+        let spin = testing::function(&db, module.id, "spin", "->").await;
+
+        let id = enqueue(
+            &db,
+            NewJob {
+                owner_id: user_id,
+                function_id: spin.id,
+                params: vec![],
+                wasi: None,
+                timeout_ms: Some(50),
+                max_credits: None,
+            },
+        )
+        .await
+        .unwrap();
+
+        assert!(worker.run_next().await.unwrap());
+
+        // Timing out again is certain, so the job isn't retried, but what
+        // it used is recorded
+        let job = job::Entity::find_by_id(id)
+            .one(&*db)
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(job.status, "failed");
+        assert_eq!(job.attempts, 1);
+        assert!(job.used_credits > 0);
+    }
+}
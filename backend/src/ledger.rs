@@ -0,0 +1,401 @@
+//! Every movement of credits is appended to the ledger, so the balance
+//! of a wallet can be told from its history. Reservations aren't
+//! recorded: what a call used is, once it is settled.
+
+use std::time::Instant;
+
+use aws_common::api::{errors::AwsError, requests::LedgerQuery, responses::LedgerEntryResponse};
+use sea_orm::{
+    ActiveValue, ColumnTrait, ConnectionTrait, DatabaseBackend, DatabaseConnection, DbErr,
+    EntityTrait, QueryFilter, QueryOrder, QuerySelect, Select,
+};
+use sea_query::{Alias, Expr, Func, SimpleExpr};
+
+use crate::{
+    constants::{LEDGER_PAGE_DEFAULT, LEDGER_PAGE_MAX},
+    credits,
+    entities::{function, ledger_entry, wallet},
+    utils::now_ms,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EntryKind {
+    Grant,
+    Revoke,
+    TopUp,
+    Refund,
+    TransferIn,
+    TransferOut,
+    Debit,
+}
+
+impl EntryKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EntryKind::Grant => "grant",
+            EntryKind::Revoke => "revoke",
+            EntryKind::TopUp => "top_up",
+            EntryKind::Refund => "refund",
+            EntryKind::TransferIn => "transfer_in",
+            EntryKind::TransferOut => "transfer_out",
+            EntryKind::Debit => "debit",
+        }
+    }
+}
+
+impl std::str::FromStr for EntryKind {
+    type Err = AwsError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        [
+            EntryKind::Grant,
+            EntryKind::Revoke,
+            EntryKind::TopUp,
+            EntryKind::Refund,
+            EntryKind::TransferIn,
+            EntryKind::TransferOut,
+            EntryKind::Debit,
+        ]
+        .into_iter()
+        .find(|kind| kind.as_str() == s)
+        .ok_or_else(|| AwsError::InvalidLedgerQuery(format!("unknown kind {s}")))
+    }
+}
+
+/// Credits used by calls of a function
+#[derive(Clone, Debug)]
+pub struct Debit {
+    pub module_id: Option<i32>,
+    pub function: Option<String>,
+    pub credits: u64,
+    pub duration_ms: u64,
+}
+
+impl Debit {
+    pub fn new(function: &function::Model, credits: u64, started: Instant) -> Self {
+        Self {
+            module_id: Some(function.module_id),
+            function: Some(function.name.clone()),
+            credits,
+            duration_ms: started.elapsed().as_millis() as u64,
+        }
+    }
+}
+
+impl From<ledger_entry::Model> for LedgerEntryResponse {
+    fn from(entry: ledger_entry::Model) -> Self {
+        Self {
+            id: entry.id,
+            kind: entry.kind,
+            amount: entry.amount,
+            module_id: entry.module_id,
+            function: entry.function,
+            duration_ms: entry.duration_ms,
+            created_at: entry.created_at,
+            counterparty_id: entry.counterparty_id,
+        }
+    }
+}
+
+/// Records `amount` credits given to the user, taken away when negative
+pub async fn record(
+    txn: &impl ConnectionTrait,
+    user_id: i32,
+    kind: EntryKind,
+    amount: i64,
+    counterparty_id: Option<i32>,
+) -> Result<(), DbErr> {
+    ledger_entry::Entity::insert(ledger_entry::ActiveModel {
+        user_id: ActiveValue::set(user_id),
+        kind: ActiveValue::set(kind.as_str().to_string()),
+        amount: ActiveValue::set(amount),
+        created_at: ActiveValue::set(now_ms()),
+        counterparty_id: ActiveValue::set(counterparty_id),
+        ..Default::default()
+    })
+    .exec(txn)
+    .await?;
+
+    Ok(())
+}
+
+/// Records the credits the user spent, leaving out debits of nothing
+pub async fn record_debits(
+    txn: &impl ConnectionTrait,
+    user_id: i32,
+    debits: &[Debit],
+) -> Result<(), DbErr> {
+    let created_at = now_ms();
+
+    let entries = debits
+        .iter()
+        .filter(|debit| debit.credits > 0)
+        .map(|debit| ledger_entry::ActiveModel {
+            user_id: ActiveValue::set(user_id),
+            kind: ActiveValue::set(EntryKind::Debit.as_str().to_string()),
+            amount: ActiveValue::set(-(debit.credits as i64)),
+            module_id: ActiveValue::set(debit.module_id),
+            function: ActiveValue::set(debit.function.clone()),
+            duration_ms: ActiveValue::set(Some(debit.duration_ms as i64)),
+            created_at: ActiveValue::set(created_at),
+            ..Default::default()
+        })
+        .collect::<Vec<_>>();
+
+    if entries.is_empty() {
+        return Ok(());
+    }
+
+    ledger_entry::Entity::insert_many(entries).exec(txn).await?;
+
+    Ok(())
+}
+
+/// Sum of the amounts of the entries `select` finds
+async fn sum(
+    db: &impl ConnectionTrait,
+    select: Select<ledger_entry::Entity>,
+) -> Result<i64, DbErr> {
+    // SUM of integers isn't an integer on every backend
+    let integer = match db.get_database_backend() {
+        DatabaseBackend::MySql => "SIGNED",
+        _ => "BIGINT",
+    };
+
+    let sum = select
+        .select_only()
+        .column_as(
+            SimpleExpr::from(Func::coalesce([
+                Func::cast_as(
+                    Func::sum(Expr::col(ledger_entry::Column::Amount)),
+                    Alias::new(integer),
+                )
+                .into(),
+                Expr::val(0).into(),
+            ])),
+            "sum",
+        )
+        .into_tuple::<i64>()
+        .one(db)
+        .await?;
+
+    Ok(sum.unwrap_or_default())
+}
+
+/// Sum of every entry of the user's ledger
+pub async fn balance(db: &impl ConnectionTrait, user_id: i32) -> Result<i64, DbErr> {
+    let select = ledger_entry::Entity::find().filter(ledger_entry::Column::UserId.eq(user_id));
+
+    sum(db, select).await
+}
+
+/// Credits the user spent on calls since `since`, only on calls of
+/// `module_id` if any
+pub async fn spent(
+    db: &impl ConnectionTrait,
+    user_id: i32,
+    module_id: Option<i32>,
+    since: i64,
+) -> Result<u64, DbErr> {
+    let mut select = ledger_entry::Entity::find()
+        .filter(ledger_entry::Column::UserId.eq(user_id))
+        .filter(ledger_entry::Column::Kind.eq(EntryKind::Debit.as_str()))
+        .filter(ledger_entry::Column::CreatedAt.gte(since));
+
+    if let Some(module_id) = module_id {
+        select = select.filter(ledger_entry::Column::ModuleId.eq(module_id));
+    }
+
+    Ok(sum(db, select).await?.unsigned_abs())
+}
+
+/// Sets every wallet to what its ledger says it holds, minus what is
+/// reserved, fixing wallets that drifted from it. Meant to run before
+/// any call does
+pub async fn reconcile(db: &DatabaseConnection) -> Result<(), DbErr> {
+    for wallet in wallet::Entity::find().all(db).await? {
+        let reserved = credits::reserved(db, wallet.user_id).await? as i64;
+
+        let expected = balance(db, wallet.user_id).await? - reserved;
+
+        if expected == wallet.credits {
+            continue;
+        }
+
+        tracing::warn!(
+            "Wallet of user {} holds {} credits, the ledger says {expected}",
+            wallet.user_id,
+            wallet.credits
+        );
+
+        wallet::Entity::update(wallet::ActiveModel {
+            id: ActiveValue::unchanged(wallet.id),
+            credits: ActiveValue::set(expected),
+            ..Default::default()
+        })
+        .exec(db)
+        .await?;
+    }
+
+    Ok(())
+}
+
+/// Page of the user's entries matching `query`, newest first
+pub async fn entries(
+    db: &DatabaseConnection,
+    user_id: i32,
+    query: &LedgerQuery,
+) -> Result<Vec<ledger_entry::Model>, AwsError> {
+    let limit = query.limit.unwrap_or(LEDGER_PAGE_DEFAULT);
+
+    if limit == 0 || limit > LEDGER_PAGE_MAX {
+        return Err(AwsError::InvalidLedgerQuery(format!(
+            "limit must be 1 to {LEDGER_PAGE_MAX}"
+        )));
+    }
+
+    let mut select = ledger_entry::Entity::find().filter(ledger_entry::Column::UserId.eq(user_id));
+
+    if let Some(kind) = &query.kind {
+        let kind = kind.parse::<EntryKind>()?;
+        select = select.filter(ledger_entry::Column::Kind.eq(kind.as_str()));
+    }
+
+    if let Some(module_id) = query.module_id {
+        select = select.filter(ledger_entry::Column::ModuleId.eq(module_id));
+    }
+
+    if let Some(since) = query.since {
+        select = select.filter(ledger_entry::Column::CreatedAt.gte(since));
+    }
+
+    if let Some(until) = query.until {
+        select = select.filter(ledger_entry::Column::CreatedAt.lt(until));
+    }
+
+    if let Some(before) = query.before {
+        select = select.filter(ledger_entry::Column::Id.lt(before));
+    }
+
+    select
+        .order_by_desc(ledger_entry::Column::Id)
+        .limit(limit)
+        .all(db)
+        .await
+        .map_err(|_| AwsError::UnknownServerError)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        credits::{reserve, settle},
+        testing,
+    };
+
+    /// User whose wallet was granted `credits`, as recorded in the ledger
+    async fn user(db: &DatabaseConnection, credits: u64) -> i32 {
+        let user_id = testing::wallet(db, "user", credits as i64).await.user_id;
+
+        record(db, user_id, EntryKind::Grant, credits as i64, None)
+            .await
+            .unwrap();
+
+        user_id
+    }
+
+    fn debit(module_id: i32, credits: u64) -> Debit {
+        Debit {
+            module_id: Some(module_id),
+            function: Some("f".to_string()),
+            credits,
+            duration_ms: 1,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_settled_calls_are_recorded() {
+        let db = testing::database().await;
+        let user_id = user(&db, 1_000).await;
+
+        for (module_id, credits) in [(1, 100), (2, 0), (2, 50), (1, 25)] {
+            let reservation = reserve(&db, user_id, Some(200)).await.unwrap();
+            settle(&db, reservation, &[debit(module_id, credits)])
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(balance(&db, user_id).await.unwrap(), 825);
+
+        // The debit of nothing isn't recorded
+        let all = entries(&db, user_id, &LedgerQuery::default())
+            .await
+            .unwrap();
+        assert_eq!(all.len(), 4);
+        assert_eq!(all[0].amount, -25);
+        assert_eq!(all[3].kind, "grant");
+
+        let query = LedgerQuery {
+            kind: Some("debit".to_string()),
+            limit: Some(2),
+            ..Default::default()
+        };
+        let page = entries(&db, user_id, &query).await.unwrap();
+        assert_eq!(
+            page.iter().map(|e| e.amount).collect::<Vec<_>>(),
+            vec![-25, -50]
+        );
+
+        let query = LedgerQuery {
+            before: Some(page[1].id),
+            ..query
+        };
+        let page = entries(&db, user_id, &query).await.unwrap();
+        assert_eq!(page.len(), 1);
+        assert_eq!(page[0].amount, -100);
+
+        let query = LedgerQuery {
+            module_id: Some(2),
+            ..Default::default()
+        };
+        assert_eq!(entries(&db, user_id, &query).await.unwrap().len(), 1);
+
+        let query = LedgerQuery {
+            kind: Some("bonus".to_string()),
+            ..Default::default()
+        };
+        assert!(matches!(
+            entries(&db, user_id, &query).await,
+            Err(AwsError::InvalidLedgerQuery(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_wallets_are_reconciled_with_the_ledger() {
+        let db = testing::database().await;
+        let user_id = user(&db, 1_000).await;
+
+        let reservation = reserve(&db, user_id, Some(300)).await.unwrap();
+        let wallet = wallet::Entity::find().one(&db).await.unwrap().unwrap();
+
+        wallet::Entity::update(wallet::ActiveModel {
+            id: ActiveValue::unchanged(wallet.id),
+            credits: ActiveValue::set(5),
+            ..Default::default()
+        })
+        .exec(&db)
+        .await
+        .unwrap();
+
+        reconcile(&db).await.unwrap();
+
+        let wallet = wallet::Entity::find().one(&db).await.unwrap().unwrap();
+        assert_eq!(wallet.credits, 700);
+
+        settle(&db, reservation, &[debit(1, 100)]).await.unwrap();
+        reconcile(&db).await.unwrap();
+
+        let wallet = wallet::Entity::find().one(&db).await.unwrap().unwrap();
+        assert_eq!(wallet.credits, 900);
+    }
+}
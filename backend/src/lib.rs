@@ -1,11 +1,30 @@
+pub mod abi;
+pub mod artifacts;
 pub mod auth;
+pub mod budgets;
 pub mod cache;
 pub mod constants;
+pub mod credits;
+pub mod cron;
 pub mod entities;
+pub mod execution;
 pub mod extractors;
 pub mod ffi;
+pub mod host;
+mod interrupt;
+pub mod jobs;
+pub mod ledger;
+pub mod limits;
 pub mod metrics;
 pub mod migrator;
+pub mod payments;
+pub mod pipelines;
+pub mod pricing;
 pub mod routes;
+pub mod scheduler;
+pub mod sessions;
+#[cfg(test)]
+mod testing;
 pub mod utils;
+pub mod wasi;
 pub use cache::ModuleCache;
@@ -0,0 +1,398 @@
+use std::{
+    ptr::NonNull,
+    sync::{
+        atomic::{AtomicBool, AtomicU32, Ordering},
+        Arc,
+    },
+};
+
+use wasmer::{
+    BaseTunables, LinkError, MemoryError, MemoryType, Pages, TableType, Tunables, WASM_PAGE_SIZE,
+};
+use wasmer_types::{entity::PrimaryMap, LocalGlobalIndex, ModuleInfo};
+use wasmer_vm::{
+    InternalStoreHandle, LinearMemory, MemoryStyle, StoreObjects, TableStyle, Trap, VMGlobal,
+    VMMemory, VMMemoryDefinition, VMTable, VMTableDefinition,
+};
+
+use crate::constants::{
+    FREE_TIER, FREE_TIER_INSTANCE_LIMIT, FREE_TIER_MEMORY_LIMIT_PAGES,
+    FREE_TIER_TABLE_LIMIT_ELEMENTS, INSTANCE_LIMIT, MEMORY_LIMIT_PAGES, TABLE_LIMIT_ELEMENTS,
+};
+
+/// Resources a single call may allocate on the host
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ResourceLimits {
+    /// Total pages of every linear memory of the call
+    pub memory_pages: u32,
+    /// Elements of any single table
+    pub table_elements: u32,
+    pub instances: u32,
+}
+
+impl Default for ResourceLimits {
+    fn default() -> Self {
+        Self {
+            memory_pages: MEMORY_LIMIT_PAGES,
+            table_elements: TABLE_LIMIT_ELEMENTS,
+            instances: INSTANCE_LIMIT,
+        }
+    }
+}
+
+impl ResourceLimits {
+    pub fn tightened(self, other: Self) -> Self {
+        Self {
+            memory_pages: self.memory_pages.min(other.memory_pages),
+            table_elements: self.table_elements.min(other.table_elements),
+            instances: self.instances.min(other.instances),
+        }
+    }
+
+    /// Limits of a user of `tier`, which can only be tighter than these
+    pub fn for_tier(self, tier: &str) -> Self {
+        match tier {
+            FREE_TIER => self.tightened(Self {
+                memory_pages: FREE_TIER_MEMORY_LIMIT_PAGES,
+                table_elements: FREE_TIER_TABLE_LIMIT_ELEMENTS,
+                instances: FREE_TIER_INSTANCE_LIMIT,
+            }),
+            _ => self,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct UsageState {
+    memory_pages: AtomicU32,
+    instances: AtomicU32,
+    exceeded: AtomicBool,
+    growth_failed: AtomicBool,
+}
+
+/// What the instances of a store allocated so far
+#[derive(Clone, Debug, Default)]
+pub struct ResourceUsage(Arc<UsageState>);
+
+impl ResourceUsage {
+    /// Memories never shrink, so this is also the peak
+    pub fn memory_bytes(&self) -> u64 {
+        self.0.memory_pages.load(Ordering::Relaxed) as u64 * WASM_PAGE_SIZE as u64
+    }
+
+    /// Whether a guest tried to go over one of the limits
+    pub fn exceeded(&self) -> bool {
+        self.0.exceeded.load(Ordering::Relaxed)
+    }
+
+    /// Whether the latest growth of a memory failed on the limits, no
+    /// growth having succeeded since
+    pub fn growth_failed(&self) -> bool {
+        self.0.growth_failed.load(Ordering::Relaxed)
+    }
+
+    fn exceed(&self) {
+        self.0.exceeded.store(true, Ordering::Relaxed);
+    }
+
+    /// Reserves `pages` more memory pages if they stay within `limit`
+    fn reserve_pages(&self, pages: u32, limit: u32) -> bool {
+        let reserved = self
+            .0
+            .memory_pages
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |current| {
+                current.checked_add(pages).filter(|total| *total <= limit)
+            })
+            .is_ok();
+
+        if !reserved {
+            self.exceed();
+        }
+
+        reserved
+    }
+
+    fn release_pages(&self, pages: u32) {
+        self.0.memory_pages.fetch_sub(pages, Ordering::Relaxed);
+    }
+}
+
+/// Memory whose growth is charged against the limits of its store
+#[derive(Debug)]
+struct LimitedMemory {
+    inner: VMMemory,
+    usage: ResourceUsage,
+    limit: u32,
+}
+
+impl LinearMemory for LimitedMemory {
+    fn ty(&self) -> MemoryType {
+        self.inner.ty()
+    }
+
+    fn size(&self) -> Pages {
+        self.inner.size()
+    }
+
+    fn style(&self) -> MemoryStyle {
+        self.inner.style()
+    }
+
+    fn grow(&mut self, delta: Pages) -> Result<Pages, MemoryError> {
+        let reserved = self.usage.reserve_pages(delta.0, self.limit);
+        self.usage
+            .0
+            .growth_failed
+            .store(!reserved, Ordering::Relaxed);
+
+        if !reserved {
+            // Makes `memory.grow` return -1 like any other failed growth
+            return Err(MemoryError::CouldNotGrow {
+                current: self.size(),
+                attempted_delta: delta,
+            });
+        }
+
+        self.inner.grow(delta).map_err(|e| {
+            self.usage.release_pages(delta.0);
+            e
+        })
+    }
+
+    fn vmmemory(&self) -> NonNull<VMMemoryDefinition> {
+        self.inner.vmmemory()
+    }
+
+    fn try_clone(&self) -> Option<Box<dyn LinearMemory + 'static>> {
+        None
+    }
+
+    unsafe fn initialize_with_data(&self, start: usize, data: &[u8]) -> Result<(), Trap> {
+        self.inner.initialize_with_data(start, data)
+    }
+}
+
+/// Tunables enforcing `ResourceLimits` on the memories, tables and
+/// instances created in a store
+pub struct LimitingTunables {
+    base: BaseTunables,
+    limits: ResourceLimits,
+    usage: ResourceUsage,
+}
+
+impl LimitingTunables {
+    pub fn new(base: BaseTunables, limits: ResourceLimits) -> Self {
+        Self {
+            base,
+            limits,
+            usage: ResourceUsage::default(),
+        }
+    }
+
+    pub fn usage(&self) -> ResourceUsage {
+        self.usage.clone()
+    }
+
+    fn limit_memory(
+        &self,
+        ty: &MemoryType,
+        create: impl FnOnce() -> Result<VMMemory, MemoryError>,
+    ) -> Result<VMMemory, MemoryError> {
+        if !self
+            .usage
+            .reserve_pages(ty.minimum.0, self.limits.memory_pages)
+        {
+            return Err(MemoryError::MinimumMemoryTooLarge {
+                min_requested: ty.minimum,
+                max_allowed: Pages(self.limits.memory_pages),
+            });
+        }
+
+        let inner = create().map_err(|e| {
+            self.usage.release_pages(ty.minimum.0);
+            e
+        })?;
+
+        Ok(VMMemory::from_custom(Box::new(LimitedMemory {
+            inner,
+            usage: self.usage.clone(),
+            limit: self.limits.memory_pages,
+        }) as Box<dyn LinearMemory>))
+    }
+
+    /// Caps the maximum of the table so `table.grow` fails past the limit
+    fn limit_table(&self, ty: &TableType) -> Result<TableType, String> {
+        if ty.minimum > self.limits.table_elements {
+            self.usage.exceed();
+
+            return Err(format!(
+                "table of {} elements exceeds the limit of {}",
+                ty.minimum, self.limits.table_elements
+            ));
+        }
+
+        let mut ty = *ty;
+        ty.maximum = Some(
+            ty.maximum
+                .unwrap_or(u32::MAX)
+                .min(self.limits.table_elements),
+        );
+
+        Ok(ty)
+    }
+}
+
+impl Tunables for LimitingTunables {
+    // Styles are left untouched so that artifacts compiled without the
+    // limits can still be instantiated with them
+    fn memory_style(&self, memory: &MemoryType) -> MemoryStyle {
+        self.base.memory_style(memory)
+    }
+
+    fn table_style(&self, table: &TableType) -> TableStyle {
+        self.base.table_style(table)
+    }
+
+    fn create_host_memory(
+        &self,
+        ty: &MemoryType,
+        style: &MemoryStyle,
+    ) -> Result<VMMemory, MemoryError> {
+        self.limit_memory(ty, || self.base.create_host_memory(ty, style))
+    }
+
+    unsafe fn create_vm_memory(
+        &self,
+        ty: &MemoryType,
+        style: &MemoryStyle,
+        vm_definition_location: NonNull<VMMemoryDefinition>,
+    ) -> Result<VMMemory, MemoryError> {
+        self.limit_memory(ty, || {
+            self.base
+                .create_vm_memory(ty, style, vm_definition_location)
+        })
+    }
+
+    fn create_host_table(&self, ty: &TableType, style: &TableStyle) -> Result<VMTable, String> {
+        self.base.create_host_table(&self.limit_table(ty)?, style)
+    }
+
+    unsafe fn create_vm_table(
+        &self,
+        ty: &TableType,
+        style: &TableStyle,
+        vm_definition_location: NonNull<VMTableDefinition>,
+    ) -> Result<VMTable, String> {
+        self.base
+            .create_vm_table(&self.limit_table(ty)?, style, vm_definition_location)
+    }
+
+    // Every instantiation creates its globals exactly once, even when
+    // there are none, which makes it the place to count instances
+    fn create_globals(
+        &self,
+        context: &mut StoreObjects,
+        module: &ModuleInfo,
+    ) -> Result<PrimaryMap<LocalGlobalIndex, InternalStoreHandle<VMGlobal>>, LinkError> {
+        let instances = &self.usage.0.instances;
+
+        if instances.fetch_add(1, Ordering::Relaxed) >= self.limits.instances {
+            self.usage.exceed();
+
+            return Err(LinkError::Resource(format!(
+                "more than {} instances",
+                self.limits.instances
+            )));
+        }
+
+        self.base.create_globals(context, module)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::limited_store;
+    use wasmer::{imports, Instance, Module, Value};
+
+    const GROW: &str = r#"
+        (module
+            (memory 1)
+            (func (export "grow") (param i32) (result i32)
+                (memory.grow (local.get 0))))
+    "#;
+
+    const LIMITS: ResourceLimits = ResourceLimits {
+        memory_pages: 4,
+        table_elements: 10,
+        instances: 1,
+    };
+
+    #[test]
+    fn test_memory_growth_is_limited() {
+        let (mut store, usage) = limited_store(LIMITS);
+        let module = Module::new(&store, GROW).unwrap();
+        let instance = Instance::new(&mut store, &module, &imports! {}).unwrap();
+        let grow = instance.exports.get_function("grow").unwrap();
+
+        assert_eq!(
+            &*grow.call(&mut store, &[Value::I32(3)]).unwrap(),
+            &[Value::I32(1)]
+        );
+        assert!(!usage.exceeded());
+
+        assert_eq!(
+            &*grow.call(&mut store, &[Value::I32(1)]).unwrap(),
+            &[Value::I32(-1)]
+        );
+        assert!(usage.exceeded());
+        assert!(usage.growth_failed());
+
+        grow.call(&mut store, &[Value::I32(0)]).unwrap();
+        assert!(!usage.growth_failed());
+        assert_eq!(usage.memory_bytes(), 4 * WASM_PAGE_SIZE as u64);
+    }
+
+    #[test]
+    fn test_large_initial_memory_fails_instantiation() {
+        let (mut store, usage) = limited_store(LIMITS);
+        let module = Module::new(&store, "(module (memory 5))").unwrap();
+
+        assert!(Instance::new(&mut store, &module, &imports! {}).is_err());
+        assert!(usage.exceeded());
+    }
+
+    #[test]
+    fn test_tables_and_instances_are_limited() {
+        let (mut store, usage) = limited_store(LIMITS);
+        let module = Module::new(&store, "(module (table 11 funcref))").unwrap();
+
+        assert!(Instance::new(&mut store, &module, &imports! {}).is_err());
+        assert!(usage.exceeded());
+
+        let (mut store, usage) = limited_store(LIMITS);
+        let module = Module::new(&store, "(module)").unwrap();
+
+        assert!(Instance::new(&mut store, &module, &imports! {}).is_ok());
+        assert!(Instance::new(&mut store, &module, &imports! {}).is_err());
+        assert!(usage.exceeded());
+    }
+
+    #[test]
+    fn test_tier_only_tightens() {
+        let global = ResourceLimits {
+            memory_pages: 1,
+            ..Default::default()
+        };
+
+        assert_eq!(global.for_tier(FREE_TIER).memory_pages, 1);
+        assert_eq!(
+            ResourceLimits::default().for_tier(FREE_TIER).memory_pages,
+            FREE_TIER_MEMORY_LIMIT_PAGES
+        );
+        assert_eq!(
+            ResourceLimits::default().for_tier("pro"),
+            ResourceLimits::default()
+        );
+    }
+}
@@ -1,5 +1,8 @@
 use lazy_static::lazy_static;
-use prometheus::{register_counter, register_gauge, register_histogram, Counter, Gauge, Histogram};
+use prometheus::{
+    register_counter, register_gauge, register_histogram, register_int_counter, Counter, Gauge,
+    Histogram, IntCounter,
+};
 
 lazy_static! {
     pub static ref FUNCTION_CALLS: Counter =
@@ -12,4 +15,33 @@ lazy_static! {
             .expect("to create histogram");
     pub static ref WASM_CODE_SIZE: Gauge =
         register_gauge!("wasm_code_size", "Size of the stored WASM code").expect("to create gauge");
+    pub static ref MODULE_CACHE_HITS: IntCounter = register_int_counter!(
+        "module_cache_hits",
+        "Calls served from a cached compiled module"
+    )
+    .expect("to create counter");
+    pub static ref MODULE_CACHE_MISSES: IntCounter = register_int_counter!(
+        "module_cache_misses",
+        "Calls that had to compile their module"
+    )
+    .expect("to create counter");
+    pub static ref MODULE_CACHE_EVICTIONS: IntCounter = register_int_counter!(
+        "module_cache_evictions",
+        "Compiled modules evicted to stay within the cache budget"
+    )
+    .expect("to create counter");
+    pub static ref FUNCTION_CALL_PEAK_MEMORY: Histogram = register_histogram!(
+        "function_call_peak_memory",
+        "Peak linear memory of function calls in bytes",
+        prometheus::exponential_buckets(65536.0, 4.0, 8).expect("valid buckets")
+    )
+    .expect("to create histogram");
+    pub static ref JOBS_DEAD_LETTERED: IntCounter = register_int_counter!(
+        "jobs_dead_lettered",
+        "Jobs that failed every one of their attempts"
+    )
+    .expect("to create counter");
+    pub static ref MODULE_CACHE_SIZE: Gauge =
+        register_gauge!("module_cache_size", "Size of the cached compiled modules")
+            .expect("to create gauge");
 }
@@ -0,0 +1,68 @@
+use sea_orm_migration::prelude::*;
+
+use super::m20230328_000002_modules_table::Module;
+
+pub struct Migration;
+
+impl MigrationName for Migration {
+    fn name(&self) -> &'static str {
+        "m20261018_000005_module_artifacts_table"
+    }
+}
+
+#[sea_orm_migration::async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(ModuleArtifact::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(ModuleArtifact::Id)
+                            .integer()
+                            .not_null()
+                            .primary_key()
+                            .auto_increment(),
+                    )
+                    .col(ColumnDef::new(ModuleArtifact::CodeHash).string().not_null())
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk-module_artifact-code_hash")
+                            .from(ModuleArtifact::Table, ModuleArtifact::CodeHash)
+                            .to(Module::Table, Module::CodeHash)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .col(ColumnDef::new(ModuleArtifact::Engine).string().not_null())
+                    .col(
+                        ColumnDef::new(ModuleArtifact::Artifact)
+                            .blob(BlobSize::Long)
+                            .not_null(),
+                    )
+                    .index(
+                        Index::create()
+                            .name("idx-module_artifact-code_hash-engine")
+                            .col(ModuleArtifact::CodeHash)
+                            .col(ModuleArtifact::Engine)
+                            .unique(),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(ModuleArtifact::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(Iden)]
+pub enum ModuleArtifact {
+    Table,
+    Id,
+    CodeHash,
+    Engine,
+    Artifact,
+}
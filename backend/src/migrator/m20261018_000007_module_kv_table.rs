@@ -0,0 +1,68 @@
+use sea_orm_migration::prelude::*;
+
+use super::m20230328_000002_modules_table::Module;
+
+pub struct Migration;
+
+impl MigrationName for Migration {
+    fn name(&self) -> &'static str {
+        "m20261018_000007_module_kv_table"
+    }
+}
+
+#[sea_orm_migration::async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(ModuleKv::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(ModuleKv::Id)
+                            .integer()
+                            .not_null()
+                            .primary_key()
+                            .auto_increment(),
+                    )
+                    .col(ColumnDef::new(ModuleKv::ModuleId).integer().not_null())
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk-module_kv-module_id")
+                            .from(ModuleKv::Table, ModuleKv::ModuleId)
+                            .to(Module::Table, Module::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .col(ColumnDef::new(ModuleKv::Key).string_len(255).not_null())
+                    .col(
+                        ColumnDef::new(ModuleKv::Value)
+                            .blob(BlobSize::Long)
+                            .not_null(),
+                    )
+                    .index(
+                        Index::create()
+                            .name("idx-module_kv-module_id-key")
+                            .col(ModuleKv::ModuleId)
+                            .col(ModuleKv::Key)
+                            .unique(),
+                    )
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(ModuleKv::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(Iden)]
+pub enum ModuleKv {
+    Table,
+    Id,
+    ModuleId,
+    Key,
+    Value,
+}
@@ -0,0 +1,104 @@
+use sea_orm_migration::prelude::*;
+
+use super::{m20230328_000001_users_table::User, m20230329_000004_functions_table::Function};
+
+pub struct Migration;
+
+impl MigrationName for Migration {
+    fn name(&self) -> &'static str {
+        "m20261018_000008_jobs_table"
+    }
+}
+
+#[sea_orm_migration::async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(Job::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(Job::Id)
+                            .integer()
+                            .not_null()
+                            .primary_key()
+                            .auto_increment(),
+                    )
+                    .col(ColumnDef::new(Job::OwnerId).integer().not_null())
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk-job-owner_id")
+                            .from(Job::Table, Job::OwnerId)
+                            .to(User::Table, User::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .col(ColumnDef::new(Job::FunctionId).integer().not_null())
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk-job-function_id")
+                            .from(Job::Table, Job::FunctionId)
+                            .to(Function::Table, Function::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .col(ColumnDef::new(Job::Params).text().not_null())
+                    .col(ColumnDef::new(Job::Wasi).text())
+                    .col(ColumnDef::new(Job::TimeoutMs).big_integer())
+                    .col(ColumnDef::new(Job::Status).string_len(16).not_null())
+                    .col(
+                        ColumnDef::new(Job::Attempts)
+                            .integer()
+                            .not_null()
+                            .default(0),
+                    )
+                    .col(ColumnDef::new(Job::Result).text())
+                    .col(ColumnDef::new(Job::Error).text())
+                    .col(
+                        ColumnDef::new(Job::UsedCredits)
+                            .big_integer()
+                            .not_null()
+                            .default(0),
+                    )
+                    .col(ColumnDef::new(Job::RunAt).big_integer().not_null())
+                    .col(ColumnDef::new(Job::CreatedAt).big_integer().not_null())
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx-job-status-run_at")
+                    .table(Job::Table)
+                    .col(Job::Status)
+                    .col(Job::RunAt)
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(Job::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(Iden)]
+pub enum Job {
+    Table,
+    Id,
+    OwnerId,
+    FunctionId,
+    Params,
+    Wasi,
+    TimeoutMs,
+    Status,
+    Attempts,
+    Result,
+    Error,
+    UsedCredits,
+    /// Unix time in milliseconds the job may run from
+    RunAt,
+    CreatedAt,
+}
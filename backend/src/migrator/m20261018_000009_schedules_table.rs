@@ -0,0 +1,145 @@
+use sea_orm_migration::prelude::*;
+
+use super::{m20230328_000001_users_table::User, m20230329_000004_functions_table::Function};
+
+pub struct Migration;
+
+impl MigrationName for Migration {
+    fn name(&self) -> &'static str {
+        "m20261018_000009_schedules_table"
+    }
+}
+
+#[sea_orm_migration::async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(Schedule::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(Schedule::Id)
+                            .integer()
+                            .not_null()
+                            .primary_key()
+                            .auto_increment(),
+                    )
+                    .col(ColumnDef::new(Schedule::OwnerId).integer().not_null())
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk-schedule-owner_id")
+                            .from(Schedule::Table, Schedule::OwnerId)
+                            .to(User::Table, User::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .col(ColumnDef::new(Schedule::FunctionId).integer().not_null())
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk-schedule-function_id")
+                            .from(Schedule::Table, Schedule::FunctionId)
+                            .to(Function::Table, Function::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .col(ColumnDef::new(Schedule::Cron).string().not_null())
+                    .col(ColumnDef::new(Schedule::Params).text().not_null())
+                    .col(ColumnDef::new(Schedule::TimeoutMs).big_integer())
+                    .col(ColumnDef::new(Schedule::Status).string_len(16).not_null())
+                    .col(ColumnDef::new(Schedule::PausedReason).string())
+                    .col(ColumnDef::new(Schedule::NextRunAt).big_integer().not_null())
+                    .col(ColumnDef::new(Schedule::CreatedAt).big_integer().not_null())
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx-schedule-status-next_run_at")
+                    .table(Schedule::Table)
+                    .col(Schedule::Status)
+                    .col(Schedule::NextRunAt)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_table(
+                Table::create()
+                    .table(ScheduleRun::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(ScheduleRun::Id)
+                            .integer()
+                            .not_null()
+                            .primary_key()
+                            .auto_increment(),
+                    )
+                    .col(ColumnDef::new(ScheduleRun::ScheduleId).integer().not_null())
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk-schedule_run-schedule_id")
+                            .from(ScheduleRun::Table, ScheduleRun::ScheduleId)
+                            .to(Schedule::Table, Schedule::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .col(
+                        ColumnDef::new(ScheduleRun::StartedAt)
+                            .big_integer()
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(ScheduleRun::Status)
+                            .string_len(16)
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(ScheduleRun::UsedCredits)
+                            .big_integer()
+                            .not_null()
+                            .default(0),
+                    )
+                    .col(ColumnDef::new(ScheduleRun::Result).text())
+                    .col(ColumnDef::new(ScheduleRun::Error).text())
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(ScheduleRun::Table).to_owned())
+            .await?;
+
+        manager
+            .drop_table(Table::drop().table(Schedule::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(Iden)]
+pub enum Schedule {
+    Table,
+    Id,
+    OwnerId,
+    FunctionId,
+    Cron,
+    Params,
+    TimeoutMs,
+    Status,
+    PausedReason,
+    NextRunAt,
+    CreatedAt,
+}
+
+#[derive(Iden)]
+pub enum ScheduleRun {
+    Table,
+    Id,
+    ScheduleId,
+    StartedAt,
+    Status,
+    UsedCredits,
+    Result,
+    Error,
+}
@@ -0,0 +1,57 @@
+use sea_orm_migration::prelude::*;
+
+use super::m20230328_000001_users_table::User;
+
+pub struct Migration;
+
+impl MigrationName for Migration {
+    fn name(&self) -> &'static str {
+        "m20261018_000010_pipelines_table"
+    }
+}
+
+#[sea_orm_migration::async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(Pipeline::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(Pipeline::Id)
+                            .integer()
+                            .not_null()
+                            .primary_key()
+                            .auto_increment(),
+                    )
+                    .col(ColumnDef::new(Pipeline::OwnerId).integer().not_null())
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk-pipeline-owner_id")
+                            .from(Pipeline::Table, Pipeline::OwnerId)
+                            .to(User::Table, User::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .col(ColumnDef::new(Pipeline::Name).string().not_null())
+                    .col(ColumnDef::new(Pipeline::Steps).text().not_null())
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(Pipeline::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(Iden)]
+pub enum Pipeline {
+    Table,
+    Id,
+    OwnerId,
+    Name,
+    Steps,
+}
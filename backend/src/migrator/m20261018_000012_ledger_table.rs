@@ -0,0 +1,129 @@
+use sea_orm_migration::prelude::*;
+
+use super::{
+    m20230328_000001_users_table::User, m20230329_000003_wallets_table::Wallet,
+    m20261018_000011_credit_reservations_table::CreditReservation,
+};
+use crate::utils::now_ms;
+
+pub struct Migration;
+
+impl MigrationName for Migration {
+    fn name(&self) -> &'static str {
+        "m20261018_000012_ledger_table"
+    }
+}
+
+#[sea_orm_migration::async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(LedgerEntry::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(LedgerEntry::Id)
+                            .integer()
+                            .not_null()
+                            .primary_key()
+                            .auto_increment(),
+                    )
+                    .col(ColumnDef::new(LedgerEntry::UserId).integer().not_null())
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk-ledger_entry-user_id")
+                            .from(LedgerEntry::Table, LedgerEntry::UserId)
+                            .to(User::Table, User::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .col(ColumnDef::new(LedgerEntry::Kind).string_len(16).not_null())
+                    .col(ColumnDef::new(LedgerEntry::Amount).big_integer().not_null())
+                    .col(ColumnDef::new(LedgerEntry::ModuleId).integer())
+                    .col(ColumnDef::new(LedgerEntry::Function).string())
+                    .col(ColumnDef::new(LedgerEntry::DurationMs).big_integer())
+                    .col(
+                        ColumnDef::new(LedgerEntry::CreatedAt)
+                            .big_integer()
+                            .not_null(),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx-ledger_entry-user_id-id")
+                    .table(LedgerEntry::Table)
+                    .col(LedgerEntry::UserId)
+                    .col(LedgerEntry::Id)
+                    .to_owned(),
+            )
+            .await?;
+
+        // Existing balances, including what is reserved, are carried over
+        // as a grant
+        let reserved = Query::select()
+            .expr(Func::sum(Expr::col((
+                CreditReservation::Table,
+                CreditReservation::Amount,
+            ))))
+            .from(CreditReservation::Table)
+            .and_where(
+                Expr::col((CreditReservation::Table, CreditReservation::UserId))
+                    .equals((Wallet::Table, Wallet::UserId)),
+            )
+            .to_owned();
+
+        let opening = Query::select()
+            .column((Wallet::Table, Wallet::UserId))
+            .expr(Expr::val("grant"))
+            .expr(
+                Expr::col((Wallet::Table, Wallet::Credits)).add(Func::coalesce([
+                    SimpleExpr::SubQuery(None, Box::new(reserved.into_sub_query_statement())),
+                    Expr::val(0).into(),
+                ])),
+            )
+            .expr(Expr::val(now_ms()))
+            .from(Wallet::Table)
+            .to_owned();
+
+        manager
+            .exec_stmt(
+                Query::insert()
+                    .into_table(LedgerEntry::Table)
+                    .columns([
+                        LedgerEntry::UserId,
+                        LedgerEntry::Kind,
+                        LedgerEntry::Amount,
+                        LedgerEntry::CreatedAt,
+                    ])
+                    .select_from(opening)
+                    .map_err(|e| DbErr::Migration(e.to_string()))?
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(LedgerEntry::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(Iden)]
+pub enum LedgerEntry {
+    Table,
+    Id,
+    UserId,
+    /// Kind of the movement, see `ledger::EntryKind`
+    Kind,
+    /// Credits added to the wallet, negative for debits
+    Amount,
+    ModuleId,
+    Function,
+    DurationMs,
+    CreatedAt,
+}
@@ -0,0 +1,120 @@
+use sea_orm_migration::prelude::*;
+
+use super::{m20230328_000001_users_table::User, m20261018_000012_ledger_table::LedgerEntry};
+
+pub struct Migration;
+
+impl MigrationName for Migration {
+    fn name(&self) -> &'static str {
+        "m20261018_000013_top_ups_table"
+    }
+}
+
+#[sea_orm_migration::async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(TopUp::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(TopUp::Id)
+                            .integer()
+                            .not_null()
+                            .primary_key()
+                            .auto_increment(),
+                    )
+                    .col(ColumnDef::new(TopUp::UserId).integer().not_null())
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk-top_up-user_id")
+                            .from(TopUp::Table, TopUp::UserId)
+                            .to(User::Table, User::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .col(ColumnDef::new(TopUp::Amount).big_integer().not_null())
+                    .col(ColumnDef::new(TopUp::Provider).string().not_null())
+                    .col(ColumnDef::new(TopUp::Reference).string().not_null())
+                    .col(ColumnDef::new(TopUp::Status).string_len(16).not_null())
+                    .col(ColumnDef::new(TopUp::CreatedAt).big_integer().not_null())
+                    .col(ColumnDef::new(TopUp::SettledAt).big_integer())
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(User::Table)
+                    .add_column(
+                        ColumnDef::new(UserAdmin::Admin)
+                            .boolean()
+                            .not_null()
+                            .default(false),
+                    )
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(LedgerEntry::Table)
+                    .add_column(ColumnDef::new(LedgerCounterparty::CounterpartyId).integer())
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(LedgerEntry::Table)
+                    .drop_column(LedgerCounterparty::CounterpartyId)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(User::Table)
+                    .drop_column(UserAdmin::Admin)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .drop_table(Table::drop().table(TopUp::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(Iden)]
+pub enum TopUp {
+    Table,
+    Id,
+    UserId,
+    Amount,
+    /// Name of the payment provider charging the user
+    Provider,
+    /// Id of the payment at the provider
+    Reference,
+    /// One of `pending`, `settled` or `failed`
+    Status,
+    CreatedAt,
+    SettledAt,
+}
+
+#[derive(Iden)]
+pub enum UserAdmin {
+    Admin,
+}
+
+#[derive(Iden)]
+pub enum LedgerCounterparty {
+    /// Other side of a transfer
+    CounterpartyId,
+}
@@ -0,0 +1,108 @@
+use sea_orm_migration::prelude::*;
+
+use super::m20230328_000001_users_table::User;
+
+pub struct Migration;
+
+impl MigrationName for Migration {
+    fn name(&self) -> &'static str {
+        "m20261018_000015_spending_limits_table"
+    }
+}
+
+#[sea_orm_migration::async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .create_table(
+                Table::create()
+                    .table(SpendingLimit::Table)
+                    .if_not_exists()
+                    .col(
+                        ColumnDef::new(SpendingLimit::Id)
+                            .integer()
+                            .not_null()
+                            .primary_key()
+                            .auto_increment(),
+                    )
+                    .col(ColumnDef::new(SpendingLimit::UserId).integer().not_null())
+                    .foreign_key(
+                        ForeignKey::create()
+                            .name("fk-spending_limit-user_id")
+                            .from(SpendingLimit::Table, SpendingLimit::UserId)
+                            .to(User::Table, User::Id)
+                            .on_delete(ForeignKeyAction::Cascade),
+                    )
+                    .col(ColumnDef::new(SpendingLimit::ModuleId).integer())
+                    .col(
+                        ColumnDef::new(SpendingLimit::Period)
+                            .string_len(16)
+                            .not_null(),
+                    )
+                    .col(
+                        ColumnDef::new(SpendingLimit::MaxCredits)
+                            .big_integer()
+                            .not_null(),
+                    )
+                    .col(ColumnDef::new(SpendingLimit::AlertPercent).integer())
+                    .col(ColumnDef::new(SpendingLimit::AlertedAt).big_integer())
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .create_index(
+                Index::create()
+                    .name("idx-spending_limit-user_id")
+                    .table(SpendingLimit::Table)
+                    .col(SpendingLimit::UserId)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(User::Table)
+                    .add_column(ColumnDef::new(UserBudgetWebhook::BudgetWebhookUrl).string())
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(User::Table)
+                    .drop_column(UserBudgetWebhook::BudgetWebhookUrl)
+                    .to_owned(),
+            )
+            .await?;
+
+        manager
+            .drop_table(Table::drop().table(SpendingLimit::Table).to_owned())
+            .await
+    }
+}
+
+#[derive(Iden)]
+pub enum SpendingLimit {
+    Table,
+    Id,
+    UserId,
+    /// Module the limit applies to, all of the user's calls without one
+    ModuleId,
+    /// Either `daily` or `monthly`, in UTC
+    Period,
+    MaxCredits,
+    /// Share of the limit, in percent, past which the user is alerted
+    AlertPercent,
+    /// When the last alert was sent, at most once per period
+    AlertedAt,
+}
+
+#[derive(Iden)]
+pub enum UserBudgetWebhook {
+    BudgetWebhookUrl,
+}
@@ -0,0 +1,28 @@
+use sea_orm_migration::prelude::*;
+
+use super::m20261018_000016_refresh_tokens_table::{self, RefreshToken};
+
+pub struct Migration;
+
+impl MigrationName for Migration {
+    fn name(&self) -> &'static str {
+        "m20261018_000018_drop_refresh_tokens"
+    }
+}
+
+/// Sessions moved to the auth service along with credentials, users log
+/// in again once
+#[sea_orm_migration::async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .drop_table(Table::drop().table(RefreshToken::Table).to_owned())
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        m20261018_000016_refresh_tokens_table::Migration
+            .up(manager)
+            .await
+    }
+}
@@ -0,0 +1,43 @@
+use sea_orm_migration::prelude::*;
+
+use super::m20230328_000002_modules_table::Module;
+
+pub struct Migration;
+
+impl MigrationName for Migration {
+    fn name(&self) -> &'static str {
+        "m20261018_000019_public_modules"
+    }
+}
+
+#[sea_orm_migration::async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Module::Table)
+                    .add_column(ColumnDef::new(PublicModule::PublicCallCredits).big_integer())
+                    .to_owned(),
+            )
+            .await
+    }
+
+    async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        manager
+            .alter_table(
+                Table::alter()
+                    .table(Module::Table)
+                    .drop_column(PublicModule::PublicCallCredits)
+                    .to_owned(),
+            )
+            .await
+    }
+}
+
+#[derive(Iden)]
+pub enum PublicModule {
+    /// Most credits a single `/run` call may use, the module not being
+    /// served on `/run` without it
+    PublicCallCredits,
+}
@@ -6,6 +6,21 @@ pub mod m20230328_000001_users_table;
 pub mod m20230328_000002_modules_table;
 pub mod m20230329_000003_wallets_table;
 pub mod m20230329_000004_functions_table;
+pub mod m20261018_000005_module_artifacts_table;
+pub mod m20261018_000006_user_tiers;
+pub mod m20261018_000007_module_kv_table;
+pub mod m20261018_000008_jobs_table;
+pub mod m20261018_000009_schedules_table;
+pub mod m20261018_000010_pipelines_table;
+pub mod m20261018_000011_credit_reservations_table;
+pub mod m20261018_000012_ledger_table;
+pub mod m20261018_000013_top_ups_table;
+pub mod m20261018_000014_wide_credits;
+pub mod m20261018_000015_spending_limits_table;
+pub mod m20261018_000016_refresh_tokens_table;
+pub mod m20261018_000017_api_keys_table;
+pub mod m20261018_000018_drop_refresh_tokens;
+pub mod m20261018_000019_public_modules;
 
 #[sea_orm_migration::async_trait::async_trait]
 impl MigratorTrait for Migrator {
@@ -15,6 +30,21 @@ impl MigratorTrait for Migrator {
             Box::new(m20230328_000002_modules_table::Migration),
             Box::new(m20230329_000003_wallets_table::Migration),
             Box::new(m20230329_000004_functions_table::Migration),
+            Box::new(m20261018_000005_module_artifacts_table::Migration),
+            Box::new(m20261018_000006_user_tiers::Migration),
+            Box::new(m20261018_000007_module_kv_table::Migration),
+            Box::new(m20261018_000008_jobs_table::Migration),
+            Box::new(m20261018_000009_schedules_table::Migration),
+            Box::new(m20261018_000010_pipelines_table::Migration),
+            Box::new(m20261018_000011_credit_reservations_table::Migration),
+            Box::new(m20261018_000012_ledger_table::Migration),
+            Box::new(m20261018_000013_top_ups_table::Migration),
+            Box::new(m20261018_000014_wide_credits::Migration),
+            Box::new(m20261018_000015_spending_limits_table::Migration),
+            Box::new(m20261018_000016_refresh_tokens_table::Migration),
+            Box::new(m20261018_000017_api_keys_table::Migration),
+            Box::new(m20261018_000018_drop_refresh_tokens::Migration),
+            Box::new(m20261018_000019_public_modules::Migration),
         ]
     }
 }
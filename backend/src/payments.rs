@@ -0,0 +1,265 @@
+//! Top-ups buy credits through a payment provider. A top-up stays
+//! pending until the provider says the payment settled, only then are
+//! the credits added to the wallet, exactly once.
+
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use aws_common::api::{errors::AwsError, responses::TopUpResponse};
+use axum::async_trait;
+use sea_orm::{
+    ActiveModelTrait, ActiveValue, ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter,
+    TransactionTrait,
+};
+
+use crate::{
+    credits::{apply, check_amount, map_transaction_error},
+    entities::top_up,
+    ledger::EntryKind,
+    utils::now_ms,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PaymentStatus {
+    Pending,
+    Settled,
+    Failed,
+}
+
+impl PaymentStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PaymentStatus::Pending => "pending",
+            PaymentStatus::Settled => "settled",
+            PaymentStatus::Failed => "failed",
+        }
+    }
+}
+
+/// Charges users for the credits they top up
+#[async_trait]
+pub trait PaymentProvider: Send + Sync {
+    /// Name recorded with the top-ups it charges
+    fn name(&self) -> &'static str;
+
+    /// Starts charging the user for `amount` credits, returning the id
+    /// of the payment at the provider
+    async fn create_payment(&self, user_id: i32, amount: u64) -> Result<String, AwsError>;
+
+    async fn payment_status(&self, reference: &str) -> Result<PaymentStatus, AwsError>;
+}
+
+/// Provider of the deployment, top-ups are disabled without one
+#[derive(Clone)]
+pub struct Payments(pub Option<Arc<dyn PaymentProvider>>);
+
+impl Payments {
+    pub fn provider(&self) -> Result<&dyn PaymentProvider, AwsError> {
+        self.0.as_deref().ok_or(AwsError::TopUpsDisabled)
+    }
+}
+
+/// Provider charging nothing, for local setups and tests. Payments stay
+/// pending until completed, unless `auto_settle` settles them on the
+/// first status check
+#[derive(Default)]
+pub struct FakePaymentProvider {
+    payments: Mutex<HashMap<String, PaymentStatus>>,
+    next_id: AtomicU64,
+    pub auto_settle: bool,
+}
+
+impl FakePaymentProvider {
+    pub fn new(auto_settle: bool) -> Self {
+        Self {
+            auto_settle,
+            ..Default::default()
+        }
+    }
+
+    pub fn complete(&self, reference: &str, status: PaymentStatus) {
+        if let Some(payment) = self.payments.lock().unwrap().get_mut(reference) {
+            *payment = status;
+        }
+    }
+}
+
+#[async_trait]
+impl PaymentProvider for FakePaymentProvider {
+    fn name(&self) -> &'static str {
+        "fake"
+    }
+
+    async fn create_payment(&self, _user_id: i32, _amount: u64) -> Result<String, AwsError> {
+        let reference = format!("fake-{}", self.next_id.fetch_add(1, Ordering::Relaxed));
+
+        self.payments
+            .lock()
+            .unwrap()
+            .insert(reference.clone(), PaymentStatus::Pending);
+
+        Ok(reference)
+    }
+
+    async fn payment_status(&self, reference: &str) -> Result<PaymentStatus, AwsError> {
+        let mut payments = self.payments.lock().unwrap();
+
+        let payment = payments.get_mut(reference).ok_or_else(|| {
+            AwsError::PaymentProviderError(format!("unknown payment {reference}"))
+        })?;
+
+        if self.auto_settle && *payment == PaymentStatus::Pending {
+            *payment = PaymentStatus::Settled;
+        }
+
+        Ok(*payment)
+    }
+}
+
+impl From<top_up::Model> for TopUpResponse {
+    fn from(top_up: top_up::Model) -> Self {
+        Self {
+            id: top_up.id,
+            amount: top_up.amount as u64,
+            status: top_up.status,
+            created_at: top_up.created_at,
+            settled_at: top_up.settled_at,
+        }
+    }
+}
+
+/// Records a pending top-up of `amount` credits paid through `provider`
+pub async fn create_top_up(
+    db: &DatabaseConnection,
+    provider: &dyn PaymentProvider,
+    user_id: i32,
+    amount: u64,
+) -> Result<top_up::Model, AwsError> {
+    check_amount(amount)?;
+
+    let reference = provider.create_payment(user_id, amount).await?;
+
+    top_up::ActiveModel {
+        user_id: ActiveValue::set(user_id),
+        amount: ActiveValue::set(amount as i64),
+        provider: ActiveValue::set(provider.name().to_string()),
+        reference: ActiveValue::set(reference),
+        status: ActiveValue::set(PaymentStatus::Pending.as_str().to_string()),
+        created_at: ActiveValue::set(now_ms()),
+        ..Default::default()
+    }
+    .insert(db)
+    .await
+    .map_err(|_| AwsError::UnknownServerError)
+}
+
+/// Asks the provider how a pending top-up is doing, adding its credits
+/// to the wallet once it settled
+pub async fn refresh_top_up(
+    db: &DatabaseConnection,
+    provider: &dyn PaymentProvider,
+    top_up: top_up::Model,
+) -> Result<top_up::Model, AwsError> {
+    if top_up.status != PaymentStatus::Pending.as_str() || top_up.provider != provider.name() {
+        return Ok(top_up);
+    }
+
+    let status = provider.payment_status(&top_up.reference).await?;
+
+    if status == PaymentStatus::Pending {
+        return Ok(top_up);
+    }
+
+    let id = top_up.id;
+
+    db.transaction(|txn| {
+        Box::pin(async move {
+            // Concurrent refreshes only complete the top-up once
+            let res = top_up::Entity::update_many()
+                .col_expr(top_up::Column::Status, status.as_str().into())
+                .col_expr(top_up::Column::SettledAt, now_ms().into())
+                .filter(top_up::Column::Id.eq(top_up.id))
+                .filter(top_up::Column::Status.eq(PaymentStatus::Pending.as_str()))
+                .exec(txn)
+                .await?;
+
+            if res.rows_affected == 1 && status == PaymentStatus::Settled {
+                apply(txn, top_up.user_id, EntryKind::TopUp, top_up.amount, None).await?;
+            }
+
+            Ok(())
+        })
+    })
+    .await
+    .map_err(map_transaction_error)?;
+
+    top_up::Entity::find_by_id(id)
+        .one(db)
+        .await
+        .map_err(|_| AwsError::UnknownServerError)?
+        .ok_or(AwsError::TopUpNotFound(id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{entities, testing};
+
+    #[tokio::test]
+    async fn test_top_ups_are_credited_once_settled() {
+        let db = testing::database().await;
+        let wallet = testing::wallet(&db, "user", 0).await;
+
+        let provider = FakePaymentProvider::default();
+        let settled = create_top_up(&db, &provider, wallet.user_id, 500)
+            .await
+            .unwrap();
+        let failed = create_top_up(&db, &provider, wallet.user_id, 300)
+            .await
+            .unwrap();
+
+        let settled = refresh_top_up(&db, &provider, settled).await.unwrap();
+        assert_eq!(settled.status, "pending");
+
+        provider.complete(&settled.reference, PaymentStatus::Settled);
+        provider.complete(&failed.reference, PaymentStatus::Failed);
+
+        let settled = refresh_top_up(&db, &provider, settled).await.unwrap();
+        let failed = refresh_top_up(&db, &provider, failed).await.unwrap();
+        assert_eq!(settled.status, "settled");
+        assert_eq!(failed.status, "failed");
+
+        // Settled top-ups aren't credited again
+        refresh_top_up(&db, &provider, settled).await.unwrap();
+
+        let wallet = entities::wallet::Entity::find()
+            .one(&db)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(wallet.credits, 500);
+
+        let entries = entities::ledger_entry::Entity::find()
+            .all(&db)
+            .await
+            .unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].kind, "top_up");
+    }
+
+    #[test]
+    fn test_top_ups_are_disabled_without_provider() {
+        assert!(matches!(
+            Payments(None).provider(),
+            Err(AwsError::TopUpsDisabled)
+        ));
+
+        let payments = Payments(Some(Arc::new(FakePaymentProvider::default())));
+        assert_eq!(payments.provider().unwrap().name(), "fake");
+    }
+}
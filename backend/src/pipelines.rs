@@ -0,0 +1,443 @@
+//! Pipelines chain functions of the owner's modules, feeding results of
+//! steps into the parameters of later ones. Steps form a DAG and run
+//! one after the other in dependency order, with the credits of every
+//! step charged at once when the pipeline is done.
+
+use std::{
+    collections::{HashMap, HashSet},
+    time::Instant,
+};
+
+use aws_common::api::{
+    errors::AwsError,
+    requests::{ParamSource, PipelineStep},
+    responses::{PipelineResponse, PipelineRunResponse, PipelineStepResult},
+};
+use sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter};
+
+use crate::{
+    constants::PIPELINE_MAX_STEPS,
+    entities::{function, module, pipeline, wallet},
+    execution::{describe, Call, ExecutionFailure, Executor},
+    ffi::WasmFFIConverter,
+    ledger::Debit,
+};
+
+impl From<pipeline::Model> for PipelineResponse {
+    fn from(pipeline: pipeline::Model) -> Self {
+        Self {
+            id: pipeline.id,
+            name: pipeline.name,
+            steps: serde_json::from_str(&pipeline.steps).unwrap_or_default(),
+        }
+    }
+}
+
+/// Indices of `steps` in the order they run, steps with no dependency
+/// between them keeping their declared order
+pub fn execution_order(steps: &[PipelineStep]) -> Result<Vec<usize>, AwsError> {
+    let invalid = |reason: String| AwsError::InvalidPipeline(reason);
+
+    if steps.is_empty() || steps.len() > PIPELINE_MAX_STEPS {
+        return Err(invalid(format!("expected 1 to {PIPELINE_MAX_STEPS} steps")));
+    }
+
+    let mut indices = HashMap::new();
+
+    for (i, step) in steps.iter().enumerate() {
+        if indices.insert(step.id.as_str(), i).is_some() {
+            return Err(invalid(format!("duplicate step {}", step.id)));
+        }
+    }
+
+    let mut dependencies = Vec::with_capacity(steps.len());
+
+    for step in steps {
+        let mut on = HashSet::new();
+
+        for source in &step.params {
+            if let ParamSource::Output { step: from, .. } = source {
+                match indices.get(from.as_str()) {
+                    Some(_) if *from == step.id => {
+                        return Err(invalid(format!("step {from} depends on itself")))
+                    }
+                    Some(&i) => on.insert(i),
+                    None => return Err(invalid(format!("unknown step {from}"))),
+                };
+            }
+        }
+
+        dependencies.push(on);
+    }
+
+    let mut order = Vec::with_capacity(steps.len());
+    let mut done = vec![false; steps.len()];
+
+    while order.len() < steps.len() {
+        let next = (0..steps.len())
+            .find(|&i| !done[i] && dependencies[i].iter().all(|&d| done[d]))
+            .ok_or_else(|| invalid("steps depend on each other".to_string()))?;
+
+        done[next] = true;
+        order.push(next);
+    }
+
+    Ok(order)
+}
+
+/// Module and function of every step, checking the owner may call them
+/// and that parameters match their signatures
+pub async fn resolve(
+    db: &DatabaseConnection,
+    owner_id: i32,
+    steps: &[PipelineStep],
+) -> Result<Vec<(module::Model, function::Model)>, AwsError> {
+    let mut resolved: Vec<(module::Model, function::Model)> = Vec::with_capacity(steps.len());
+
+    for step in steps {
+        let module = module::Entity::find_by_id(step.module_id)
+            .filter(module::Column::OwnerId.eq(owner_id))
+            .one(db)
+            .await
+            .map_err(|_| AwsError::UnknownServerError)?
+            .ok_or(AwsError::EndpointNotFound(step.module_id))?;
+
+        let function = function::Entity::find()
+            .filter(function::Column::ModuleId.eq(module.id))
+            .filter(function::Column::Name.eq(&step.function))
+            .one(db)
+            .await
+            .map_err(|_| AwsError::UnknownServerError)?
+            .ok_or_else(|| AwsError::FunctionNotFound(step.function.clone()))?;
+
+        let expected = function.get_param_types()?.len();
+
+        if step.params.len() != expected {
+            return Err(AwsError::InvalidPipeline(format!(
+                "step {} takes {expected} params, got {}",
+                step.id,
+                step.params.len()
+            )));
+        }
+
+        resolved.push((module, function));
+    }
+
+    for step in steps {
+        for source in &step.params {
+            let ParamSource::Output { step: from, index } = source else {
+                continue;
+            };
+
+            // `execution_order` already checked the step exists
+            let Some(i) = steps.iter().position(|s| s.id == *from) else {
+                continue;
+            };
+
+            if *index >= resolved[i].1.get_ret_types()?.len() {
+                return Err(AwsError::InvalidPipeline(format!(
+                    "step {from} has no result {index}"
+                )));
+            }
+        }
+    }
+
+    Ok(resolved)
+}
+
+/// Runs the steps within a single deadline out of a single
+/// reservation, charging the credits used by every step that ran once,
+/// even when a step fails
+pub async fn run(
+    executor: &Executor,
+    wallet: wallet::Model,
+    steps: &[PipelineStep],
+    params: &[serde_json::Value],
+    timeout_ms: Option<u64>,
+    max_credits: Option<u64>,
+) -> Result<PipelineRunResponse, AwsError> {
+    let order = execution_order(steps)?;
+    let resolved = resolve(&executor.db, wallet.user_id, steps).await?;
+    let deadline = Instant::now() + executor.limits.timeout(timeout_ms);
+    let modules = resolved
+        .iter()
+        .map(|(module, _)| module.id)
+        .collect::<Vec<_>>();
+    let reservation = executor
+        .reserve(
+            wallet.user_id,
+            &modules,
+            executor.limits.reservation(max_credits, steps.len()),
+        )
+        .await?;
+
+    let mut outputs: HashMap<&str, Vec<serde_json::Value>> = HashMap::new();
+    let mut results = Vec::with_capacity(steps.len());
+    let mut debits = Vec::with_capacity(steps.len());
+    let mut used_credits = 0u64;
+    let mut failure = None;
+
+    for i in order {
+        let step = &steps[i];
+        let (module, function) = resolved[i].clone();
+        let started = Instant::now();
+
+        let step_params = step
+            .params
+            .iter()
+            .map(|source| match source {
+                ParamSource::Value(v) => Ok(v.clone()),
+                ParamSource::Input(index) => params.get(*index).cloned().ok_or_else(|| {
+                    AwsError::InvalidPipeline(format!("missing pipeline param {index}"))
+                }),
+                ParamSource::Output { step, index } => Ok(outputs[step.as_str()][*index].clone()),
+            })
+            .collect::<Result<Vec<_>, _>>();
+
+        let outcome = match step_params.and_then(|p| function.to_abi_params(&p)) {
+            Ok(abi_params) => {
+                let credits = reservation.amount.saturating_sub(used_credits);
+                let remaining = deadline.saturating_duration_since(Instant::now());
+
+                executor
+                    .run(
+                        Call {
+                            wallet: wallet.clone(),
+                            module,
+                            function: function.clone(),
+                            params: abi_params,
+                            wasi: None,
+                            timeout_ms: None,
+                            max_credits: None,
+                        },
+                        credits,
+                        remaining,
+                    )
+                    .await
+            }
+            Err(error) => Err(ExecutionFailure::from(error)),
+        };
+
+        let used = match &outcome {
+            Ok(execution) => execution.used_credits,
+            Err(failure) => failure.used_credits,
+        };
+
+        used_credits += used;
+        debits.push(Debit::new(&function, used, started));
+
+        match outcome {
+            Ok(execution) => {
+                outputs.insert(&step.id, execution.return_value.clone());
+                results.push(PipelineStepResult {
+                    id: step.id.clone(),
+                    return_value: execution.return_value,
+                    logs: execution.logs,
+                    used_credits: execution.used_credits,
+                });
+            }
+            Err(ExecutionFailure { error, .. }) => {
+                failure = Some((i, step.id.clone(), error));
+                break;
+            }
+        }
+    }
+
+    executor.settle_debits(reservation, &debits).await?;
+
+    if let Some((index, step, error)) = failure {
+        let (status, message) = describe(error).await;
+
+        return Err(AwsError::PipelineStepFailed {
+            index,
+            step,
+            status,
+            message,
+            steps: results,
+            used_credits,
+        });
+    }
+
+    Ok(PipelineRunResponse {
+        steps: results,
+        used_credits,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cache::ModuleCache, entities, execution::ExecutionLimits, testing};
+    use std::sync::Arc;
+
+    const MODULE: &str = r#"
+        (module
+            (func (export "add") (param i32 i32) (result i32)
+                (i32.add (local.get 0) (local.get 1)))
+            (func (export "double") (param i32) (result i32)
+                (i32.mul (local.get 0) (i32.const 2)))
+            (func (export "fail") (param i32) (result i32)
+                (block (drop (i32.mul (local.get 0) (local.get 0))))
+                (unreachable)))
+    "#;
+
+    fn step(id: &str, function: &str, params: Vec<ParamSource>) -> PipelineStep {
+        PipelineStep {
+            id: id.to_string(),
+            module_id: 1,
+            function: function.to_string(),
+            params,
+        }
+    }
+
+    fn output(step: &str) -> ParamSource {
+        ParamSource::Output {
+            step: step.to_string(),
+            index: 0,
+        }
+    }
+
+    #[test]
+    fn test_steps_run_after_their_dependencies() {
+        let steps = [
+            step("c", "add", vec![output("a"), output("b")]),
+            step("a", "double", vec![ParamSource::Input(0)]),
+            step("b", "double", vec![output("a")]),
+        ];
+
+        assert_eq!(execution_order(&steps).unwrap(), vec![1, 2, 0]);
+
+        let cycle = [
+            step("a", "double", vec![output("b")]),
+            step("b", "double", vec![output("a")]),
+        ];
+
+        assert!(matches!(
+            execution_order(&cycle),
+            Err(AwsError::InvalidPipeline(_))
+        ));
+        assert!(execution_order(&[step("a", "double", vec![output("a")])]).is_err());
+        assert!(execution_order(&[step("a", "double", vec![output("x")])]).is_err());
+        assert!(execution_order(&[]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_pipeline_is_charged_once() {
+        let db = testing::database().await;
+        let wallet = testing::wallet(&db, "user", 1_000).await;
+
+        let module = testing::module(&db, wallet.user_id, MODULE).await;
+
+        for (name, signature) in [("add", "i32,i32->i32"), ("double", "i32->i32")] {
+            testing::function(&db, module.id, name, signature).await;
+        }
+
+        let executor = Executor {
+            db: Arc::new(db),
+            cache: ModuleCache::default(),
+            limits: ExecutionLimits::default(),
+        };
+
+        let steps = [
+            step(
+                "sum",
+                "add",
+                vec![ParamSource::Input(0), ParamSource::Input(1)],
+            ),
+            step("doubled", "double", vec![output("sum")]),
+        ];
+
+        let response = run(
+            &executor,
+            wallet,
+            &steps,
+            &[serde_json::json!(1), serde_json::json!(2)],
+            None,
+            None,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(response.steps.len(), 2);
+        assert_eq!(response.steps[1].return_value, vec![serde_json::json!(6)]);
+        assert_eq!(
+            response.used_credits,
+            response.steps.iter().map(|s| s.used_credits).sum::<u64>()
+        );
+
+        let wallet = entities::wallet::Entity::find()
+            .one(&*executor.db)
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(wallet.credits as u64, 1_000 - response.used_credits);
+
+        let wrong_arity = [step("sum", "add", vec![ParamSource::Input(0)])];
+
+        assert!(matches!(
+            resolve(&executor.db, wallet.user_id, &wrong_arity).await,
+            Err(AwsError::InvalidPipeline(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_failed_step_returns_the_steps_that_ran() {
+        let db = testing::database().await;
+        let wallet = testing::wallet(&db, "user", 1_000).await;
+
+        let module = testing::module(&db, wallet.user_id, MODULE).await;
+
+        for (name, signature) in [("double", "i32->i32"), ("fail", "i32->i32")] {
+            testing::function(&db, module.id, name, signature).await;
+        }
+
+        let executor = Executor {
+            db: Arc::new(db),
+            cache: ModuleCache::default(),
+            limits: ExecutionLimits::default(),
+        };
+
+        let steps = [
+            step("doubled", "double", vec![ParamSource::Input(0)]),
+            step("failed", "fail", vec![output("doubled")]),
+            step("never", "double", vec![output("failed")]),
+        ];
+
+        let failure = run(
+            &executor,
+            wallet,
+            &steps,
+            &[serde_json::json!(2)],
+            None,
+            None,
+        )
+        .await
+        .err()
+        .unwrap();
+
+        let AwsError::PipelineStepFailed {
+            index,
+            step,
+            steps: ran,
+            used_credits,
+            ..
+        } = failure
+        else {
+            panic!("expected a failed step, got {failure:?}");
+        };
+
+        assert_eq!((index, step.as_str()), (1, "failed"));
+        assert_eq!(ran.len(), 1);
+        assert_eq!(ran[0].return_value, vec![serde_json::json!(4)]);
+        assert!(used_credits > ran[0].used_credits);
+
+        let wallet = entities::wallet::Entity::find()
+            .one(&*executor.db)
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(wallet.credits as u64, 1_000 - used_credits);
+    }
+}
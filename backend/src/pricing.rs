@@ -0,0 +1,208 @@
+//! What calls are charged, in metering points. The pricing is read from
+//! a TOML file and reloaded whenever the file changes, calls using the
+//! pricing in effect when they start. Opcode weights are compiled into
+//! modules, so changing them recompiles modules on their next call.
+
+use std::{
+    collections::BTreeMap,
+    path::PathBuf,
+    sync::{Arc, RwLock},
+    time::{Duration, SystemTime},
+};
+
+use lazy_static::lazy_static;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use wasmer::{wasmparser::Operator, WASM_PAGE_SIZE};
+
+use crate::constants::{
+    CALL_BASE_FEE, HOST_CALL_BYTE_COST, HOST_CALL_COST, MEMORY_PAGE_SECOND_COST,
+    PRICING_RELOAD_INTERVAL, WASI_SYSCALL_COST,
+};
+
+lazy_static! {
+    static ref PRICING: RwLock<Arc<Pricing>> = RwLock::new(Arc::new(Pricing::default()));
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Pricing {
+    /// Points per operator, by name as in `I32Load`
+    pub opcodes: BTreeMap<String, u64>,
+    /// Points of operators without a weight of their own
+    pub default_opcode: u64,
+    /// Points charged for every call on top of what it runs
+    pub call_base_fee: u64,
+    /// Points per page of memory held by a call for a second
+    pub memory_page_second: u64,
+    pub host_call: u64,
+    pub host_call_byte: u64,
+    pub wasi_syscall: u64,
+}
+
+impl Default for Pricing {
+    fn default() -> Self {
+        let opcodes = [
+            ("LocalGet", 1),
+            ("LocalSet", 2),
+            ("LocalTee", 3),
+            ("GlobalGet", 4),
+            ("GlobalSet", 5),
+            ("I32Load", 6),
+            ("I64Load", 7),
+            ("F32Load", 8),
+            ("F64Load", 9),
+        ];
+
+        Self {
+            opcodes: opcodes
+                .into_iter()
+                .map(|(name, points)| (name.to_string(), points))
+                .collect(),
+            default_opcode: 1,
+            call_base_fee: CALL_BASE_FEE,
+            memory_page_second: MEMORY_PAGE_SECOND_COST,
+            host_call: HOST_CALL_COST,
+            host_call_byte: HOST_CALL_BYTE_COST,
+            wasi_syscall: WASI_SYSCALL_COST,
+        }
+    }
+}
+
+impl Pricing {
+    pub fn from_toml(toml: &str) -> Result<Self, toml::de::Error> {
+        toml::from_str(toml)
+    }
+
+    pub fn opcode_cost(&self, op: &Operator) -> u64 {
+        if self.opcodes.is_empty() {
+            return self.default_opcode;
+        }
+
+        // Operators print as their name followed by their immediates
+        let op = format!("{op:?}");
+        let name = op.split(' ').next().unwrap_or_default();
+
+        self.opcodes
+            .get(name)
+            .copied()
+            .unwrap_or(self.default_opcode)
+    }
+
+    /// Points for holding `memory_bytes` of memory during `elapsed`
+    pub fn memory_cost(&self, memory_bytes: u64, elapsed: Duration) -> u64 {
+        let pages = memory_bytes / WASM_PAGE_SIZE as u64;
+        let cost = pages as u128 * self.memory_page_second as u128 * elapsed.as_millis() / 1_000;
+
+        u64::try_from(cost).unwrap_or(u64::MAX)
+    }
+
+    /// Identifies the opcode weights, modules compiled with other
+    /// weights can't be reused
+    pub fn opcodes_id(&self) -> u64 {
+        let mut hasher = Sha256::new();
+
+        for (name, points) in &self.opcodes {
+            hasher.update(name.as_bytes());
+            hasher.update(points.to_le_bytes());
+        }
+
+        hasher.update(self.default_opcode.to_le_bytes());
+
+        let digest = hasher.finalize();
+        u64::from_le_bytes(digest[..8].try_into().unwrap())
+    }
+}
+
+/// Pricing in effect
+pub fn current() -> Arc<Pricing> {
+    PRICING.read().unwrap().clone()
+}
+
+pub fn set(pricing: Pricing) {
+    *PRICING.write().unwrap() = Arc::new(pricing);
+}
+
+/// Reloads the pricing whenever its file is modified, keeping the
+/// previous one while the file is invalid
+pub struct PricingWatcher {
+    pub path: PathBuf,
+}
+
+impl PricingWatcher {
+    pub fn load(&self) -> anyhow::Result<Pricing> {
+        let toml = std::fs::read_to_string(&self.path)?;
+
+        Ok(Pricing::from_toml(&toml)?)
+    }
+
+    fn modified(&self) -> Option<SystemTime> {
+        std::fs::metadata(&self.path)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+    }
+
+    /// Loads the pricing once, then watches for changes
+    pub fn start(self) -> anyhow::Result<()> {
+        let mut modified = self.modified();
+        set(self.load()?);
+
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(PRICING_RELOAD_INTERVAL).await;
+
+                let now = self.modified();
+
+                if now == modified {
+                    continue;
+                }
+
+                modified = now;
+
+                match self.load() {
+                    Ok(pricing) => {
+                        tracing::info!("Reloaded pricing from {}", self.path.display());
+                        set(pricing);
+                    }
+                    Err(e) => tracing::error!("Invalid pricing {}: {e:#}", self.path.display()),
+                }
+            }
+        });
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pricing_is_read_from_toml() {
+        let pricing = Pricing::from_toml(
+            r#"
+            default_opcode = 2
+            call_base_fee = 50
+            memory_page_second = 10
+
+            [opcodes]
+            I32Add = 3
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(pricing.opcode_cost(&Operator::I32Add), 3);
+        assert_eq!(
+            pricing.opcode_cost(&Operator::LocalGet { local_index: 0 }),
+            2
+        );
+        assert_eq!(pricing.call_base_fee, 50);
+        assert_eq!(pricing.host_call, HOST_CALL_COST);
+        assert_ne!(pricing.opcodes_id(), Pricing::default().opcodes_id());
+
+        let pages = 4 * WASM_PAGE_SIZE as u64;
+        assert_eq!(pricing.memory_cost(pages, Duration::from_millis(1_500)), 60);
+
+        assert!(Pricing::from_toml("call_fee = 1").is_err());
+    }
+}
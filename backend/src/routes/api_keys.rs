@@ -0,0 +1,64 @@
+use aws_common::api::{
+    errors::AwsError,
+    requests::CreateApiKeyBody,
+    responses::{ApiKeysResponse, CreatedApiKeyResponse},
+};
+use axum::{extract::Path, http::StatusCode, Extension};
+use sea_orm::{ColumnTrait, EntityTrait, QueryFilter};
+
+use crate::{
+    auth::{api_keys, jwt::ClaimsExtract},
+    entities::api_key,
+    utils::DbConn,
+};
+
+/// Creates an API key, only ever returned by this request
+pub async fn create_api_key(
+    ClaimsExtract(claims): ClaimsExtract,
+    Extension(DbConn(db)): Extension<DbConn>,
+    axum::extract::Json(body): axum::extract::Json<CreateApiKeyBody>,
+) -> Result<(StatusCode, axum::Json<CreatedApiKeyResponse>), AwsError> {
+    let (key, model) = api_keys::create(&db, claims.uid, &body.name, &body.scopes).await?;
+
+    Ok((
+        StatusCode::CREATED,
+        axum::Json::from(CreatedApiKeyResponse {
+            key,
+            api_key: model.into(),
+        }),
+    ))
+}
+
+pub async fn get_api_keys(
+    ClaimsExtract(claims): ClaimsExtract,
+    Extension(DbConn(db)): Extension<DbConn>,
+) -> Result<axum::Json<ApiKeysResponse>, AwsError> {
+    let keys = api_key::Entity::find()
+        .filter(api_key::Column::UserId.eq(claims.uid))
+        .all(&*db)
+        .await
+        .map_err(|_| AwsError::UnknownServerError)?;
+
+    Ok(axum::Json::from(ApiKeysResponse {
+        api_keys: keys.into_iter().map(Into::into).collect(),
+    }))
+}
+
+/// Revokes an API key, requests using it failing right away
+pub async fn delete_api_key(
+    ClaimsExtract(claims): ClaimsExtract,
+    Extension(DbConn(db)): Extension<DbConn>,
+    Path(id): Path<i32>,
+) -> Result<(), AwsError> {
+    let res = api_key::Entity::delete_many()
+        .filter(api_key::Column::Id.eq(id))
+        .filter(api_key::Column::UserId.eq(claims.uid))
+        .exec(&*db)
+        .await
+        .map_err(|_| AwsError::UnknownServerError)?;
+
+    match res.rows_affected {
+        0 => Err(AwsError::ApiKeyNotFound(id)),
+        _ => Ok(()),
+    }
+}
@@ -0,0 +1,151 @@
+use aws_common::api::{
+    errors::AwsError,
+    requests::{BudgetWebhookBody, SpendingLimitBody},
+    responses::{SpendingLimitResponse, SpendingLimitsResponse},
+};
+use axum::{extract::Path, Extension};
+use sea_orm::{ActiveModelTrait, ActiveValue, ColumnTrait, EntityTrait, QueryFilter};
+
+use crate::{
+    auth::jwt::ClaimsExtract,
+    budgets::{limit_response, resolve_webhook, spent, Period},
+    constants::BUDGET_ALERT_PERCENT_DEFAULT,
+    entities::{module, spending_limit, user},
+    utils::DbConn,
+};
+
+pub async fn get_spending_limits(
+    ClaimsExtract(claims): ClaimsExtract,
+    Extension(DbConn(db)): Extension<DbConn>,
+) -> Result<axum::Json<SpendingLimitsResponse>, AwsError> {
+    let user = user::Entity::find_by_id(claims.uid)
+        .one(&*db)
+        .await
+        .map_err(|_| AwsError::UnknownServerError)?
+        .ok_or(AwsError::Unauthorized)?;
+
+    let limits = spending_limit::Entity::find()
+        .filter(spending_limit::Column::UserId.eq(claims.uid))
+        .all(&*db)
+        .await
+        .map_err(|_| AwsError::UnknownServerError)?;
+
+    let mut responses = Vec::with_capacity(limits.len());
+
+    for limit in limits {
+        let spent = spent(&*db, &limit)
+            .await
+            .map_err(|_| AwsError::UnknownServerError)?;
+
+        responses.push(limit_response(&limit, spent));
+    }
+
+    Ok(axum::Json::from(SpendingLimitsResponse {
+        limits: responses,
+        webhook_url: user.budget_webhook_url,
+    }))
+}
+
+/// Sets the limit of a module, or of all calls, for a period
+pub async fn set_spending_limit(
+    ClaimsExtract(claims): ClaimsExtract,
+    Extension(DbConn(db)): Extension<DbConn>,
+    axum::extract::Json(body): axum::extract::Json<SpendingLimitBody>,
+) -> Result<axum::Json<SpendingLimitResponse>, AwsError> {
+    let period = body.period.parse::<Period>()?;
+
+    let alert_percent = match body.alert_percent.unwrap_or(BUDGET_ALERT_PERCENT_DEFAULT) {
+        0 => None,
+        percent @ 1..=100 => Some(percent as i32),
+        _ => {
+            return Err(AwsError::InvalidSpendingLimit(
+                "alert_percent must be 0 to 100".to_string(),
+            ))
+        }
+    };
+
+    let max_credits = i64::try_from(body.max_credits)
+        .map_err(|_| AwsError::InvalidSpendingLimit("max_credits is out of range".to_string()))?;
+
+    if let Some(module_id) = body.module_id {
+        module::Entity::find_by_id(module_id)
+            .filter(module::Column::OwnerId.eq(claims.uid))
+            .one(&*db)
+            .await
+            .map_err(|_| AwsError::UnknownServerError)?
+            .ok_or(AwsError::EndpointNotFound(module_id))?;
+    }
+
+    let existing = spending_limit::Entity::find()
+        .filter(spending_limit::Column::UserId.eq(claims.uid))
+        .filter(spending_limit::Column::Period.eq(period.as_str()))
+        .filter(match body.module_id {
+            Some(module_id) => spending_limit::Column::ModuleId.eq(module_id),
+            None => spending_limit::Column::ModuleId.is_null(),
+        })
+        .one(&*db)
+        .await
+        .map_err(|_| AwsError::UnknownServerError)?;
+
+    let limit = spending_limit::ActiveModel {
+        id: existing.map_or(ActiveValue::NotSet, |limit| {
+            ActiveValue::unchanged(limit.id)
+        }),
+        user_id: ActiveValue::set(claims.uid),
+        module_id: ActiveValue::set(body.module_id),
+        period: ActiveValue::set(period.as_str().to_string()),
+        max_credits: ActiveValue::set(max_credits),
+        alert_percent: ActiveValue::set(alert_percent),
+        alerted_at: ActiveValue::set(None),
+    }
+    .save(&*db)
+    .await
+    .map_err(|_| AwsError::UnknownServerError)?;
+
+    let limit = spending_limit::Model::try_from(limit).map_err(|_| AwsError::UnknownServerError)?;
+    let spent = spent(&*db, &limit)
+        .await
+        .map_err(|_| AwsError::UnknownServerError)?;
+
+    Ok(axum::Json::from(limit_response(&limit, spent)))
+}
+
+pub async fn delete_spending_limit(
+    ClaimsExtract(claims): ClaimsExtract,
+    Extension(DbConn(db)): Extension<DbConn>,
+    Path(id): Path<i32>,
+) -> Result<(), AwsError> {
+    let res = spending_limit::Entity::delete_many()
+        .filter(spending_limit::Column::Id.eq(id))
+        .filter(spending_limit::Column::UserId.eq(claims.uid))
+        .exec(&*db)
+        .await
+        .map_err(|_| AwsError::UnknownServerError)?;
+
+    match res.rows_affected {
+        0 => Err(AwsError::SpendingLimitNotFound(id)),
+        _ => Ok(()),
+    }
+}
+
+/// Sets the URL budget alerts are posted to
+pub async fn set_budget_webhook(
+    ClaimsExtract(claims): ClaimsExtract,
+    Extension(DbConn(db)): Extension<DbConn>,
+    axum::extract::Json(body): axum::extract::Json<BudgetWebhookBody>,
+) -> Result<(), AwsError> {
+    if let Some(url) = &body.url {
+        resolve_webhook(url).await?;
+    }
+
+    user::ActiveModel {
+        id: ActiveValue::unchanged(claims.uid),
+        budget_webhook_url: ActiveValue::set(body.url),
+        ..Default::default()
+    }
+    .update(&*db)
+    .await
+    .map_err(|_| AwsError::UnknownServerError)?;
+
+    Ok(())
+}
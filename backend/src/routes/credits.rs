@@ -0,0 +1,119 @@
+use aws_common::api::{
+    errors::AwsError,
+    requests::{CreditsBody, TransferBody},
+    responses::{GetCreditsResponse, TopUpResponse},
+};
+use axum::{extract::Path, http::StatusCode, Extension};
+use sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter};
+
+use crate::{
+    auth::jwt::ClaimsExtract,
+    credits::{grant, transfer},
+    entities::{top_up, wallet},
+    extractors::AdminExtract,
+    payments::{create_top_up, refresh_top_up, Payments},
+    utils::DbConn,
+};
+
+async fn remaining_credits(
+    db: &DatabaseConnection,
+    user_id: i32,
+) -> Result<axum::Json<GetCreditsResponse>, AwsError> {
+    let wallet = find_wallet(db, user_id).await?;
+
+    Ok(axum::Json::from(GetCreditsResponse {
+        credits: wallet.credits,
+    }))
+}
+
+async fn find_wallet(db: &DatabaseConnection, user_id: i32) -> Result<wallet::Model, AwsError> {
+    wallet::Entity::find()
+        .filter(wallet::Column::UserId.eq(user_id))
+        .one(db)
+        .await
+        .map_err(|_| AwsError::UnknownServerError)?
+        .ok_or_else(|| AwsError::UserNotFound(user_id.to_string()))
+}
+
+pub async fn grant_credits(
+    AdminExtract(admin): AdminExtract,
+    Extension(DbConn(db)): Extension<DbConn>,
+    Path(user_id): Path<i32>,
+    axum::extract::Json(body): axum::extract::Json<CreditsBody>,
+) -> Result<axum::Json<GetCreditsResponse>, AwsError> {
+    find_wallet(&db, user_id).await?;
+    grant(&db, user_id, body.amount, false).await?;
+
+    tracing::info!(
+        "Admin {} granted {} credits to {user_id}",
+        admin.id,
+        body.amount
+    );
+
+    remaining_credits(&db, user_id).await
+}
+
+pub async fn revoke_credits(
+    AdminExtract(admin): AdminExtract,
+    Extension(DbConn(db)): Extension<DbConn>,
+    Path(user_id): Path<i32>,
+    axum::extract::Json(body): axum::extract::Json<CreditsBody>,
+) -> Result<axum::Json<GetCreditsResponse>, AwsError> {
+    find_wallet(&db, user_id).await?;
+    grant(&db, user_id, body.amount, true).await?;
+
+    tracing::info!(
+        "Admin {} revoked {} credits of {user_id}",
+        admin.id,
+        body.amount
+    );
+
+    remaining_credits(&db, user_id).await
+}
+
+pub async fn transfer_credits(
+    ClaimsExtract(claims): ClaimsExtract,
+    Extension(DbConn(db)): Extension<DbConn>,
+    axum::extract::Json(body): axum::extract::Json<TransferBody>,
+) -> Result<axum::Json<GetCreditsResponse>, AwsError> {
+    transfer(&db, claims.uid, &body.to, body.amount).await?;
+
+    remaining_credits(&db, claims.uid).await
+}
+
+/// Starts buying credits, added to the wallet once the payment settled
+pub async fn top_up_credits(
+    ClaimsExtract(claims): ClaimsExtract,
+    Extension(DbConn(db)): Extension<DbConn>,
+    Extension(payments): Extension<Payments>,
+    axum::extract::Json(body): axum::extract::Json<CreditsBody>,
+) -> Result<(StatusCode, axum::Json<TopUpResponse>), AwsError> {
+    let top_up = create_top_up(&db, payments.provider()?, claims.uid, body.amount).await?;
+
+    Ok((
+        StatusCode::CREATED,
+        axum::Json::from(TopUpResponse::from(top_up)),
+    ))
+}
+
+pub async fn get_top_up(
+    ClaimsExtract(claims): ClaimsExtract,
+    Extension(DbConn(db)): Extension<DbConn>,
+    Extension(payments): Extension<Payments>,
+    Path(id): Path<i32>,
+) -> Result<axum::Json<TopUpResponse>, AwsError> {
+    let top_up = top_up::Entity::find_by_id(id)
+        .filter(top_up::Column::UserId.eq(claims.uid))
+        .one(&*db)
+        .await
+        .map_err(|_| AwsError::UnknownServerError)?
+        .ok_or(AwsError::TopUpNotFound(id))?;
+
+    // Top-ups made before they were disabled are shown as last known
+    let top_up = match payments.provider() {
+        Ok(provider) => refresh_top_up(&db, provider, top_up).await?,
+        Err(_) => top_up,
+    };
+
+    Ok(axum::Json::from(TopUpResponse::from(top_up)))
+}
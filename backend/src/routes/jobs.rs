@@ -0,0 +1,94 @@
+use aws_common::api::{errors::AwsError, responses::JobResponse};
+use axum::{extract::Path, Extension};
+use sea_orm::{ColumnTrait, EntityTrait, QueryFilter};
+
+use crate::{
+    auth::api_keys::Scope,
+    entities::{function, job},
+    extractors::Caller,
+    utils::DbConn,
+};
+
+/// Job of the caller. Keys without the read scope may poll the jobs of
+/// the modules they invoke, i.e. the jobs they could have enqueued
+pub async fn get_job(
+    caller: Caller,
+    Extension(DbConn(db)): Extension<DbConn>,
+    Path(id): Path<i32>,
+) -> Result<axum::Json<JobResponse>, AwsError> {
+    let job = job::Entity::find_by_id(id)
+        .filter(job::Column::OwnerId.eq(caller.uid))
+        .one(&*db)
+        .await
+        .map_err(|_| AwsError::UnknownServerError)?
+        .ok_or(AwsError::JobNotFound(id))?;
+
+    if caller.require(Scope::Read).is_err() {
+        let function = function::Entity::find_by_id(job.function_id)
+            .one(&*db)
+            .await
+            .map_err(|_| AwsError::UnknownServerError)?
+            .ok_or(AwsError::Forbidden)?;
+
+        caller.require(Scope::Invoke(function.module_id))?;
+    }
+
+    Ok(axum::Json::from(JobResponse::from(job)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        jobs::{enqueue, NewJob},
+        testing,
+    };
+    use std::sync::Arc;
+
+    const MODULE: &str = r#"(module (func (export "one") (result i32) (i32.const 1)))"#;
+
+    #[tokio::test]
+    async fn test_invoke_keys_poll_the_jobs_of_their_modules() {
+        let db = testing::database().await;
+        let uid = testing::wallet(&db, "user", 0).await.user_id;
+        let module = testing::module(&db, uid, MODULE).await;
+        let function = testing::function(&db, module.id, "one", "->i32").await;
+
+        let id = enqueue(
+            &db,
+            NewJob {
+                owner_id: uid,
+                function_id: function.id,
+                params: vec![],
+                wasi: None,
+                timeout_ms: None,
+                max_credits: None,
+            },
+        )
+        .await
+        .unwrap();
+
+        let db = DbConn(Arc::new(db));
+        let key = |scopes| Caller {
+            uid,
+            scopes: Some(scopes),
+        };
+
+        for scopes in [vec![Scope::Read], vec![Scope::Invoke(module.id)]] {
+            let job = get_job(key(scopes), Extension(db.clone()), Path(id))
+                .await
+                .unwrap();
+            assert_eq!(job.id, id);
+        }
+
+        assert!(matches!(
+            get_job(
+                key(vec![Scope::Invoke(module.id + 1)]),
+                Extension(db),
+                Path(id)
+            )
+            .await,
+            Err(AwsError::Forbidden)
+        ));
+    }
+}
@@ -1,5 +1,13 @@
+pub mod api_keys;
+pub mod budgets;
+pub mod credits;
 pub mod fallback;
 pub mod functions;
+pub mod jobs;
 pub mod metrics;
 pub mod modules;
+pub mod pipelines;
+pub mod run;
+pub mod schedules;
+pub mod sessions;
 pub mod user;
@@ -1,5 +1,6 @@
 use aws_common::api::{
     errors::AwsError,
+    requests::PublicModuleBody,
     responses::{
         DeployModuleResponse, DeployedFunctionResponse, DeployedModulesResponse, GetModulesResponse,
     },
@@ -12,30 +13,31 @@ use axum::{
 };
 
 use sea_orm::{
-    ActiveModelTrait, ActiveValue, ColumnTrait, DbErr, EntityTrait, QueryFilter, TransactionError,
-    TransactionTrait,
+    sea_query::Expr, ActiveModelTrait, ActiveValue, ColumnTrait, DbErr, EntityTrait, QueryFilter,
+    TransactionError, TransactionTrait,
 };
 
 use sha2::{Digest, Sha256};
 
 use crate::{
-    auth::jwt::AwsClaims, entities, extractors::ModuleHashPathParam, ffi, metrics::WASM_CODE_SIZE,
-    utils::DbConn, ModuleCache,
+    abi::exported_signatures,
+    artifacts::{compile, save_artifact},
+    auth::{api_keys::Scope, jwt::ClaimsExtract},
+    credits::check_amount,
+    entities,
+    extractors::{Caller, ModuleHashPathParam},
+    metrics::WASM_CODE_SIZE,
+    utils::{metered_store, DbConn},
+    ModuleCache,
 };
 
-fn wasmer_types_to_string(types: &[wasmer::Type]) -> Result<String, AwsError> {
-    types
-        .iter()
-        .map(|x| TryInto::<&str>::try_into(ffi::Type(*x)))
-        .collect::<Result<Vec<&str>, AwsError>>()
-        .map(|x| x.join(","))
-}
-
 pub async fn deploy_module(
-    claims: AwsClaims,
+    caller: Caller,
     Extension(DbConn(db)): Extension<DbConn>,
     data: body::Bytes,
 ) -> Result<(StatusCode, axum::Json<DeployModuleResponse>), AwsError> {
+    caller.require(Scope::Deploy)?;
+
     let code = data.to_vec();
     let code_len = code.len();
 
@@ -43,41 +45,32 @@ pub async fn deploy_module(
 
     let inside_hash = code_hash.clone();
 
-    let engine = wasmer::Store::default();
-    let module =
-        wasmer::Module::from_binary(&engine, &code).map_err(|_| AwsError::InvalidWasmModule)?;
+    // Compile ahead of time with the engine calls run on, so they only
+    // have to load the persisted artifact
+    let (module, artifact) = compile(&metered_store(), &code)?;
 
-    let mut exports = module
-        .exports()
-        .filter_map(|x| {
-            let name = x.name().to_string();
-            let fnc = x.ty().func()?.clone();
-
-            Some((name, fnc))
+    let mut exports = exported_signatures(&module)?
+        .into_iter()
+        .map(|(name, signature)| entities::function::ActiveModel {
+            name: ActiveValue::set(name),
+            signature: ActiveValue::set(signature.to_string()),
+            ..Default::default()
         })
-        .map(|(name, fnc)| {
-            let p = wasmer_types_to_string(fnc.params())?;
-            let r = wasmer_types_to_string(fnc.results())?;
-
-            Ok(entities::function::ActiveModel {
-                name: ActiveValue::set(name),
-                signature: ActiveValue::set(format!("{p}->{r}")),
-                ..Default::default()
-            })
-        })
-        .collect::<Result<Vec<_>, AwsError>>()?;
+        .collect::<Vec<_>>();
 
     db.transaction::<_, _, DbErr>(|txn| {
         Box::pin(async move {
             let added_endpoint = entities::module::ActiveModel {
-                owner_id: ActiveValue::set(claims.uid),
+                owner_id: ActiveValue::set(caller.uid),
                 wasm_code: ActiveValue::set(code),
-                code_hash: ActiveValue::set(inside_hash),
+                code_hash: ActiveValue::set(inside_hash.clone()),
                 ..Default::default()
             }
             .save(txn)
             .await?;
 
+            save_artifact(txn, inside_hash, artifact.to_vec()).await?;
+
             for e in exports.iter_mut() {
                 e.module_id = added_endpoint.id.clone();
             }
@@ -103,11 +96,13 @@ pub async fn deploy_module(
 }
 
 pub async fn get_deployed_modules(
-    claims: AwsClaims,
+    caller: Caller,
     Extension(DbConn(db)): Extension<DbConn>,
 ) -> Result<axum::Json<DeployedModulesResponse>, AwsError> {
+    caller.require(Scope::Read)?;
+
     let modules = entities::module::Entity::find()
-        .filter(entities::module::Column::OwnerId.eq(claims.uid))
+        .filter(entities::module::Column::OwnerId.eq(caller.uid))
         .all(&*db)
         .await
         .map_err(|_| AwsError::UnknownServerError)?;
@@ -139,10 +134,40 @@ pub async fn get_deployed_modules(
     Ok(axum::Json::from(deployments))
 }
 
+/// Serves the module on `/run` with each call using at most
+/// `max_credits` of the owner, or stops serving it. Its calls are also
+/// only served while a spending limit applies to them
+pub async fn set_module_public(
+    caller: Caller,
+    Extension(DbConn(db)): Extension<DbConn>,
+    Path(ModuleHashPathParam { id }): Path<ModuleHashPathParam>,
+    axum::extract::Json(body): axum::extract::Json<PublicModuleBody>,
+) -> Result<(), AwsError> {
+    caller.require(Scope::Deploy)?;
+
+    let max_credits = body.max_credits.map(check_amount).transpose()?;
+
+    let res = entities::module::Entity::update_many()
+        .col_expr(
+            entities::module::Column::PublicCallCredits,
+            Expr::value(max_credits),
+        )
+        .filter(entities::module::Column::Id.eq(id))
+        .filter(entities::module::Column::OwnerId.eq(caller.uid))
+        .exec(&*db)
+        .await
+        .map_err(|_| AwsError::UnknownServerError)?;
+
+    match res.rows_affected {
+        0 => Err(AwsError::EndpointNotFound(id)),
+        _ => Ok(()),
+    }
+}
+
 pub async fn delete_module(
-    claims: AwsClaims,
+    ClaimsExtract(claims): ClaimsExtract,
     Extension(DbConn(db)): Extension<DbConn>,
-    Extension(_cache): Extension<ModuleCache>,
+    Extension(cache): Extension<ModuleCache>,
     Path(ModuleHashPathParam { id }): Path<ModuleHashPathParam>,
     uri: Uri,
 ) -> Result<(), AwsError> {
@@ -176,5 +201,7 @@ pub async fn delete_module(
         _ => AwsError::UnknownServerError,
     })?;
 
+    cache.remove(id).await;
+
     Ok(())
 }
@@ -0,0 +1,111 @@
+use aws_common::api::{
+    errors::AwsError,
+    requests::{CreatePipelineBody, PipelineStep, RunPipelineBody},
+    responses::{PipelineResponse, PipelineRunResponse, PipelinesResponse},
+};
+use axum::{extract::Path, http::StatusCode, Extension};
+use sea_orm::{ActiveModelTrait, ActiveValue, ColumnTrait, EntityTrait, QueryFilter};
+
+use crate::{
+    auth::{api_keys::Scope, jwt::ClaimsExtract},
+    entities::pipeline,
+    execution::Executor,
+    extractors::{Caller, WalletExtract},
+    pipelines::{execution_order, resolve, run},
+    utils::DbConn,
+};
+
+pub async fn create_pipeline(
+    ClaimsExtract(claims): ClaimsExtract,
+    Extension(DbConn(db)): Extension<DbConn>,
+    axum::extract::Json(body): axum::extract::Json<CreatePipelineBody>,
+) -> Result<(StatusCode, axum::Json<PipelineResponse>), AwsError> {
+    execution_order(&body.steps)?;
+    resolve(&db, claims.uid, &body.steps).await?;
+
+    let pipeline = pipeline::ActiveModel {
+        owner_id: ActiveValue::set(claims.uid),
+        name: ActiveValue::set(body.name),
+        steps: ActiveValue::set(
+            serde_json::to_string(&body.steps).map_err(|_| AwsError::UnknownServerError)?,
+        ),
+        ..Default::default()
+    }
+    .insert(&*db)
+    .await
+    .map_err(|_| AwsError::UnknownServerError)?;
+
+    Ok((
+        StatusCode::CREATED,
+        axum::Json::from(PipelineResponse::from(pipeline)),
+    ))
+}
+
+pub async fn get_pipelines(
+    ClaimsExtract(claims): ClaimsExtract,
+    Extension(DbConn(db)): Extension<DbConn>,
+) -> Result<axum::Json<PipelinesResponse>, AwsError> {
+    let pipelines = pipeline::Entity::find()
+        .filter(pipeline::Column::OwnerId.eq(claims.uid))
+        .all(&*db)
+        .await
+        .map_err(|_| AwsError::UnknownServerError)?;
+
+    Ok(axum::Json::from(PipelinesResponse {
+        pipelines: pipelines.into_iter().map(Into::into).collect(),
+    }))
+}
+
+pub async fn delete_pipeline(
+    ClaimsExtract(claims): ClaimsExtract,
+    Extension(DbConn(db)): Extension<DbConn>,
+    Path(id): Path<i32>,
+) -> Result<(), AwsError> {
+    let res = pipeline::Entity::delete_many()
+        .filter(pipeline::Column::Id.eq(id))
+        .filter(pipeline::Column::OwnerId.eq(claims.uid))
+        .exec(&*db)
+        .await
+        .map_err(|_| AwsError::UnknownServerError)?;
+
+    match res.rows_affected {
+        0 => Err(AwsError::PipelineNotFound(id)),
+        _ => Ok(()),
+    }
+}
+
+/// Runs every step of the pipeline in one request. A failing step
+/// stops the pipeline, the steps that ran being charged all the same
+pub async fn run_pipeline(
+    caller: Caller,
+    WalletExtract(wallet): WalletExtract,
+    Extension(executor): Extension<Executor>,
+    Path(id): Path<i32>,
+    axum::extract::Json(body): axum::extract::Json<RunPipelineBody>,
+) -> Result<axum::Json<PipelineRunResponse>, AwsError> {
+    let pipeline = pipeline::Entity::find_by_id(id)
+        .filter(pipeline::Column::OwnerId.eq(wallet.user_id))
+        .one(&*executor.db)
+        .await
+        .map_err(|_| AwsError::UnknownServerError)?
+        .ok_or(AwsError::PipelineNotFound(id))?;
+
+    let steps = serde_json::from_str::<Vec<PipelineStep>>(&pipeline.steps)
+        .map_err(|_| AwsError::UnknownServerError)?;
+
+    for step in &steps {
+        caller.require(Scope::Invoke(step.module_id))?;
+    }
+
+    let response = run(
+        &executor,
+        wallet,
+        &steps,
+        &body.params,
+        body.timeout_ms,
+        body.max_credits,
+    )
+    .await?;
+
+    Ok(axum::Json::from(response))
+}
@@ -0,0 +1,324 @@
+use std::collections::HashMap;
+
+use aws_common::api::errors::AwsError;
+use axum::{
+    body::Bytes,
+    extract::Path,
+    http::{
+        header::{AUTHORIZATION, COOKIE, PROXY_AUTHORIZATION},
+        HeaderMap, HeaderName, HeaderValue, Method, StatusCode, Uri,
+    },
+    response::{IntoResponse, Response},
+    Extension,
+};
+use base64::Engine;
+use sea_orm::{ColumnTrait, EntityTrait, QueryFilter};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    abi::{AbiType, AbiValue, Signature},
+    budgets,
+    constants::HTTP_HANDLER,
+    entities,
+    execution::{Call, Executor},
+};
+
+/// Headers carrying the caller's credentials, never handed to the guest
+const CREDENTIAL_HEADERS: [HeaderName; 3] = [AUTHORIZATION, COOKIE, PROXY_AUTHORIZATION];
+
+/// Headers of the guest's response that are dropped: hop-by-hop and
+/// framing headers are the server's to set, and cookies and origin wide
+/// policies would apply to the whole platform's origin
+const RESERVED_RESPONSE_HEADERS: [&str; 11] = [
+    "connection",
+    "content-length",
+    "keep-alive",
+    "proxy-authenticate",
+    "set-cookie",
+    "set-cookie2",
+    "strict-transport-security",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+fn is_reserved_response_header(name: &HeaderName) -> bool {
+    // Names are lowercase once parsed
+    RESERVED_RESPONSE_HEADERS.contains(&name.as_str())
+        || name.as_str().starts_with("access-control-")
+}
+
+#[derive(Deserialize)]
+pub struct RunPath {
+    id: i32,
+    #[serde(default)]
+    path: String,
+}
+
+/// Request handed to the `http_handler` export of a module
+#[derive(Serialize)]
+struct HandlerRequest {
+    method: String,
+    path: String,
+    query: Option<String>,
+    /// Repeated headers are joined with `, `, credentials are left out
+    headers: HashMap<String, String>,
+    body_base64: String,
+}
+
+/// Response returned by the `http_handler` export, with either a text
+/// `body` or a binary `body_base64`
+#[derive(Deserialize)]
+struct HandlerResponse {
+    #[serde(default = "default_status")]
+    status: u16,
+    #[serde(default)]
+    headers: HashMap<String, String>,
+    body: Option<String>,
+    body_base64: Option<String>,
+}
+
+fn default_status() -> u16 {
+    200
+}
+
+impl HandlerRequest {
+    fn new(method: Method, path: String, uri: &Uri, headers: &HeaderMap, body: &[u8]) -> Self {
+        let mut joined = HashMap::<String, String>::new();
+
+        for (name, value) in headers {
+            if CREDENTIAL_HEADERS.contains(name) {
+                continue;
+            }
+
+            let value = String::from_utf8_lossy(value.as_bytes());
+
+            joined
+                .entry(name.to_string())
+                .and_modify(|v| {
+                    v.push_str(", ");
+                    v.push_str(&value);
+                })
+                .or_insert_with(|| value.into_owned());
+        }
+
+        Self {
+            method: method.to_string(),
+            path: format!("/{path}"),
+            query: uri.query().map(str::to_string),
+            headers: joined,
+            body_base64: base64::engine::general_purpose::STANDARD.encode(body),
+        }
+    }
+}
+
+impl TryFrom<HandlerResponse> for Response {
+    type Error = AwsError;
+
+    fn try_from(handler: HandlerResponse) -> Result<Self, Self::Error> {
+        let invalid = |reason: &str| AwsError::InvalidGuestAbi(format!("http handler {reason}"));
+
+        let status = StatusCode::from_u16(handler.status).map_err(|_| invalid("status"))?;
+
+        let body = match (handler.body, handler.body_base64) {
+            (Some(body), None) => body.into_bytes(),
+            (None, Some(body)) => base64::engine::general_purpose::STANDARD
+                .decode(body)
+                .map_err(|_| invalid("body_base64"))?,
+            (None, None) => Vec::new(),
+            (Some(_), Some(_)) => return Err(invalid("body and body_base64 are exclusive")),
+        };
+
+        let mut response = (status, body).into_response();
+
+        for (name, value) in handler.headers {
+            let name = HeaderName::try_from(name).map_err(|_| invalid("header name"))?;
+            let value = HeaderValue::try_from(value).map_err(|_| invalid("header value"))?;
+
+            if is_reserved_response_header(&name) {
+                continue;
+            }
+
+            response.headers_mut().insert(name, value);
+        }
+
+        Ok(response)
+    }
+}
+
+/// Serves `/run/:id/*path` with the `http_handler` export of the module,
+/// a `json->json` function from `HandlerRequest` to `HandlerResponse`.
+/// The endpoint is public and calls are charged to the module owner, so
+/// only modules their owner made public are served, while a spending
+/// limit applies to them.
+pub async fn run_handler(
+    Path(RunPath { id, path }): Path<RunPath>,
+    Extension(executor): Extension<Executor>,
+    method: Method,
+    uri: Uri,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Response, AwsError> {
+    let module = entities::module::Entity::find_by_id(id)
+        .one(&*executor.db)
+        .await
+        .map_err(|_| AwsError::UnknownServerError)?
+        .ok_or(AwsError::EndpointNotFound(id))?;
+
+    let max_credits = module
+        .public_call_credits
+        .ok_or(AwsError::EndpointNotFound(id))? as u64;
+
+    // The allowance is checked again when reserving, this only makes
+    // sure public calls can't spend without bound
+    if budgets::allowance(&*executor.db, module.owner_id, &[module.id])
+        .await?
+        .is_none()
+    {
+        return Err(AwsError::Forbidden);
+    }
+
+    let function = entities::function::Entity::find()
+        .filter(entities::function::Column::ModuleId.eq(module.id))
+        .filter(entities::function::Column::Name.eq(HTTP_HANDLER))
+        .one(&*executor.db)
+        .await
+        .map_err(|_| AwsError::UnknownServerError)?
+        .ok_or_else(|| AwsError::FunctionNotFound(HTTP_HANDLER.to_string()))?;
+
+    let handler_signature = Signature {
+        params: vec![AbiType::Json],
+        results: vec![AbiType::Json],
+    };
+
+    if function.signature.parse::<Signature>()? != handler_signature {
+        return Err(AwsError::InvalidSignature(function.signature));
+    }
+
+    let wallet = entities::wallet::Entity::find()
+        .filter(entities::wallet::Column::UserId.eq(module.owner_id))
+        .one(&*executor.db)
+        .await
+        .map_err(|_| AwsError::UnknownServerError)?
+        .ok_or(AwsError::UnknownServerError)?;
+
+    let request = HandlerRequest::new(method, path, &uri, &headers, &body);
+    let request = serde_json::to_vec(&request).map_err(|_| AwsError::UnknownServerError)?;
+
+    let execution = executor
+        .call(Call {
+            wallet,
+            module,
+            function,
+            params: vec![AbiValue::Buffer(request)],
+            wasi: None,
+            timeout_ms: None,
+            max_credits: Some(max_credits),
+        })
+        .await?;
+
+    let response =
+        execution.return_value.into_iter().next().ok_or_else(|| {
+            AwsError::InvalidGuestAbi("http handler returned nothing".to_string())
+        })?;
+
+    let response = serde_json::from_value::<HandlerResponse>(response)
+        .map_err(|e| AwsError::InvalidGuestAbi(format!("http handler response: {e}")))?;
+
+    response.try_into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::HttpBody;
+
+    #[test]
+    fn test_request_joins_headers_without_credentials() {
+        let mut headers = HeaderMap::new();
+        headers.append("accept", HeaderValue::from_static("text/plain"));
+        headers.append("accept", HeaderValue::from_static("text/html"));
+        headers.append(AUTHORIZATION, HeaderValue::from_static("Bearer token"));
+        headers.append(COOKIE, HeaderValue::from_static("session=1"));
+
+        let uri = "/run/1/hooks/github?x=1".parse::<Uri>().unwrap();
+        let request = HandlerRequest::new(
+            Method::POST,
+            "hooks/github".to_string(),
+            &uri,
+            &headers,
+            b"hi",
+        );
+
+        assert_eq!(request.path, "/hooks/github");
+        assert_eq!(request.query.as_deref(), Some("x=1"));
+        assert_eq!(request.headers["accept"], "text/plain, text/html");
+        assert!(!request.headers.contains_key("authorization"));
+        assert!(!request.headers.contains_key("cookie"));
+        assert_eq!(request.body_base64, "aGk=");
+    }
+
+    #[tokio::test]
+    async fn test_handler_response_is_converted() {
+        let handler = serde_json::from_value::<HandlerResponse>(serde_json::json!({
+            "status": 201,
+            "headers": { "content-type": "text/plain" },
+            "body": "created",
+        }))
+        .unwrap();
+
+        let response = Response::try_from(handler).unwrap();
+
+        assert_eq!(response.status(), StatusCode::CREATED);
+        assert_eq!(response.headers()["content-type"], "text/plain");
+        assert_eq!(
+            response.into_body().data().await.unwrap().unwrap(),
+            "created"
+        );
+
+        let invalid = serde_json::from_value::<HandlerResponse>(serde_json::json!({
+            "status": 1000,
+        }))
+        .unwrap();
+
+        assert!(Response::try_from(invalid).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_handler_response_drops_reserved_headers() {
+        let handler = serde_json::from_value::<HandlerResponse>(serde_json::json!({
+            "status": 200,
+            "headers": {
+                "x-request-id": "1",
+                "set-cookie": "session=stolen",
+                "content-length": "1000",
+                "transfer-encoding": "chunked",
+                "connection": "close",
+                "keep-alive": "timeout=5",
+                "access-control-allow-origin": "*",
+                "access-control-allow-credentials": "true",
+                "strict-transport-security": "max-age=63072000",
+            },
+            "body": "ok",
+        }))
+        .unwrap();
+
+        let response = Response::try_from(handler).unwrap();
+        let headers = response.headers();
+
+        assert_eq!(headers["x-request-id"], "1");
+        for name in [
+            "set-cookie",
+            "content-length",
+            "transfer-encoding",
+            "connection",
+            "keep-alive",
+            "access-control-allow-origin",
+            "access-control-allow-credentials",
+            "strict-transport-security",
+        ] {
+            assert!(!headers.contains_key(name), "{name}");
+        }
+    }
+}
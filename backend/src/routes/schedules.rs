@@ -0,0 +1,192 @@
+use aws_common::api::{
+    errors::AwsError,
+    requests::CreateScheduleBody,
+    responses::{ScheduleResponse, ScheduleRunResponse, ScheduleRunsResponse, SchedulesResponse},
+};
+use axum::{extract::Path, http::StatusCode, Extension};
+use sea_orm::{
+    ActiveModelTrait, ActiveValue, ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter,
+    QueryOrder, QuerySelect,
+};
+
+use crate::{
+    auth::jwt::ClaimsExtract,
+    constants::SCHEDULE_RUNS_LISTED,
+    cron::CronSchedule,
+    entities::{schedule, schedule_run},
+    extractors::{Caller, ModuleFunctionExtract},
+    ffi::WasmFFIConverter,
+    scheduler::{next_run_from_now, ScheduleStatus},
+    utils::{now_ms, DbConn},
+};
+
+async fn find_schedule(
+    db: &DatabaseConnection,
+    owner_id: i32,
+    id: i32,
+) -> Result<schedule::Model, AwsError> {
+    schedule::Entity::find_by_id(id)
+        .filter(schedule::Column::OwnerId.eq(owner_id))
+        .one(db)
+        .await
+        .map_err(|_| AwsError::UnknownServerError)?
+        .ok_or(AwsError::ScheduleNotFound(id))
+}
+
+/// Schedules calls of a function. Schedules keep spending credits long
+/// after they're created, so only the user's session may create them
+pub async fn create_schedule(
+    caller: Caller,
+    ModuleFunctionExtract { module, function }: ModuleFunctionExtract,
+    Extension(DbConn(db)): Extension<DbConn>,
+    axum::extract::Json(body): axum::extract::Json<CreateScheduleBody>,
+) -> Result<(StatusCode, axum::Json<ScheduleResponse>), AwsError> {
+    caller.require_session()?;
+
+    let cron = body.cron.parse::<CronSchedule>()?;
+
+    // Rejects parameters that would make every run fail
+    function.to_abi_params(&body.params)?;
+
+    let schedule = schedule::ActiveModel {
+        owner_id: ActiveValue::set(module.owner_id),
+        function_id: ActiveValue::set(function.id),
+        cron: ActiveValue::set(cron.to_string()),
+        params: ActiveValue::set(
+            serde_json::to_string(&body.params).map_err(|_| AwsError::UnknownServerError)?,
+        ),
+        timeout_ms: ActiveValue::set(body.timeout_ms.map(|ms| ms.min(i64::MAX as u64) as i64)),
+        status: ActiveValue::set(ScheduleStatus::Active.as_str().to_string()),
+        paused_reason: ActiveValue::set(None),
+        next_run_at: ActiveValue::set(next_run_from_now(&cron)?),
+        created_at: ActiveValue::set(now_ms()),
+        ..Default::default()
+    }
+    .insert(&*db)
+    .await
+    .map_err(|_| AwsError::UnknownServerError)?;
+
+    Ok((
+        StatusCode::CREATED,
+        axum::Json::from(ScheduleResponse::from(schedule)),
+    ))
+}
+
+pub async fn get_schedules(
+    ClaimsExtract(claims): ClaimsExtract,
+    Extension(DbConn(db)): Extension<DbConn>,
+) -> Result<axum::Json<SchedulesResponse>, AwsError> {
+    let schedules = schedule::Entity::find()
+        .filter(schedule::Column::OwnerId.eq(claims.uid))
+        .all(&*db)
+        .await
+        .map_err(|_| AwsError::UnknownServerError)?;
+
+    Ok(axum::Json::from(SchedulesResponse {
+        schedules: schedules.into_iter().map(Into::into).collect(),
+    }))
+}
+
+pub async fn get_schedule_runs(
+    ClaimsExtract(claims): ClaimsExtract,
+    Extension(DbConn(db)): Extension<DbConn>,
+    Path(id): Path<i32>,
+) -> Result<axum::Json<ScheduleRunsResponse>, AwsError> {
+    let schedule = find_schedule(&db, claims.uid, id).await?;
+
+    let runs = schedule_run::Entity::find()
+        .filter(schedule_run::Column::ScheduleId.eq(schedule.id))
+        .order_by_desc(schedule_run::Column::Id)
+        .limit(SCHEDULE_RUNS_LISTED)
+        .all(&*db)
+        .await
+        .map_err(|_| AwsError::UnknownServerError)?;
+
+    Ok(axum::Json::from(ScheduleRunsResponse {
+        runs: runs.into_iter().map(ScheduleRunResponse::from).collect(),
+    }))
+}
+
+/// Reactivates a paused schedule from its next run on
+pub async fn resume_schedule(
+    ClaimsExtract(claims): ClaimsExtract,
+    Extension(DbConn(db)): Extension<DbConn>,
+    Path(id): Path<i32>,
+) -> Result<axum::Json<ScheduleResponse>, AwsError> {
+    let schedule = find_schedule(&db, claims.uid, id).await?;
+    let cron = schedule.cron.parse::<CronSchedule>()?;
+
+    let schedule = schedule::ActiveModel {
+        id: ActiveValue::unchanged(schedule.id),
+        status: ActiveValue::set(ScheduleStatus::Active.as_str().to_string()),
+        paused_reason: ActiveValue::set(None),
+        next_run_at: ActiveValue::set(next_run_from_now(&cron)?),
+        ..Default::default()
+    }
+    .update(&*db)
+    .await
+    .map_err(|_| AwsError::UnknownServerError)?;
+
+    Ok(axum::Json::from(ScheduleResponse::from(schedule)))
+}
+
+pub async fn delete_schedule(
+    ClaimsExtract(claims): ClaimsExtract,
+    Extension(DbConn(db)): Extension<DbConn>,
+    Path(id): Path<i32>,
+) -> Result<(), AwsError> {
+    let res = schedule::Entity::delete_many()
+        .filter(schedule::Column::Id.eq(id))
+        .filter(schedule::Column::OwnerId.eq(claims.uid))
+        .exec(&*db)
+        .await
+        .map_err(|_| AwsError::UnknownServerError)?;
+
+    match res.rows_affected {
+        0 => Err(AwsError::ScheduleNotFound(id)),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{auth::api_keys::Scope, testing};
+    use std::sync::Arc;
+
+    const MODULE: &str = r#"(module (func (export "one") (result i32) (i32.const 1)))"#;
+
+    #[tokio::test]
+    async fn test_api_keys_cannot_create_schedules() {
+        let db = testing::database().await;
+        let uid = testing::wallet(&db, "user", 0).await.user_id;
+        let module = testing::module(&db, uid, MODULE).await;
+        let function = testing::function(&db, module.id, "one", "->i32").await;
+
+        let db = DbConn(Arc::new(db));
+        let body = || CreateScheduleBody {
+            cron: "* * * * *".to_string(),
+            params: vec![],
+            timeout_ms: None,
+        };
+        let target = || ModuleFunctionExtract {
+            module: module.clone(),
+            function: function.clone(),
+        };
+
+        let key = Caller {
+            uid,
+            scopes: Some(vec![Scope::Invoke(module.id)]),
+        };
+        assert!(matches!(
+            create_schedule(key, target(), Extension(db.clone()), axum::Json(body())).await,
+            Err(AwsError::Forbidden)
+        ));
+
+        let session = Caller { uid, scopes: None };
+        let (status, _) = create_schedule(session, target(), Extension(db), axum::Json(body()))
+            .await
+            .unwrap();
+        assert_eq!(status, StatusCode::CREATED);
+    }
+}
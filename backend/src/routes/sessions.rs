@@ -0,0 +1,87 @@
+use aws_common::api::{
+    errors::AwsError,
+    requests::CallSessionBody,
+    responses::{CallFunctionResponse, SessionResponse, SnapshotResponse},
+};
+use axum::{extract::Path, http::StatusCode, Extension};
+
+use crate::{
+    auth::{api_keys::Scope, jwt::ClaimsExtract},
+    execution::Executor,
+    extractors::{Caller, ModuleExtractor, WalletExtract},
+    sessions::SessionStore,
+};
+
+pub async fn create_session(
+    ModuleExtractor(module): ModuleExtractor,
+    Extension(executor): Extension<Executor>,
+    Extension(sessions): Extension<SessionStore>,
+) -> Result<(StatusCode, axum::Json<SessionResponse>), AwsError> {
+    let session_id = sessions.create(&executor, module).await?;
+
+    Ok((
+        StatusCode::CREATED,
+        axum::Json::from(SessionResponse {
+            session_id,
+            ttl_ms: sessions.ttl.as_millis() as u64,
+        }),
+    ))
+}
+
+pub async fn call_session(
+    caller: Caller,
+    WalletExtract(wallet): WalletExtract,
+    Extension(executor): Extension<Executor>,
+    Extension(sessions): Extension<SessionStore>,
+    Path((id, func_name)): Path<(String, String)>,
+    axum::extract::Json(ctx): axum::extract::Json<CallSessionBody>,
+) -> Result<CallFunctionResponse, AwsError> {
+    caller.require(Scope::Invoke(sessions.module_id(caller.uid, &id)?))?;
+
+    let execution = sessions
+        .call(&executor, wallet, &id, &func_name, ctx)
+        .await?;
+
+    Ok(CallFunctionResponse {
+        return_value: execution.return_value,
+        wasi: None,
+        logs: execution.logs,
+    })
+}
+
+pub async fn snapshot_session(
+    ClaimsExtract(claims): ClaimsExtract,
+    Extension(sessions): Extension<SessionStore>,
+    Path(id): Path<String>,
+) -> Result<(StatusCode, axum::Json<SnapshotResponse>), AwsError> {
+    let snapshot_id = sessions.snapshot(claims.uid, &id).await?;
+
+    Ok((
+        StatusCode::CREATED,
+        axum::Json::from(SnapshotResponse { snapshot_id }),
+    ))
+}
+
+pub async fn restore_session(
+    ClaimsExtract(claims): ClaimsExtract,
+    Extension(sessions): Extension<SessionStore>,
+    Path((id, snapshot_id)): Path<(String, usize)>,
+) -> Result<(), AwsError> {
+    sessions.restore(claims.uid, &id, snapshot_id).await
+}
+
+pub async fn delete_snapshot(
+    ClaimsExtract(claims): ClaimsExtract,
+    Extension(sessions): Extension<SessionStore>,
+    Path((id, snapshot_id)): Path<(String, usize)>,
+) -> Result<(), AwsError> {
+    sessions.delete_snapshot(claims.uid, &id, snapshot_id)
+}
+
+pub async fn delete_session(
+    ClaimsExtract(claims): ClaimsExtract,
+    Extension(sessions): Extension<SessionStore>,
+    Path(id): Path<String>,
+) -> Result<(), AwsError> {
+    sessions.close(claims.uid, &id)
+}
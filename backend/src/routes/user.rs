@@ -1,140 +1,137 @@
-use std::time::{SystemTime, UNIX_EPOCH};
-
-use aws_common::api::{errors::AwsError, responses::GetCreditsResponse};
-use axum::{http::StatusCode, Extension};
-use sea_orm::{
-    ActiveModelTrait, ActiveValue, ColumnTrait, DbErr, EntityTrait, QueryFilter, TransactionTrait,
+use aws_common::api::{
+    auth::{Credentials, IssuedTokens, JwtResponse, LogoutRequest, RefreshRequest, RegisteredUser},
+    errors::AwsError,
+    requests::LedgerQuery,
+    responses::{GetCreditsResponse, LedgerResponse},
+};
+use axum::{
+    extract::Query,
+    http::{Method, StatusCode},
+    Extension,
 };
-use serde::Deserialize;
+use sea_orm::{ActiveModelTrait, ActiveValue, DbErr, TransactionTrait};
 
 use crate::{
-    auth::jwt::{AwsClaims, JwtResponse},
-    constants::{INITIAL_WALLET_CREDITS, JWT_TOKEN_VALIDITY, MINIMUM_PASSWORD_LENGTH},
+    auth::{
+        accounts::{self, CredentialsExported},
+        api_keys::Scope,
+        client,
+        jwt::ClaimsExtract,
+    },
+    constants::LEDGER_PAGE_DEFAULT,
     entities,
-    extractors::WalletExtract,
+    extractors::{Caller, WalletExtract},
+    ledger,
     metrics::ACTIVE_USERS,
-    utils::{password_secure_check, DbConn},
-};
-
-use argon2::{
-    self,
-    password_hash::{rand_core::OsRng, SaltString},
-    Argon2, PasswordHash, PasswordHasher, PasswordVerifier,
+    utils::DbConn,
 };
 
 pub async fn get_remaining_credits(
+    caller: Caller,
     WalletExtract(wallet): WalletExtract,
 ) -> Result<axum::Json<GetCreditsResponse>, AwsError> {
+    caller.require(Scope::Read)?;
+
     Ok(axum::Json::from(GetCreditsResponse {
         credits: wallet.credits,
     }))
 }
 
-#[derive(Deserialize)]
-pub struct Credentials {
-    username: String,
-    password: String,
+/// Movements of the user's credits, newest first
+pub async fn get_ledger(
+    caller: Caller,
+    Extension(DbConn(db)): Extension<DbConn>,
+    Query(query): Query<LedgerQuery>,
+) -> Result<axum::Json<LedgerResponse>, AwsError> {
+    caller.require(Scope::Read)?;
+
+    let entries = ledger::entries(&db, caller.uid, &query).await?;
+    let balance = ledger::balance(&*db, caller.uid)
+        .await
+        .map_err(|_| AwsError::UnknownServerError)?;
+
+    let limit = query.limit.unwrap_or(LEDGER_PAGE_DEFAULT) as usize;
+    let next_before = match entries.last() {
+        Some(last) if entries.len() == limit => Some(last.id),
+        _ => None,
+    };
+
+    Ok(axum::Json::from(LedgerResponse {
+        balance,
+        entries: entries.into_iter().map(Into::into).collect(),
+        next_before,
+    }))
 }
 
-// TODO(Livian): Implement Refresh Tokens + Redis cache
 pub async fn register_user(
     Extension(DbConn(db)): Extension<DbConn>,
-    axum::extract::Json(Credentials { username, password }): axum::extract::Json<Credentials>,
+    Extension(exported): Extension<CredentialsExported>,
+    axum::extract::Json(credentials): axum::extract::Json<Credentials>,
 ) -> Result<StatusCode, AwsError> {
-    tracing::debug!(
-        "Registering user {user} with password {password}",
-        user = username,
-        password = password
-    );
-
-    if password.len() < MINIMUM_PASSWORD_LENGTH {
-        return Err(AwsError::PasswordTooShort);
-    }
-
-    if !password_secure_check(&password) {
-        return Err(AwsError::PasswordTooWeak);
-    }
+    exported.check()?;
 
-    let salt = SaltString::generate(&mut OsRng);
+    let RegisteredUser { uid, username } =
+        client::call(Method::POST, "/api/v1/register", &credentials).await?;
 
-    let hashed_password = Argon2::default()
-        .hash_password(password.as_bytes(), &salt)
-        .map_err(|_| AwsError::UnknownServerError)?
-        .to_string();
+    accounts::provision(&db, uid, &username).await?;
 
-    db.transaction::<_, _, DbErr>(|txn| {
-        Box::pin(async move {
-            let new_user = entities::user::ActiveModel {
-                username: ActiveValue::Set(username),
-                password: ActiveValue::Set(hashed_password),
-                ..Default::default()
-            };
+    Ok(StatusCode::OK)
+}
 
-            let added_user = new_user.save(txn).await?;
+pub async fn login_user(
+    Extension(DbConn(db)): Extension<DbConn>,
+    Extension(exported): Extension<CredentialsExported>,
+    axum::extract::Json(credentials): axum::extract::Json<Credentials>,
+) -> Result<axum::Json<JwtResponse>, AwsError> {
+    exported.check()?;
 
-            let new_wallet = entities::wallet::ActiveModel {
-                user_id: added_user.id,
-                credits: ActiveValue::set(INITIAL_WALLET_CREDITS),
-                ..Default::default()
-            };
+    let IssuedTokens {
+        uid,
+        username,
+        tokens,
+    } = client::call(Method::POST, "/api/v1/login", &credentials).await?;
 
-            new_wallet.save(txn).await?;
+    // Accounts whose registration didn't complete are created on login
+    accounts::provision(&db, uid, &username).await?;
 
-            Ok(())
-        })
-    })
-    .await
-    .map_err(|_| AwsError::DuplicateUsername)?;
+    Ok(axum::Json::from(tokens))
+}
 
-    ACTIVE_USERS.inc();
+/// Trades a refresh token for a new access token and refresh token
+pub async fn refresh_tokens(
+    axum::extract::Json(refresh): axum::extract::Json<RefreshRequest>,
+) -> Result<axum::Json<JwtResponse>, AwsError> {
+    let IssuedTokens { tokens, .. } =
+        client::call(Method::POST, "/api/v1/refresh", &refresh).await?;
 
-    Ok(StatusCode::OK)
+    Ok(axum::Json::from(tokens))
 }
 
-pub async fn login_user(
-    Extension(DbConn(db)): Extension<DbConn>,
-    axum::extract::Json(Credentials { username, password }): axum::extract::Json<Credentials>,
-) -> Result<axum::Json<JwtResponse>, AwsError> {
-    let res = entities::user::Entity::find()
-        .filter(entities::user::Column::Username.eq(&username))
-        .one(&*db)
-        .await
-        .map_err(|_| AwsError::UnknownServerError)?
-        .ok_or_else(|| AwsError::InvalidCredentials)?;
-
-    let password_hash =
-        PasswordHash::new(&res.password).map_err(|_| AwsError::UnknownServerError)?;
-
-    Argon2::default()
-        .verify_password(password.as_bytes(), &password_hash)
-        .map_err(|_| AwsError::InvalidCredentials)?;
-
-    tracing::debug!(
-        "Login OK for user {user} with password {password}",
-        user = username,
-        password = password
-    );
-
-    let expiration = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .map_err(|_| AwsError::UnknownServerError)?
-        + JWT_TOKEN_VALIDITY;
-
-    let claims = AwsClaims {
-        sub: username,
-        exp: expiration.as_secs() as usize,
-        uid: res.id,
-    };
+/// Ends the session of the access token, its refresh token included
+pub async fn logout_user(ClaimsExtract(claims): ClaimsExtract) -> Result<StatusCode, AwsError> {
+    client::send(
+        Method::POST,
+        "/api/v1/logout",
+        &LogoutRequest { jti: claims.jti },
+    )
+    .await?;
 
-    Ok(axum::Json::from(JwtResponse {
-        jwt: claims.to_jwt().await?,
-    }))
+    Ok(StatusCode::OK)
 }
 
+/// Deletes the user's credentials, which ends their sessions, then their
+/// account
 pub async fn delete_account(
-    claims: AwsClaims,
+    ClaimsExtract(claims): ClaimsExtract,
     Extension(DbConn(db)): Extension<DbConn>,
 ) -> Result<(), AwsError> {
+    client::send(
+        Method::DELETE,
+        &format!("/api/v1/users/{uid}", uid = claims.uid),
+        &(),
+    )
+    .await?;
+
     db.transaction::<_, _, DbErr>(|txn| {
         Box::pin(async move {
             let user = entities::user::ActiveModel {
@@ -154,3 +151,49 @@ pub async fn delete_account(
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_registering_waits_for_the_credential_export() {
+        let db = DbConn(Arc::new(testing::database().await));
+        let exported = CredentialsExported::default();
+        let credentials = || Credentials {
+            username: "user".to_string(),
+            password: "A password strong enough 1!".to_string(),
+        };
+
+        // Neither asks the auth service before the export finished
+        let registered = register_user(
+            Extension(db.clone()),
+            Extension(exported.clone()),
+            axum::Json(credentials()),
+        )
+        .await;
+        assert!(matches!(
+            registered,
+            Err(AwsError::AuthServiceError {
+                status: StatusCode::SERVICE_UNAVAILABLE,
+                ..
+            })
+        ));
+
+        let logged_in = login_user(
+            Extension(db),
+            Extension(exported),
+            axum::Json(credentials()),
+        )
+        .await;
+        assert!(matches!(
+            logged_in,
+            Err(AwsError::AuthServiceError {
+                status: StatusCode::SERVICE_UNAVAILABLE,
+                ..
+            })
+        ));
+    }
+}
@@ -0,0 +1,307 @@
+//! Runs functions on the cron schedules their owners attached to them.
+//! Every run is charged to the owner and recorded in `schedule_run`,
+//! and schedules are paused once the owner runs out of credits.
+
+use aws_common::api::{
+    errors::AwsError,
+    responses::{ScheduleResponse, ScheduleRunResponse},
+};
+use sea_orm::{ActiveModelTrait, ActiveValue, ColumnTrait, DbErr, EntityTrait, QueryFilter};
+use sea_query::Expr;
+use tokio::task::JoinHandle;
+
+use crate::{
+    constants::SCHEDULER_INTERVAL,
+    cron::{next_run_ms, CronSchedule},
+    entities::{schedule, schedule_run},
+    execution::{describe, ExecutionFailure, Executor, StoredCall},
+    utils::now_ms,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ScheduleStatus {
+    Active,
+    Paused,
+}
+
+impl ScheduleStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ScheduleStatus::Active => "active",
+            ScheduleStatus::Paused => "paused",
+        }
+    }
+}
+
+const RUN_SUCCEEDED: &str = "succeeded";
+const RUN_FAILED: &str = "failed";
+
+impl From<schedule::Model> for ScheduleResponse {
+    fn from(schedule: schedule::Model) -> Self {
+        Self {
+            id: schedule.id,
+            function_id: schedule.function_id,
+            cron: schedule.cron,
+            params: serde_json::from_str(&schedule.params).unwrap_or_default(),
+            timeout_ms: schedule.timeout_ms.map(|ms| ms as u64),
+            status: schedule.status,
+            paused_reason: schedule.paused_reason,
+            next_run_at: schedule.next_run_at,
+        }
+    }
+}
+
+impl From<schedule_run::Model> for ScheduleRunResponse {
+    fn from(run: schedule_run::Model) -> Self {
+        Self {
+            started_at: run.started_at,
+            status: run.status,
+            used_credits: run.used_credits as u64,
+            result: run.result.and_then(|r| serde_json::from_str(&r).ok()),
+            error: run.error,
+        }
+    }
+}
+
+/// Next run of `cron` after now, failing on expressions that never match
+pub fn next_run_from_now(cron: &CronSchedule) -> Result<i64, AwsError> {
+    next_run_ms(cron, now_ms()).ok_or_else(|| AwsError::InvalidCronExpression(cron.to_string()))
+}
+
+#[derive(Clone)]
+pub struct Scheduler {
+    pub executor: Executor,
+}
+
+impl Scheduler {
+    pub fn start(self) {
+        tokio::spawn(async move {
+            loop {
+                if let Err(e) = self.run_due().await {
+                    tracing::error!("Scheduler {e:#?}");
+                }
+
+                tokio::time::sleep(SCHEDULER_INTERVAL).await;
+            }
+        });
+    }
+
+    /// Starts a run of every due schedule. Runs that were missed while
+    /// the backend was down are only made up for once
+    pub async fn run_due(&self) -> Result<Vec<JoinHandle<()>>, DbErr> {
+        let due = schedule::Entity::find()
+            .filter(schedule::Column::Status.eq(ScheduleStatus::Active.as_str()))
+            .filter(schedule::Column::NextRunAt.lte(now_ms()))
+            .all(&*self.executor.db)
+            .await?;
+
+        let mut runs = Vec::new();
+
+        for schedule in due {
+            if self.claim(&schedule).await? {
+                runs.push(tokio::spawn(self.clone().run(schedule)));
+            }
+        }
+
+        Ok(runs)
+    }
+
+    /// Moves the schedule to its next run, unless another scheduler
+    /// already did
+    async fn claim(&self, schedule: &schedule::Model) -> Result<bool, DbErr> {
+        let next = schedule
+            .cron
+            .parse::<CronSchedule>()
+            .ok()
+            .and_then(|cron| next_run_ms(&cron, now_ms()));
+
+        let Some(next) = next else {
+            self.pause(schedule.id, "cron expression never matches")
+                .await?;
+            return Ok(false);
+        };
+
+        let res = schedule::Entity::update_many()
+            .col_expr(schedule::Column::NextRunAt, Expr::value(next))
+            .filter(schedule::Column::Id.eq(schedule.id))
+            .filter(schedule::Column::NextRunAt.eq(schedule.next_run_at))
+            .exec(&*self.executor.db)
+            .await?;
+
+        Ok(res.rows_affected == 1)
+    }
+
+    async fn pause(&self, id: i32, reason: &str) -> Result<(), DbErr> {
+        schedule::ActiveModel {
+            id: ActiveValue::unchanged(id),
+            status: ActiveValue::set(ScheduleStatus::Paused.as_str().to_string()),
+            paused_reason: ActiveValue::set(Some(reason.to_string())),
+            ..Default::default()
+        }
+        .update(&*self.executor.db)
+        .await
+        .map(|_| ())
+    }
+
+    async fn run(self, schedule: schedule::Model) {
+        let started_at = now_ms();
+
+        let outcome = self
+            .executor
+            .call_stored(StoredCall {
+                owner_id: schedule.owner_id,
+                function_id: schedule.function_id,
+                params: schedule.params,
+                wasi: None,
+                timeout_ms: schedule.timeout_ms,
+                max_credits: None,
+            })
+            .await;
+
+        let mut run = schedule_run::ActiveModel {
+            schedule_id: ActiveValue::set(schedule.id),
+            started_at: ActiveValue::set(started_at),
+            ..Default::default()
+        };
+
+        match outcome {
+            Ok((result, used_credits)) => {
+                run.status = ActiveValue::set(RUN_SUCCEEDED.to_string());
+                run.used_credits = ActiveValue::set(used_credits as i64);
+                run.result = ActiveValue::set(Some(result.to_string()));
+            }
+            Err(ExecutionFailure {
+                error: e,
+                used_credits,
+            }) => {
+                let out_of_credits = matches!(e, AwsError::InsufficientCredits);
+                let (_, message) = describe(e).await;
+
+                if out_of_credits {
+                    if let Err(e) = self.pause(schedule.id, &message).await {
+                        tracing::error!("Failed to pause schedule {} {e:#?}", schedule.id);
+                    }
+                }
+
+                run.status = ActiveValue::set(RUN_FAILED.to_string());
+                run.used_credits = ActiveValue::set(used_credits as i64);
+                run.error = ActiveValue::set(Some(message));
+            }
+        }
+
+        if let Err(e) = run.insert(&*self.executor.db).await {
+            tracing::error!("Failed to record run of schedule {} {e:#?}", schedule.id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cache::ModuleCache, execution::ExecutionLimits, testing};
+    use std::sync::Arc;
+
+    const MODULE: &str = r#"
+        (module
+            (func (export "spin") (param i32) (result i32)
+                (loop $l (br_if $l (local.tee 0 (i32.sub (local.get 0) (i32.const 1)))))
+                (local.get 0)))
+    "#;
+
+    async fn scheduler(credits: i64) -> (Scheduler, schedule::Model) {
+        let db = testing::database().await;
+        let user_id = testing::wallet(&db, "user", credits).await.user_id;
+
+        let module = testing::module(&db, user_id, MODULE).await;
+
+        let function = testing::function(&db, module.id, "spin", "i32->i32").await;
+
+        let schedule = schedule::ActiveModel {
+            owner_id: ActiveValue::set(user_id),
+            function_id: ActiveValue::set(function.id),
+            cron: ActiveValue::set("* * * * *".to_string()),
+            params: ActiveValue::set("[100]".to_string()),
+            status: ActiveValue::set(ScheduleStatus::Active.as_str().to_string()),
+            next_run_at: ActiveValue::set(0),
+            created_at: ActiveValue::set(0),
+            ..Default::default()
+        }
+        .insert(&db)
+        .await
+        .unwrap();
+
+        let scheduler = Scheduler {
+            executor: Executor {
+                db: Arc::new(db),
+                cache: ModuleCache::default(),
+                limits: ExecutionLimits::default(),
+            },
+        };
+
+        (scheduler, schedule)
+    }
+
+    async fn run_due(scheduler: &Scheduler) -> usize {
+        let runs = scheduler.run_due().await.unwrap();
+        let started = runs.len();
+
+        for run in runs {
+            run.await.unwrap();
+        }
+
+        started
+    }
+
+    #[tokio::test]
+    async fn test_due_schedule_runs_once() {
+        let (scheduler, schedule) = scheduler(1_000_000).await;
+
+        assert_eq!(run_due(&scheduler).await, 1);
+        assert_eq!(run_due(&scheduler).await, 0);
+
+        let runs = schedule_run::Entity::find()
+            .all(&*scheduler.executor.db)
+            .await
+            .unwrap();
+
+        assert_eq!(runs.len(), 1);
+        assert_eq!(runs[0].status, RUN_SUCCEEDED);
+        assert_eq!(runs[0].result.as_deref(), Some(r#"{"return_value":[0]}"#));
+
+        let schedule = schedule::Entity::find_by_id(schedule.id)
+            .one(&*scheduler.executor.db)
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert!(schedule.next_run_at > now_ms());
+    }
+
+    #[tokio::test]
+    async fn test_schedule_is_paused_without_credits() {
+        let (scheduler, schedule) = scheduler(10).await;
+
+        assert_eq!(run_due(&scheduler).await, 1);
+
+        let schedule = schedule::Entity::find_by_id(schedule.id)
+            .one(&*scheduler.executor.db)
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(schedule.status, ScheduleStatus::Paused.as_str());
+        assert_eq!(
+            schedule.paused_reason.as_deref(),
+            Some("insufficient credits")
+        );
+
+        // The guest ran until its credits were gone, which is charged
+        let runs = schedule_run::Entity::find()
+            .all(&*scheduler.executor.db)
+            .await
+            .unwrap();
+
+        assert_eq!(runs[0].status, RUN_FAILED);
+        assert_eq!(runs[0].used_credits, 10);
+    }
+}
@@ -0,0 +1,590 @@
+//! Sessions keep an instance of a module alive between calls, so
+//! guests can hold state in their linear memory. Sessions live in the
+//! memory of the backend and are closed after being idle for a while.
+//! What their instances and snapshots hold counts against a limit per
+//! user.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use aws_common::api::{errors::AwsError, requests::CallSessionBody};
+use sea_orm::{ColumnTrait, EntityTrait, QueryFilter};
+use wasmer::{AsStoreMut, Extern, Instance, Mutability, Pages, Value, WASM_PAGE_SIZE};
+
+use crate::{
+    artifacts::load_module,
+    constants::{
+        SESSION_MAX_SNAPSHOTS, SESSION_MEMORY_PER_USER, SESSION_SWEEP_INTERVAL, SESSION_TTL,
+    },
+    entities,
+    execution::{execute_live, Execution, Executor, LiveInstance},
+    ffi::WasmFFIConverter,
+    host::KvStore,
+    limits::ResourceUsage,
+    utils::limited_store,
+};
+
+/// Exported memories and mutable globals of an instance. Globals the
+/// guest doesn't export can't be read, so state worth restoring has to
+/// live in memory or exported globals
+struct Snapshot {
+    memories: Vec<(String, Vec<u8>)>,
+    globals: Vec<(String, Value)>,
+}
+
+impl Snapshot {
+    fn take(store: &mut impl AsStoreMut, instance: &Instance) -> Result<Self, AwsError> {
+        let mut memories = Vec::new();
+        let mut globals = Vec::new();
+
+        for (name, export) in instance.exports.iter() {
+            match export {
+                Extern::Memory(memory) => {
+                    let view = memory.view(store);
+                    let mut data = vec![0; view.data_size() as usize];
+
+                    view.read(0, &mut data)
+                        .map_err(|_| AwsError::UnknownServerError)?;
+                    memories.push((name.clone(), data));
+                }
+                // The metering globals belong to the host
+                Extern::Global(global)
+                    if global.ty(store).mutability == Mutability::Var
+                        && !name.starts_with("wasmer_metering") =>
+                {
+                    let value = global.get(store);
+
+                    if matches!(
+                        value,
+                        Value::I32(_) | Value::I64(_) | Value::F32(_) | Value::F64(_)
+                    ) {
+                        globals.push((name.clone(), value));
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        Ok(Self { memories, globals })
+    }
+
+    fn restore(&self, store: &mut impl AsStoreMut, instance: &Instance) -> Result<(), AwsError> {
+        let exports = &instance.exports;
+
+        for (name, data) in &self.memories {
+            let memory = exports
+                .get_memory(name)
+                .map_err(|_| AwsError::UnknownServerError)?;
+            let size = memory.view(store).data_size() as usize;
+
+            // Memories can't shrink, what the guest grew since is zeroed
+            if size < data.len() {
+                let pages = (data.len() - size) / WASM_PAGE_SIZE;
+
+                memory
+                    .grow(store, Pages(pages as u32))
+                    .map_err(|_| AwsError::MemoryLimitExceeded)?;
+            }
+
+            let view = memory.view(store);
+            let zeroes = vec![0; (view.data_size() as usize).saturating_sub(data.len())];
+
+            view.write(0, data)
+                .and_then(|_| view.write(data.len() as u64, &zeroes))
+                .map_err(|_| AwsError::UnknownServerError)?;
+        }
+
+        for (name, value) in &self.globals {
+            exports
+                .get_global(name)
+                .map_err(|_| AwsError::UnknownServerError)?
+                .set(store, value.clone())
+                .map_err(|_| AwsError::UnknownServerError)?;
+        }
+
+        Ok(())
+    }
+
+    fn size(&self) -> u64 {
+        self.memories
+            .iter()
+            .map(|(_, data)| data.len() as u64)
+            .sum()
+    }
+}
+
+struct Session {
+    owner_id: i32,
+    module: entities::module::Model,
+    live: Arc<Mutex<LiveInstance>>,
+    usage: ResourceUsage,
+    /// Snapshots by id, ids of deleted snapshots aren't reused
+    snapshots: BTreeMap<usize, Arc<Snapshot>>,
+    next_snapshot_id: usize,
+    last_used: Instant,
+}
+
+impl Session {
+    fn held_memory(&self) -> u64 {
+        self.usage.memory_bytes() + self.snapshots.values().map(|s| s.size()).sum::<u64>()
+    }
+}
+
+/// Sessions of every user, keyed by their id
+#[derive(Clone)]
+pub struct SessionStore {
+    sessions: Arc<Mutex<HashMap<String, Session>>>,
+    pub ttl: Duration,
+    pub memory_per_user: u64,
+}
+
+impl Default for SessionStore {
+    fn default() -> Self {
+        Self::new(SESSION_TTL, SESSION_MEMORY_PER_USER)
+    }
+}
+
+impl SessionStore {
+    pub fn new(ttl: Duration, memory_per_user: u64) -> Self {
+        Self {
+            sessions: Default::default(),
+            ttl,
+            memory_per_user,
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, Session>> {
+        self.sessions
+            .lock()
+            .expect("sessions lock to not be poisoned")
+    }
+
+    /// Closes the sessions left idle for longer than the TTL
+    pub fn start(self) {
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(SESSION_SWEEP_INTERVAL).await;
+                self.expire();
+            }
+        });
+    }
+
+    fn expire(&self) {
+        let ttl = self.ttl;
+
+        self.lock()
+            .retain(|_, session| session.last_used.elapsed() < ttl);
+    }
+
+    fn held_memory(sessions: &HashMap<String, Session>, owner_id: i32) -> u64 {
+        sessions
+            .values()
+            .filter(|session| session.owner_id == owner_id)
+            .map(Session::held_memory)
+            .sum()
+    }
+
+    /// Fails once the sessions of the user hold more than they may,
+    /// closing the session `id` when given
+    fn check_memory(&self, owner_id: i32, id: Option<&str>) -> Result<(), AwsError> {
+        let mut sessions = self.lock();
+
+        if Self::held_memory(&sessions, owner_id) <= self.memory_per_user {
+            return Ok(());
+        }
+
+        if let Some(id) = id {
+            sessions.remove(id);
+        }
+
+        Err(AwsError::SessionMemoryExceeded)
+    }
+
+    /// Instantiates the module in a new session of its owner
+    pub async fn create(
+        &self,
+        executor: &Executor,
+        module: entities::module::Model,
+    ) -> Result<String, AwsError> {
+        self.check_memory(module.owner_id, None)?;
+
+        let user = entities::user::Entity::find_by_id(module.owner_id)
+            .one(&*executor.db)
+            .await
+            .map_err(|_| AwsError::UnknownServerError)?
+            .ok_or(AwsError::Unauthorized)?;
+
+        let (store, usage) = limited_store(executor.limits.resources.for_tier(&user.tier));
+        let compiled = load_module(&executor.db, &executor.cache, &store, &module).await?;
+
+        let kv = KvStore {
+            db: executor.db.clone(),
+            module_id: module.id,
+            runtime: tokio::runtime::Handle::current(),
+        };
+
+        let instance_usage = usage.clone();
+        let live = tokio::task::spawn_blocking(move || {
+            LiveInstance::new(store, instance_usage, &compiled, Some(kv))
+        })
+        .await
+        .map_err(|_| AwsError::UnknownServerError)??;
+
+        let mut id = [0u8; 16];
+        OsRng.fill_bytes(&mut id);
+        let id = format!("{:032x}", u128::from_le_bytes(id));
+
+        let owner_id = module.owner_id;
+
+        self.lock().insert(
+            id.clone(),
+            Session {
+                owner_id,
+                module,
+                live: Arc::new(Mutex::new(live)),
+                usage,
+                snapshots: BTreeMap::new(),
+                next_snapshot_id: 0,
+                last_used: Instant::now(),
+            },
+        );
+
+        self.check_memory(owner_id, Some(&id))?;
+
+        Ok(id)
+    }
+
+    /// Instance and module of a session of the user, marking it used
+    fn get(
+        &self,
+        owner_id: i32,
+        id: &str,
+    ) -> Result<(Arc<Mutex<LiveInstance>>, entities::module::Model), AwsError> {
+        let mut sessions = self.lock();
+
+        match sessions.get_mut(id) {
+            Some(session) if session.owner_id == owner_id => {
+                session.last_used = Instant::now();
+                Ok((session.live.clone(), session.module.clone()))
+            }
+            _ => Err(AwsError::SessionNotFound(id.to_string())),
+        }
+    }
+
+    /// Module the session instantiated
+    pub fn module_id(&self, owner_id: i32, id: &str) -> Result<i32, AwsError> {
+        match self.lock().get(id) {
+            Some(session) if session.owner_id == owner_id => Ok(session.module.id),
+            _ => Err(AwsError::SessionNotFound(id.to_string())),
+        }
+    }
+
+    /// Calls a function of the session's instance, charged like any
+    /// other call
+    pub async fn call(
+        &self,
+        executor: &Executor,
+        wallet: entities::wallet::Model,
+        id: &str,
+        func_name: &str,
+        body: CallSessionBody,
+    ) -> Result<Execution, AwsError> {
+        let (live, module) = self.get(wallet.user_id, id)?;
+
+        let function = entities::function::Entity::find()
+            .filter(entities::function::Column::ModuleId.eq(module.id))
+            .filter(entities::function::Column::Name.eq(func_name))
+            .one(&*executor.db)
+            .await
+            .map_err(|_| AwsError::UnknownServerError)?
+            .ok_or_else(|| AwsError::FunctionNotFound(func_name.to_string()))?;
+
+        let params = function.to_abi_params(&body.params)?;
+        let timeout = executor.limits.timeout(body.timeout_ms);
+        let reservation = executor
+            .reserve(
+                wallet.user_id,
+                &[module.id],
+                executor.limits.reservation(body.max_credits, 1),
+            )
+            .await?;
+        let credits = reservation.amount;
+        let started = Instant::now();
+
+        let outcome = execute_live(live, function.clone(), params, credits, timeout).await;
+        let execution = executor
+            .settle(reservation, &function, started, outcome)
+            .await?;
+
+        self.check_memory(wallet.user_id, Some(id))?;
+
+        Ok(execution)
+    }
+
+    /// Fails unless the session may hold one more snapshot, as large as
+    /// its instance's memory is now
+    fn check_snapshot(&self, owner_id: i32, id: &str) -> Result<(), AwsError> {
+        let sessions = self.lock();
+        let session = sessions
+            .get(id)
+            .filter(|session| session.owner_id == owner_id)
+            .ok_or_else(|| AwsError::SessionNotFound(id.to_string()))?;
+
+        let held = Self::held_memory(&sessions, owner_id) + session.usage.memory_bytes();
+
+        if session.snapshots.len() >= SESSION_MAX_SNAPSHOTS || held > self.memory_per_user {
+            return Err(AwsError::SessionMemoryExceeded);
+        }
+
+        Ok(())
+    }
+
+    /// Saves the state of the session's instance, returning the id to
+    /// restore it by
+    pub async fn snapshot(&self, owner_id: i32, id: &str) -> Result<usize, AwsError> {
+        let (live, _) = self.get(owner_id, id)?;
+
+        // Checked before copying the memory, and again once it is copied
+        // in case the memory grew or other snapshots were taken meanwhile
+        self.check_snapshot(owner_id, id)?;
+
+        let snapshot = tokio::task::spawn_blocking(move || {
+            let mut live = live.lock().map_err(|_| AwsError::UnknownServerError)?;
+            let LiveInstance {
+                store, instance, ..
+            } = &mut *live;
+
+            Snapshot::take(store, instance)
+        })
+        .await
+        .map_err(|_| AwsError::UnknownServerError)??;
+
+        let snapshot_id = {
+            let mut sessions = self.lock();
+            let session = sessions
+                .get_mut(id)
+                .ok_or_else(|| AwsError::SessionNotFound(id.to_string()))?;
+
+            if session.snapshots.len() >= SESSION_MAX_SNAPSHOTS {
+                return Err(AwsError::SessionMemoryExceeded);
+            }
+
+            let snapshot_id = session.next_snapshot_id;
+            session.next_snapshot_id += 1;
+            session.snapshots.insert(snapshot_id, Arc::new(snapshot));
+
+            // Only the new snapshot goes over the limit, so the session
+            // is kept without it
+            if Self::held_memory(&sessions, owner_id) > self.memory_per_user {
+                if let Some(session) = sessions.get_mut(id) {
+                    session.snapshots.remove(&snapshot_id);
+                }
+
+                return Err(AwsError::SessionMemoryExceeded);
+            }
+
+            snapshot_id
+        };
+
+        Ok(snapshot_id)
+    }
+
+    pub async fn restore(
+        &self,
+        owner_id: i32,
+        id: &str,
+        snapshot_id: usize,
+    ) -> Result<(), AwsError> {
+        let (live, _) = self.get(owner_id, id)?;
+
+        let snapshot = self
+            .lock()
+            .get(id)
+            .and_then(|session| session.snapshots.get(&snapshot_id).cloned())
+            .ok_or(AwsError::SnapshotNotFound(snapshot_id))?;
+
+        tokio::task::spawn_blocking(move || {
+            let mut live = live.lock().map_err(|_| AwsError::UnknownServerError)?;
+            let LiveInstance {
+                store, instance, ..
+            } = &mut *live;
+
+            snapshot.restore(store, instance)
+        })
+        .await
+        .map_err(|_| AwsError::UnknownServerError)?
+    }
+
+    /// Deletes a snapshot of the session, freeing the memory it held
+    pub fn delete_snapshot(
+        &self,
+        owner_id: i32,
+        id: &str,
+        snapshot_id: usize,
+    ) -> Result<(), AwsError> {
+        let mut sessions = self.lock();
+        let session = sessions
+            .get_mut(id)
+            .filter(|session| session.owner_id == owner_id)
+            .ok_or_else(|| AwsError::SessionNotFound(id.to_string()))?;
+
+        session
+            .snapshots
+            .remove(&snapshot_id)
+            .map(|_| ())
+            .ok_or(AwsError::SnapshotNotFound(snapshot_id))
+    }
+
+    pub fn close(&self, owner_id: i32, id: &str) -> Result<(), AwsError> {
+        let mut sessions = self.lock();
+
+        match sessions.get(id) {
+            Some(session) if session.owner_id == owner_id => {
+                sessions.remove(id);
+                Ok(())
+            }
+            _ => Err(AwsError::SessionNotFound(id.to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cache::ModuleCache, execution::ExecutionLimits, testing};
+
+    const MODULE: &str = r#"
+        (module
+            (memory (export "memory") 1)
+            (func (export "incr") (result i32)
+                (i32.store (i32.const 0) (i32.add (i32.load (i32.const 0)) (i32.const 1)))
+                (i32.load (i32.const 0))))
+    "#;
+
+    async fn executor() -> (Executor, entities::wallet::Model, entities::module::Model) {
+        let db = testing::database().await;
+        let wallet = testing::wallet(&db, "user", 1_000_000).await;
+
+        let module = testing::module(&db, wallet.user_id, MODULE).await;
+
+        testing::function(&db, module.id, "incr", "->i32").await;
+
+        let executor = Executor {
+            db: Arc::new(db),
+            cache: ModuleCache::default(),
+            limits: ExecutionLimits::default(),
+        };
+
+        (executor, wallet, module)
+    }
+
+    #[tokio::test]
+    async fn test_session_keeps_and_restores_memory() {
+        let (executor, wallet, module) = executor().await;
+        let sessions = SessionStore::default();
+        let id = sessions.create(&executor, module).await.unwrap();
+
+        let incr = || {
+            let body = CallSessionBody {
+                params: Vec::new(),
+                timeout_ms: None,
+                max_credits: None,
+            };
+
+            sessions.call(&executor, wallet.clone(), &id, "incr", body)
+        };
+
+        assert_eq!(
+            incr().await.unwrap().return_value,
+            vec![serde_json::json!(1)]
+        );
+
+        let snapshot_id = sessions.snapshot(wallet.user_id, &id).await.unwrap();
+
+        assert_eq!(
+            incr().await.unwrap().return_value,
+            vec![serde_json::json!(2)]
+        );
+
+        sessions
+            .restore(wallet.user_id, &id, snapshot_id)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            incr().await.unwrap().return_value,
+            vec![serde_json::json!(2)]
+        );
+        assert!(matches!(
+            sessions.snapshot(wallet.user_id + 1, &id).await,
+            Err(AwsError::SessionNotFound(_))
+        ));
+
+        // Once at the cap, deleting a snapshot makes room for another
+        for _ in 1..SESSION_MAX_SNAPSHOTS {
+            sessions.snapshot(wallet.user_id, &id).await.unwrap();
+        }
+        assert!(matches!(
+            sessions.snapshot(wallet.user_id, &id).await,
+            Err(AwsError::SessionMemoryExceeded)
+        ));
+
+        sessions
+            .delete_snapshot(wallet.user_id, &id, snapshot_id)
+            .unwrap();
+
+        assert!(matches!(
+            sessions.restore(wallet.user_id, &id, snapshot_id).await,
+            Err(AwsError::SnapshotNotFound(_))
+        ));
+        assert!(matches!(
+            sessions.delete_snapshot(wallet.user_id, &id, snapshot_id),
+            Err(AwsError::SnapshotNotFound(_))
+        ));
+        assert_eq!(
+            sessions.snapshot(wallet.user_id, &id).await.unwrap(),
+            SESSION_MAX_SNAPSHOTS
+        );
+
+        sessions.close(wallet.user_id, &id).unwrap();
+
+        assert!(incr().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_sessions_are_limited_and_expire() {
+        let (executor, _, module) = executor().await;
+
+        // The memory of one instance is a single page
+        let limited = SessionStore::new(SESSION_TTL, WASM_PAGE_SIZE as u64);
+
+        let id = limited.create(&executor, module.clone()).await.unwrap();
+
+        assert!(matches!(
+            limited.create(&executor, module.clone()).await,
+            Err(AwsError::SessionMemoryExceeded)
+        ));
+
+        // A snapshot over the limit is dropped, the session is kept
+        assert!(matches!(
+            limited.snapshot(module.owner_id, &id).await,
+            Err(AwsError::SessionMemoryExceeded)
+        ));
+        assert!(matches!(
+            limited.restore(module.owner_id, &id, 0).await,
+            Err(AwsError::SnapshotNotFound(0))
+        ));
+        assert!(limited.close(module.owner_id, &id).is_ok());
+
+        let expiring = SessionStore::new(Duration::ZERO, SESSION_MEMORY_PER_USER);
+        let id = expiring.create(&executor, module.clone()).await.unwrap();
+
+        expiring.expire();
+
+        assert!(expiring.close(module.owner_id, &id).is_err());
+    }
+}
@@ -0,0 +1,74 @@
+//! Fixtures shared by the tests of every module, each test running
+//! against its own in-memory database
+
+use sea_orm::{ActiveModelTrait, ActiveValue, Database, DatabaseConnection};
+use sea_orm_migration::MigratorTrait;
+use sha2::{Digest, Sha256};
+
+use crate::{entities, migrator::Migrator};
+
+/// Empty database with every migration applied
+pub async fn database() -> DatabaseConnection {
+    let db = Database::connect("sqlite::memory:").await.unwrap();
+    Migrator::up(&db, None).await.unwrap();
+
+    db
+}
+
+/// Wallet of a new user named `username`, holding `credits`
+pub async fn wallet(
+    db: &DatabaseConnection,
+    username: &str,
+    credits: i64,
+) -> entities::wallet::Model {
+    let user = entities::user::ActiveModel {
+        username: ActiveValue::set(username.to_string()),
+        password: ActiveValue::set(String::new()),
+        ..Default::default()
+    }
+    .insert(db)
+    .await
+    .unwrap();
+
+    entities::wallet::ActiveModel {
+        user_id: ActiveValue::set(user.id),
+        credits: ActiveValue::set(credits),
+        ..Default::default()
+    }
+    .insert(db)
+    .await
+    .unwrap()
+}
+
+/// Module of `owner_id` built from `wat`
+pub async fn module(db: &DatabaseConnection, owner_id: i32, wat: &str) -> entities::module::Model {
+    let code = wasmer::wat2wasm(wat.as_bytes()).unwrap().to_vec();
+
+    entities::module::ActiveModel {
+        owner_id: ActiveValue::set(owner_id),
+        code_hash: ActiveValue::set(format!("{:x}", Sha256::digest(&code))),
+        wasm_code: ActiveValue::set(code),
+        ..Default::default()
+    }
+    .insert(db)
+    .await
+    .unwrap()
+}
+
+/// Export `name` of the module, declared with `signature`
+pub async fn function(
+    db: &DatabaseConnection,
+    module_id: i32,
+    name: &str,
+    signature: &str,
+) -> entities::function::Model {
+    entities::function::ActiveModel {
+        module_id: ActiveValue::set(module_id),
+        name: ActiveValue::set(name.to_string()),
+        signature: ActiveValue::set(signature.to_string()),
+        ..Default::default()
+    }
+    .insert(db)
+    .await
+    .unwrap()
+}
@@ -1,34 +1,69 @@
-use lazy_static::lazy_static;
-use sea_orm::DatabaseConnection;
+use sea_orm::{DatabaseConnection, DbErr, RuntimeErr, SqlxError, SqlxMySqlError};
 use std::sync::Arc;
-use wasmer::{wasmparser::Operator, ModuleMiddleware};
+use wasmer::{BaseTunables, CompilerConfig, Engine, EngineBuilder, Store};
 
-lazy_static! {
-    pub static ref WASM_COST_FUNCTION: Arc<dyn ModuleMiddleware> =
-        Arc::new(wasmer_middlewares::Metering::new(10, wasm_cost_function));
+use crate::{
+    limits::{LimitingTunables, ResourceLimits, ResourceUsage},
+    pricing,
+};
+
+/// Version of the compiler configuration built by `metered_store`, bump it
+/// whenever the middlewares change so previously compiled artifacts aren't
+/// reused. Opcode weights are told apart by `Pricing::opcodes_id`
+pub const METERED_ENGINE_VERSION: u32 = 1;
+
+fn metered_engine() -> Engine {
+    let pricing = pricing::current();
+
+    let mut compiler_config = wasmer_compiler_cranelift::Cranelift::default();
+    compiler_config.push_middleware(Arc::new(wasmer_middlewares::Metering::new(
+        10,
+        move |op: &wasmer::wasmparser::Operator| pricing.opcode_cost(op),
+    )));
+
+    EngineBuilder::new(compiler_config).engine()
 }
 
-pub fn wasm_cost_function(op: &Operator) -> u64 {
-    match op {
-        Operator::LocalGet { local_index: _ } => 1,
-        Operator::LocalSet { local_index: _ } => 2,
-        Operator::LocalTee { local_index: _ } => 3,
-        Operator::GlobalGet { global_index: _ } => 4,
-        Operator::GlobalSet { global_index: _ } => 5,
-        Operator::I32Load { memarg: _ } => 6,
-        Operator::I64Load { memarg: _ } => 7,
-        Operator::F32Load { memarg: _ } => 8,
-        Operator::F64Load { memarg: _ } => 9,
-        Operator::F32x4RelaxedDotBf16x8AddF32x4 => 100_000,
-        _ => 1,
-    }
+pub fn metered_store() -> Store {
+    Store::new(metered_engine())
+}
+
+/// Metered store whose instances are bound by `limits`
+pub fn limited_store(limits: ResourceLimits) -> (Store, ResourceUsage) {
+    limited_store_with(metered_engine(), limits)
 }
 
-pub fn password_secure_check(pass: &str) -> bool {
-    pass.chars().any(|c| c.is_ascii_digit())
-        && pass.chars().any(|c| c.is_uppercase())
-        && pass.chars().any(|c| c.is_lowercase())
-        && pass.chars().any(|c| !c.is_alphabetic())
+/// Limited store on an existing engine, whose compiled modules can be
+/// instantiated in all of its stores
+pub fn limited_store_with(engine: Engine, limits: ResourceLimits) -> (Store, ResourceUsage) {
+    let tunables = LimitingTunables::new(BaseTunables::for_target(engine.target()), limits);
+    let usage = tunables.usage();
+
+    (Store::new_with_tunables(engine, tunables), usage)
+}
+
+/// Unix time in milliseconds, as stored in the database
+pub fn now_ms() -> i64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|now| now.as_millis() as i64)
+        .unwrap_or_default()
+}
+
+/// Whether the statement failed for breaking a unique constraint
+pub fn is_unique_violation(e: &DbErr) -> bool {
+    let (DbErr::Exec(RuntimeErr::SqlxError(SqlxError::Database(e)))
+    | DbErr::Query(RuntimeErr::SqlxError(SqlxError::Database(e)))) = e
+    else {
+        return false;
+    };
+
+    if let Some(e) = e.try_downcast_ref::<SqlxMySqlError>() {
+        return e.number() == 1062;
+    }
+
+    // Extended SQLite codes of unique and primary key constraints
+    matches!(e.code().as_deref(), Some("2067" | "1555"))
 }
 
 #[derive(Clone)]
@@ -0,0 +1,371 @@
+use std::{
+    io::{self, Read, Seek, Write},
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use aws_common::api::{errors::AwsError, requests::WasiOptions, responses::WasiOutput};
+use base64::Engine;
+use wasmer::{
+    AsStoreMut, Extern, Function, FunctionEnv, FunctionEnvMut, Imports, Instance, Module,
+    RuntimeError,
+};
+use wasmer_middlewares::metering::{get_remaining_points, set_remaining_points, MeteringPoints};
+use wasmer_vfs::{mem_fs, FileSystem, FsError, VirtualFile};
+use wasmer_wasi::{WasiFunctionEnv, WasiState};
+
+use crate::{constants::WASI_OUTPUT_LIMIT, interrupt::Interrupt, pricing};
+
+/// Trap raised by a metered syscall when the wallet can't cover it
+#[derive(Debug)]
+pub struct CreditsExhausted;
+
+impl std::fmt::Display for CreditsExhausted {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "insufficient credits")
+    }
+}
+
+impl std::error::Error for CreditsExhausted {}
+
+/// Takes `cost` from the metering points of `instance`, trapping with
+/// `CreditsExhausted` when there aren't enough left
+pub fn charge_points(
+    store: &mut impl AsStoreMut,
+    instance: &Instance,
+    cost: u64,
+) -> Result<(), RuntimeError> {
+    match get_remaining_points(store, instance) {
+        MeteringPoints::Remaining(x) if x >= cost => {
+            set_remaining_points(store, instance, x - cost);
+            Ok(())
+        }
+        _ => {
+            set_remaining_points(store, instance, 0);
+            Err(RuntimeError::user(Box::new(CreditsExhausted)))
+        }
+    }
+}
+
+/// Instance whose metering points are charged for syscalls,
+/// set once the module is instantiated, and deadline of its call
+#[derive(Default)]
+struct SyscallMeter {
+    instance: Option<Instance>,
+    interrupt: Option<Interrupt>,
+}
+
+/// Captures a stdio stream of the guest, up to `WASI_OUTPUT_LIMIT` bytes
+#[derive(Debug, Clone, Default)]
+struct CapturedOutput(Arc<Mutex<Vec<u8>>>);
+
+impl CapturedOutput {
+    fn contents(&self) -> String {
+        String::from_utf8_lossy(&self.0.lock().expect("output lock to not be poisoned"))
+            .into_owned()
+    }
+}
+
+impl Write for CapturedOutput {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut output = self.0.lock().expect("output lock to not be poisoned");
+        let len = buf
+            .len()
+            .min(WASI_OUTPUT_LIMIT.saturating_sub(output.len()));
+
+        output.extend_from_slice(&buf[..len]);
+
+        // Output past the limit is silently dropped so the guest
+        // doesn't fail just because it is chatty
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Read for CapturedOutput {
+    fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+        Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "cannot read from captured output",
+        ))
+    }
+}
+
+impl Seek for CapturedOutput {
+    fn seek(&mut self, _pos: io::SeekFrom) -> io::Result<u64> {
+        Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "cannot seek captured output",
+        ))
+    }
+}
+
+impl VirtualFile for CapturedOutput {
+    fn last_accessed(&self) -> u64 {
+        0
+    }
+
+    fn last_modified(&self) -> u64 {
+        0
+    }
+
+    fn created_time(&self) -> u64 {
+        0
+    }
+
+    fn size(&self) -> u64 {
+        0
+    }
+
+    fn set_len(&mut self, _new_size: u64) -> Result<(), FsError> {
+        Err(FsError::PermissionDenied)
+    }
+
+    fn unlink(&mut self) -> Result<(), FsError> {
+        Ok(())
+    }
+
+    fn bytes_available(&self) -> Result<usize, FsError> {
+        Ok(0)
+    }
+}
+
+/// WASI preview1 environment of a single call, backed by an in-memory
+/// filesystem and with stdout/stderr captured for the response
+pub struct WasiContext {
+    env: WasiFunctionEnv,
+    meter: FunctionEnv<SyscallMeter>,
+    stdout: CapturedOutput,
+    stderr: CapturedOutput,
+}
+
+impl WasiContext {
+    pub fn new(
+        store: &mut impl AsStoreMut,
+        program: &str,
+        options: &WasiOptions,
+    ) -> Result<Self, AwsError> {
+        let stdout = CapturedOutput::default();
+        let stderr = CapturedOutput::default();
+
+        let env = WasiState::new(program)
+            .args(&options.args)
+            .envs(&options.env)
+            .set_fs(Box::new(memory_fs(options)?))
+            .preopen_dir("/")
+            .and_then(|builder| {
+                builder
+                    .stdout(Box::new(stdout.clone()))
+                    .stderr(Box::new(stderr.clone()))
+                    .finalize(store)
+            })
+            .map_err(|e| AwsError::InvalidWasiOptions(e.to_string()))?;
+
+        Ok(Self {
+            env,
+            meter: FunctionEnv::new(store, SyscallMeter::default()),
+            stdout,
+            stderr,
+        })
+    }
+
+    /// WASI imports for `module`, each syscall charging
+    /// the `wasi_syscall` points of the pricing before it runs
+    pub fn imports(
+        &self,
+        store: &mut impl AsStoreMut,
+        module: &Module,
+    ) -> Result<Imports, AwsError> {
+        let wasi_imports = self
+            .env
+            .import_object(store, module)
+            .map_err(|e| AwsError::InvalidWasiOptions(e.to_string()))?;
+
+        let mut imports = Imports::new();
+
+        for ((namespace, name), import) in &wasi_imports {
+            let import = match import {
+                Extern::Function(func) => Extern::Function(metered(store, &self.meter, func)),
+                other => other,
+            };
+
+            imports.define(&namespace, &name, import);
+        }
+
+        Ok(imports)
+    }
+
+    pub fn initialize(
+        &mut self,
+        store: &mut impl AsStoreMut,
+        instance: &Instance,
+    ) -> Result<(), AwsError> {
+        self.env
+            .initialize(store, instance)
+            .map_err(|e| AwsError::InvalidWasiOptions(e.to_string()))?;
+
+        self.meter.as_mut(store).instance = Some(instance.clone());
+
+        Ok(())
+    }
+
+    /// Sets the deadline of the call about to run, past which syscalls
+    /// trap
+    pub fn arm(&self, store: &mut impl AsStoreMut, interrupt: Interrupt) {
+        self.meter.as_mut(store).interrupt = Some(interrupt);
+    }
+
+    pub fn output(&self, exit_code: Option<u32>) -> WasiOutput {
+        WasiOutput {
+            stdout: self.stdout.contents(),
+            stderr: self.stderr.contents(),
+            exit_code,
+        }
+    }
+}
+
+fn memory_fs(options: &WasiOptions) -> Result<mem_fs::FileSystem, AwsError> {
+    let fs = mem_fs::FileSystem::default();
+
+    for (path, contents) in &options.files {
+        let contents = base64::engine::general_purpose::STANDARD
+            .decode(contents)
+            .map_err(|_| AwsError::InvalidWasmBase64)?;
+
+        let path = Path::new("/").join(path);
+
+        if let Some(parent) = path.parent() {
+            // The root always exists, only create the directories below it
+            let mut dirs = parent
+                .ancestors()
+                .filter(|dir| dir.parent().is_some())
+                .collect::<Vec<_>>();
+            dirs.reverse();
+
+            for dir in dirs {
+                match fs.create_dir(dir) {
+                    Ok(()) | Err(FsError::AlreadyExists) => (),
+                    Err(e) => return Err(AwsError::InvalidWasiOptions(e.to_string())),
+                }
+            }
+        }
+
+        fs.new_open_options()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)
+            .and_then(|mut file| file.write_all(&contents).map_err(FsError::from))
+            .map_err(|e| AwsError::InvalidWasiOptions(format!("{}: {e}", path.display())))?;
+    }
+
+    Ok(fs)
+}
+
+fn metered(
+    store: &mut impl AsStoreMut,
+    meter: &FunctionEnv<SyscallMeter>,
+    func: Function,
+) -> Function {
+    let ty = func.ty(store);
+
+    Function::new_with_env(
+        store,
+        meter,
+        ty,
+        move |mut env: FunctionEnvMut<SyscallMeter>, args| {
+            if let Some(interrupt) = &env.data().interrupt {
+                interrupt.check()?;
+            }
+
+            if let Some(instance) = env.data().instance.clone() {
+                charge_points(&mut env, &instance, pricing::current().wasi_syscall)?;
+            }
+
+            func.call(&mut env, args).map(|ret| ret.into_vec())
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{constants::WASI_SYSCALL_COST, utils::metered_store};
+
+    const HELLO: &str = r#"
+        (module
+            (import "wasi_snapshot_preview1" "fd_write"
+                (func $fd_write (param i32 i32 i32 i32) (result i32)))
+            (memory (export "memory") 1)
+            (data (i32.const 16) "hello\n")
+            (func (export "hello") (result i32)
+                (i32.store (i32.const 0) (i32.const 16))
+                (i32.store (i32.const 4) (i32.const 6))
+                (call $fd_write (i32.const 1) (i32.const 0) (i32.const 1) (i32.const 8))))
+    "#;
+
+    fn call_hello(points: u64) -> (Result<Box<[wasmer::Value]>, RuntimeError>, WasiOutput, u64) {
+        let mut store = metered_store();
+        let module = Module::new(&store, HELLO).unwrap();
+
+        let mut wasi = WasiContext::new(&mut store, "hello", &WasiOptions::default()).unwrap();
+        let imports = wasi.imports(&mut store, &module).unwrap();
+        let instance = Instance::new(&mut store, &module, &imports).unwrap();
+        wasi.initialize(&mut store, &instance).unwrap();
+
+        set_remaining_points(&mut store, &instance, points);
+
+        let result = instance
+            .exports
+            .get_function("hello")
+            .unwrap()
+            .call(&mut store, &[]);
+
+        let remaining = match get_remaining_points(&mut store, &instance) {
+            MeteringPoints::Remaining(x) => x,
+            MeteringPoints::Exhausted => 0,
+        };
+
+        (result, wasi.output(None), remaining)
+    }
+
+    #[test]
+    fn test_stdout_is_captured_and_metered() {
+        let (result, output, remaining) = call_hello(1_000);
+
+        assert_eq!(&*result.unwrap(), &[wasmer::Value::I32(0)]);
+        assert_eq!(output.stdout, "hello\n");
+        assert!(remaining <= 1_000 - WASI_SYSCALL_COST);
+    }
+
+    #[test]
+    fn test_syscall_fails_without_credits() {
+        let (result, output, _) = call_hello(WASI_SYSCALL_COST / 2);
+
+        assert!(result.unwrap_err().is::<CreditsExhausted>());
+        assert_eq!(output.stdout, "");
+    }
+
+    #[test]
+    fn test_files_are_written_to_memory_fs() {
+        let options = WasiOptions {
+            files: [("data/input.txt".to_string(), "aGVsbG8=".to_string())].into(),
+            ..Default::default()
+        };
+
+        let fs = memory_fs(&options).unwrap();
+        let mut contents = String::new();
+
+        fs.new_open_options()
+            .read(true)
+            .open("/data/input.txt")
+            .unwrap()
+            .read_to_string(&mut contents)
+            .unwrap();
+
+        assert_eq!(contents, "hello");
+    }
+}
@@ -1,16 +1,268 @@
+use std::fmt::Write;
+
+use ring::{
+    hmac,
+    rand::{SecureRandom, SystemRandom},
+};
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize, Debug)]
+/// Header carrying the HMAC-SHA256 of a request to the auth service, in hex
+pub const SIGNATURE_HEADER: &str = "x-aws-signature";
+/// Header carrying when a request to the auth service was signed, in Unix
+/// milliseconds
+pub const TIMESTAMP_HEADER: &str = "x-aws-timestamp";
+/// Header carrying the random nonce a request to the auth service was
+/// signed with
+pub const NONCE_HEADER: &str = "x-aws-nonce";
+/// How far from the auth service's clock a request may have been signed
+pub const SIGNATURE_MAX_SKEW_MS: i64 = 30_000;
+/// Shortest secret shared by the backend and the auth service
+pub const SHARED_SECRET_MIN_LEN: usize = 32;
+
+/// Claims of the access tokens the auth service issues
+#[derive(Clone, Serialize, Deserialize, Debug)]
 pub struct AwsClaims {
     pub sub: String,
     pub exp: usize,
     pub uid: i32,
+    /// Id of the token, the session it belongs to has to be live
+    pub jti: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Credentials {
+    pub username: String,
+    pub password: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct RefreshRequest {
+    pub refresh_token: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct JwtResponse {
+    pub jwt: String,
+    /// Trades for new tokens once the access token expired, once
+    pub refresh_token: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct RegisteredUser {
+    pub uid: i32,
+    pub username: String,
 }
 
+/// Tokens the auth service issued, along with whom to
 #[derive(Serialize, Deserialize)]
-struct SignRequest {
-    claims: AwsClaims,
+pub struct IssuedTokens {
+    pub uid: i32,
+    pub username: String,
+    #[serde(flatten)]
+    pub tokens: JwtResponse,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct LogoutRequest {
+    pub jti: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct IntrospectRequest {
+    pub uid: i32,
+    pub jti: String,
 }
 
 #[derive(Serialize, Deserialize)]
-struct SignResponse {}
+pub struct IntrospectResponse {
+    /// Whether the token's session is live
+    pub active: bool,
+}
+
+/// Credentials the backend held before the auth service owned them
+#[derive(Serialize, Deserialize)]
+pub struct ImportedCredentials {
+    pub uid: i32,
+    pub username: String,
+    /// Argon2 PHC string
+    pub password_hash: String,
+}
+
+/// What a request to the auth service is signed for
+pub struct SignedRequest<'a> {
+    pub timestamp: i64,
+    /// Random value of the request, the auth service accepts once
+    pub nonce: &'a str,
+    pub method: &'a str,
+    pub path: &'a str,
+    pub body: &'a [u8],
+}
+
+impl SignedRequest<'_> {
+    fn message(&self) -> Vec<u8> {
+        let SignedRequest {
+            timestamp,
+            nonce,
+            method,
+            path,
+            body,
+        } = self;
+
+        [
+            format!("{timestamp}.{nonce}.{method}.{path}.").as_bytes(),
+            body,
+        ]
+        .concat()
+    }
+}
+
+/// 128 random bits, in hex, to sign a request with
+pub fn request_nonce() -> String {
+    let mut bytes = [0u8; 16];
+
+    SystemRandom::new()
+        .fill(&mut bytes)
+        .expect("the system's random number generator to work");
+
+    to_hex(&bytes)
+}
+
+/// HMAC-SHA256 of the request, in hex, which can't be replayed on
+/// another endpoint
+pub fn request_signature(secret: &[u8], request: &SignedRequest) -> String {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, secret);
+
+    to_hex(hmac::sign(&key, &request.message()).as_ref())
+}
+
+/// Whether `signature` is the signature of the request, sent recently
+/// enough. Compares in constant time. Replays of the request within the
+/// skew are left to the receiver to reject, by its nonce
+pub fn verify_request_signature(
+    secret: &[u8],
+    request: &SignedRequest,
+    signature: &str,
+    now: i64,
+) -> bool {
+    if now.abs_diff(request.timestamp) > SIGNATURE_MAX_SKEW_MS as u64 {
+        return false;
+    }
+
+    let Some(signature) = from_hex(signature) else {
+        return false;
+    };
+
+    let key = hmac::Key::new(hmac::HMAC_SHA256, secret);
+
+    hmac::verify(&key, &request.message(), &signature).is_ok()
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut hex, byte| {
+        let _ = write!(hex, "{byte:02x}");
+        hex
+    })
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_signatures_are_hex_hmac_sha256() {
+        let request = SignedRequest {
+            timestamp: 1_000,
+            nonce: "0123",
+            method: "GET",
+            path: "/",
+            body: b"{}",
+        };
+        let key = hmac::Key::new(hmac::HMAC_SHA256, b"Jefe");
+        let expected = hmac::sign(&key, b"1000.0123.GET./.{}");
+
+        assert_eq!(
+            request_signature(b"Jefe", &request),
+            to_hex(expected.as_ref())
+        );
+
+        assert_eq!(from_hex("00ff10"), Some(vec![0x00, 0xff, 0x10]));
+        assert_eq!(from_hex("0"), None);
+        assert_eq!(from_hex("zz"), None);
+        assert_ne!(request_nonce(), request_nonce());
+    }
+
+    #[test]
+    fn test_request_signatures_are_verified() {
+        let secret = b"a secret shared by both services";
+        let request = SignedRequest {
+            timestamp: 1_000,
+            nonce: "0123",
+            method: "POST",
+            path: "/api/v1/login",
+            body: br#"{"username":"user"}"#,
+        };
+        let signature = request_signature(secret, &request);
+
+        assert!(verify_request_signature(
+            secret, &request, &signature, 1_500
+        ));
+        assert!(!verify_request_signature(
+            b"another secret",
+            &request,
+            &signature,
+            1_500
+        ));
+        assert!(!verify_request_signature(
+            secret, &request, &signature, 100_000
+        ));
+        assert!(!verify_request_signature(
+            secret,
+            &request,
+            &signature,
+            i64::MIN
+        ));
+        assert!(!verify_request_signature(
+            secret, &request, "not hex", 1_500
+        ));
+
+        let tampered = [
+            SignedRequest {
+                body: b"{}",
+                ..request
+            },
+            SignedRequest {
+                timestamp: 1_001,
+                ..request
+            },
+            SignedRequest {
+                nonce: "0124",
+                ..request
+            },
+            SignedRequest {
+                path: "/api/v1/register",
+                ..request
+            },
+            SignedRequest {
+                method: "DELETE",
+                ..request
+            },
+        ];
+
+        for request in tampered {
+            assert!(!verify_request_signature(
+                secret, &request, &signature, 1_500
+            ));
+        }
+    }
+}
@@ -1,5 +1,7 @@
 use axum::{http::StatusCode, response::IntoResponse};
 
+use super::responses::PipelineStepResult;
+
 #[derive(Debug)]
 pub enum AwsError {
     UnknownServerError,
@@ -21,6 +23,52 @@ pub enum AwsError {
     PasswordTooShort,
     PasswordTooWeak,
     JwtSignatureFailure,
+    InvalidWasiOptions(String),
+    ExecutionTimeout,
+    MemoryLimitExceeded,
+    InvalidGuestAbi(String),
+    JobNotFound(i32),
+    InvalidCronExpression(String),
+    ScheduleNotFound(i32),
+    InvalidPipeline(String),
+    PipelineNotFound(i32),
+    /// A step failed, after the steps in `steps` ran. The credits of
+    /// every step that ran, the failed one included, were charged
+    PipelineStepFailed {
+        index: usize,
+        step: String,
+        status: StatusCode,
+        message: String,
+        steps: Vec<PipelineStepResult>,
+        used_credits: u64,
+    },
+    InvalidBatch(String),
+    SessionNotFound(String),
+    SessionMemoryExceeded,
+    SnapshotNotFound(usize),
+    InvalidLedgerQuery(String),
+    Forbidden,
+    UserNotFound(String),
+    InvalidAmount(u64),
+    InvalidTransfer(String),
+    TopUpNotFound(i32),
+    PaymentProviderError(String),
+    TopUpsDisabled,
+    BudgetExceeded {
+        period: String,
+        module_id: Option<i32>,
+    },
+    InvalidSpendingLimit(String),
+    SpendingLimitNotFound(i32),
+    InvalidWebhookUrl,
+    InvalidRefreshToken,
+    InvalidApiKey(String),
+    ApiKeyNotFound(i32),
+    /// Error of the auth service, passed through
+    AuthServiceError {
+        status: StatusCode,
+        message: String,
+    },
 }
 
 impl IntoResponse for AwsError {
@@ -127,6 +175,191 @@ impl IntoResponse for AwsError {
                     "error": format!("failed to sign token")
                 })),
             ),
+            AwsError::InvalidWasiOptions(reason) => (
+                StatusCode::BAD_REQUEST,
+                axum::Json::from(serde_json::json!({
+                    "error": format!("invalid wasi options: {reason}")
+                })),
+            ),
+            AwsError::ExecutionTimeout => (
+                StatusCode::GATEWAY_TIMEOUT,
+                axum::Json::from(serde_json::json!({
+                    "error": format!("execution timed out")
+                })),
+            ),
+            AwsError::MemoryLimitExceeded => (
+                StatusCode::BAD_REQUEST,
+                axum::Json::from(serde_json::json!({
+                    "error": format!("memory limit exceeded")
+                })),
+            ),
+            AwsError::InvalidGuestAbi(reason) => (
+                StatusCode::BAD_REQUEST,
+                axum::Json::from(serde_json::json!({
+                    "error": format!("guest abi violation: {reason}")
+                })),
+            ),
+            AwsError::JobNotFound(id) => (
+                StatusCode::NOT_FOUND,
+                axum::Json::from(serde_json::json!({ "error": format!("job {id} not found") })),
+            ),
+            AwsError::InvalidCronExpression(expr) => (
+                StatusCode::BAD_REQUEST,
+                axum::Json::from(serde_json::json!({
+                    "error": format!("invalid cron expression {expr}")
+                })),
+            ),
+            AwsError::ScheduleNotFound(id) => (
+                StatusCode::NOT_FOUND,
+                axum::Json::from(serde_json::json!({
+                    "error": format!("schedule {id} not found")
+                })),
+            ),
+            AwsError::InvalidPipeline(reason) => (
+                StatusCode::BAD_REQUEST,
+                axum::Json::from(serde_json::json!({
+                    "error": format!("invalid pipeline: {reason}")
+                })),
+            ),
+            AwsError::PipelineNotFound(id) => (
+                StatusCode::NOT_FOUND,
+                axum::Json::from(serde_json::json!({
+                    "error": format!("pipeline {id} not found")
+                })),
+            ),
+            AwsError::InvalidBatch(reason) => (
+                StatusCode::BAD_REQUEST,
+                axum::Json::from(serde_json::json!({
+                    "error": format!("invalid batch: {reason}")
+                })),
+            ),
+            AwsError::SessionNotFound(id) => (
+                StatusCode::NOT_FOUND,
+                axum::Json::from(serde_json::json!({
+                    "error": format!("session {id} not found")
+                })),
+            ),
+            AwsError::SessionMemoryExceeded => (
+                StatusCode::BAD_REQUEST,
+                axum::Json::from(serde_json::json!({
+                    "error": "memory held by sessions exceeds the limit"
+                })),
+            ),
+            AwsError::SnapshotNotFound(id) => (
+                StatusCode::NOT_FOUND,
+                axum::Json::from(serde_json::json!({
+                    "error": format!("snapshot {id} not found")
+                })),
+            ),
+            AwsError::InvalidLedgerQuery(reason) => (
+                StatusCode::BAD_REQUEST,
+                axum::Json::from(serde_json::json!({
+                    "error": format!("invalid ledger query: {reason}")
+                })),
+            ),
+            AwsError::Forbidden => (
+                StatusCode::FORBIDDEN,
+                axum::Json::from(serde_json::json!({"error": "forbidden"})),
+            ),
+            AwsError::UserNotFound(username) => (
+                StatusCode::NOT_FOUND,
+                axum::Json::from(serde_json::json!({
+                    "error": format!("user {username} not found")
+                })),
+            ),
+            AwsError::InvalidAmount(max) => (
+                StatusCode::BAD_REQUEST,
+                axum::Json::from(serde_json::json!({
+                    "error": format!("amount must be 1 to {max} credits")
+                })),
+            ),
+            AwsError::InvalidTransfer(reason) => (
+                StatusCode::BAD_REQUEST,
+                axum::Json::from(serde_json::json!({
+                    "error": format!("invalid transfer: {reason}")
+                })),
+            ),
+            AwsError::TopUpNotFound(id) => (
+                StatusCode::NOT_FOUND,
+                axum::Json::from(serde_json::json!({
+                    "error": format!("top-up {id} not found")
+                })),
+            ),
+            AwsError::PaymentProviderError(reason) => (
+                StatusCode::BAD_GATEWAY,
+                axum::Json::from(serde_json::json!({
+                    "error": format!("payment provider error: {reason}")
+                })),
+            ),
+            AwsError::TopUpsDisabled => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                axum::Json::from(serde_json::json!({
+                    "error": "top-ups are disabled"
+                })),
+            ),
+            AwsError::BudgetExceeded { period, module_id } => (
+                StatusCode::PAYMENT_REQUIRED,
+                axum::Json::from(serde_json::json!({
+                    "error": match module_id {
+                        Some(id) => format!("{period} spending limit of module {id} reached"),
+                        None => format!("{period} spending limit reached"),
+                    }
+                })),
+            ),
+            AwsError::InvalidSpendingLimit(reason) => (
+                StatusCode::BAD_REQUEST,
+                axum::Json::from(serde_json::json!({
+                    "error": format!("invalid spending limit: {reason}")
+                })),
+            ),
+            AwsError::SpendingLimitNotFound(id) => (
+                StatusCode::NOT_FOUND,
+                axum::Json::from(serde_json::json!({
+                    "error": format!("spending limit {id} not found")
+                })),
+            ),
+            AwsError::InvalidWebhookUrl => (
+                StatusCode::BAD_REQUEST,
+                axum::Json::from(serde_json::json!({
+                    "error": "webhook url must be http or https and resolve to a public address"
+                })),
+            ),
+            AwsError::InvalidRefreshToken => (
+                StatusCode::UNAUTHORIZED,
+                axum::Json::from(serde_json::json!({"error": "invalid refresh token"})),
+            ),
+            AwsError::InvalidApiKey(reason) => (
+                StatusCode::BAD_REQUEST,
+                axum::Json::from(serde_json::json!({
+                    "error": format!("invalid api key: {reason}")
+                })),
+            ),
+            AwsError::ApiKeyNotFound(id) => (
+                StatusCode::NOT_FOUND,
+                axum::Json::from(serde_json::json!({
+                    "error": format!("api key {id} not found")
+                })),
+            ),
+            AwsError::AuthServiceError { status, message } => (
+                status,
+                axum::Json::from(serde_json::json!({ "error": message })),
+            ),
+            AwsError::PipelineStepFailed {
+                index,
+                step,
+                status,
+                message,
+                steps,
+                used_credits,
+            } => (
+                status,
+                axum::Json::from(serde_json::json!({
+                    "error": format!("pipeline step {step} failed: {message}"),
+                    "failed_step": index,
+                    "steps": steps,
+                    "used_credits": used_credits,
+                })),
+            ),
         }
         .into_response()
     }
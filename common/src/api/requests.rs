@@ -1,4 +1,6 @@
-use serde::Deserialize;
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
 
 #[derive(Deserialize)]
 pub struct EpxortedFunction {
@@ -14,4 +16,169 @@ pub struct DeployModuleBody {
 #[derive(Deserialize)]
 pub struct CallFunctionBody {
     pub params: Vec<serde_json::Value>,
+    #[serde(default)]
+    pub wasi: Option<WasiOptions>,
+    /// Deadline of the call, capped by the server's
+    #[serde(default)]
+    pub timeout_ms: Option<u64>,
+    /// Most credits the call may use, a default reservation otherwise
+    #[serde(default)]
+    pub max_credits: Option<u64>,
+}
+
+#[derive(Deserialize)]
+pub struct CreateScheduleBody {
+    /// Five field cron expression, in UTC
+    pub cron: String,
+    pub params: Vec<serde_json::Value>,
+    #[serde(default)]
+    pub timeout_ms: Option<u64>,
+}
+
+/// Where a pipeline step gets one of its parameters from
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ParamSource {
+    Value(serde_json::Value),
+    /// Parameter the pipeline is run with
+    Input(usize),
+    /// Result of another step
+    Output {
+        step: String,
+        index: usize,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PipelineStep {
+    /// Name other steps refer to the step by
+    pub id: String,
+    pub module_id: i32,
+    pub function: String,
+    #[serde(default)]
+    pub params: Vec<ParamSource>,
+}
+
+#[derive(Deserialize)]
+pub struct CreatePipelineBody {
+    pub name: String,
+    /// Steps run once the steps they take outputs from did
+    pub steps: Vec<PipelineStep>,
+}
+
+#[derive(Deserialize)]
+pub struct RunPipelineBody {
+    #[serde(default)]
+    pub params: Vec<serde_json::Value>,
+    /// Deadline of the whole pipeline, capped by the server's
+    #[serde(default)]
+    pub timeout_ms: Option<u64>,
+    /// Most credits the pipeline may use, a default reservation per step
+    /// otherwise
+    #[serde(default)]
+    pub max_credits: Option<u64>,
+}
+
+#[derive(Deserialize)]
+pub struct CallBatchBody {
+    /// Params of every call of the batch
+    pub items: Vec<Vec<serde_json::Value>>,
+    #[serde(default)]
+    pub parallel: bool,
+    /// Deadline of the whole batch, capped by the server's
+    #[serde(default)]
+    pub timeout_ms: Option<u64>,
+    /// Most credits the batch may use, a default reservation per item
+    /// otherwise
+    #[serde(default)]
+    pub max_credits: Option<u64>,
+}
+
+#[derive(Deserialize)]
+pub struct CallSessionBody {
+    pub params: Vec<serde_json::Value>,
+    /// Deadline of the call, capped by the server's
+    #[serde(default)]
+    pub timeout_ms: Option<u64>,
+    /// Most credits the call may use, a default reservation otherwise
+    #[serde(default)]
+    pub max_credits: Option<u64>,
+}
+
+/// Filters of the ledger, entries being listed newest first
+#[derive(Deserialize, Default)]
+pub struct LedgerQuery {
+    /// One of `grant`, `revoke`, `top_up`, `refund`, `transfer_in`,
+    /// `transfer_out` or `debit`
+    pub kind: Option<String>,
+    pub module_id: Option<i32>,
+    /// Unix time in milliseconds, inclusive
+    pub since: Option<i64>,
+    /// Unix time in milliseconds, exclusive
+    pub until: Option<i64>,
+    /// Only entries older than this one, to fetch the next page
+    pub before: Option<i32>,
+    pub limit: Option<u64>,
+}
+
+#[derive(Deserialize)]
+pub struct CreditsBody {
+    pub amount: u64,
+}
+
+#[derive(Deserialize)]
+pub struct TransferBody {
+    /// Username of the recipient
+    pub to: String,
+    pub amount: u64,
+}
+
+/// Caps what the user spends per period, replacing the limit of the
+/// same module and period if any
+#[derive(Deserialize)]
+pub struct SpendingLimitBody {
+    /// Module the limit applies to, all of the user's calls without one
+    #[serde(default)]
+    pub module_id: Option<i32>,
+    /// Either `daily` or `monthly`, in UTC
+    pub period: String,
+    pub max_credits: u64,
+    /// Share of the limit, in percent, past which the budget webhook is
+    /// called. 80 by default, 0 disables alerts
+    #[serde(default)]
+    pub alert_percent: Option<u8>,
+}
+
+#[derive(Deserialize)]
+pub struct BudgetWebhookBody {
+    /// Removes the webhook when missing
+    #[serde(default)]
+    pub url: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct PublicModuleBody {
+    /// Most credits a single `/run` call may use, the module is no longer
+    /// served on `/run` when missing
+    #[serde(default)]
+    pub max_credits: Option<u64>,
+}
+
+#[derive(Deserialize)]
+pub struct CreateApiKeyBody {
+    pub name: String,
+    /// Any of `invoke:<module_id>`, `deploy` and `read`
+    pub scopes: Vec<String>,
+}
+
+/// Opt-in WASI preview1 environment for a single call
+#[derive(Serialize, Deserialize, Default)]
+pub struct WasiOptions {
+    #[serde(default)]
+    pub args: Vec<String>,
+    #[serde(default)]
+    pub env: HashMap<String, String>,
+    /// Files to create in the in-memory filesystem, path -> base64 contents
+    #[serde(default)]
+    pub files: HashMap<String, String>,
 }
@@ -1,7 +1,7 @@
 use axum::response::IntoResponse;
 use serde::{Deserialize, Serialize};
 
-use super::errors::AwsError;
+use super::{errors::AwsError, requests::PipelineStep};
 
 #[derive(Serialize, Deserialize)]
 pub struct DeployedModulesResponse {
@@ -23,7 +23,7 @@ pub struct DeployedFunctionResponse {
 
 #[derive(Serialize, Deserialize)]
 pub struct GetCreditsResponse {
-    pub credits: i32,
+    pub credits: i64,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -31,25 +31,263 @@ pub struct DeployModuleResponse {
     pub mod_hash: String,
 }
 
+/// Largest integer a JSON number can hold without losing precision
+/// in most clients (`Number.MAX_SAFE_INTEGER`)
+const JSON_MAX_SAFE_INTEGER: u64 = (1 << 53) - 1;
+
+#[derive(Serialize, Deserialize)]
+pub struct WasiOutput {
+    pub stdout: String,
+    pub stderr: String,
+    pub exit_code: Option<u32>,
+}
+
+/// Message logged by a guest through the host `log` function
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LogEntry {
+    pub level: String,
+    pub message: String,
+}
+
 pub struct CallFunctionResponse {
-    pub return_value: Vec<wasmer::Value>,
+    pub return_value: Vec<serde_json::Value>,
+    pub wasi: Option<WasiOutput>,
+    pub logs: Vec<LogEntry>,
+}
+
+/// JSON representation of a scalar wasm value, `i64`s that would lose
+/// precision as numbers are represented as strings
+pub fn scalar_to_json(v: &wasmer::Value) -> Result<serde_json::Value, AwsError> {
+    match v {
+        wasmer::Value::I32(x) => Ok(serde_json::Value::from(*x)),
+        wasmer::Value::I64(x) if x.unsigned_abs() <= JSON_MAX_SAFE_INTEGER => {
+            Ok(serde_json::Value::from(*x))
+        }
+        wasmer::Value::I64(x) => Ok(serde_json::Value::from(x.to_string())),
+        wasmer::Value::F32(x) => Ok(serde_json::Value::from(*x)),
+        wasmer::Value::F64(x) => Ok(serde_json::Value::from(*x)),
+        _ => Err(AwsError::UnimplementedWasmType),
+    }
+}
+
+impl CallFunctionResponse {
+    pub fn into_json(self) -> serde_json::Value {
+        let mut body = serde_json::json!({ "return_value": self.return_value });
+
+        if let Some(wasi) = self.wasi {
+            body["wasi"] = serde_json::json!(wasi);
+        }
+
+        if !self.logs.is_empty() {
+            body["logs"] = serde_json::json!(self.logs);
+        }
+
+        body
+    }
 }
 
 impl IntoResponse for CallFunctionResponse {
     fn into_response(self) -> axum::response::Response {
-        let values = self
-            .return_value
-            .iter()
-            .map(|v| match v {
-                wasmer::Value::I32(x) => Ok(serde_json::Value::from(*x)),
-                wasmer::Value::F32(x) => Ok(serde_json::Value::from(*x)),
-                _ => Err(AwsError::UnimplementedWasmType),
-            })
-            .collect::<Result<Vec<_>, _>>();
-
-        match values {
-            Ok(v) => axum::Json::from(serde_json::json!({ "return_value": v })).into_response(),
-            Err(e) => e.into_response(),
-        }
+        axum::Json::from(self.into_json()).into_response()
     }
 }
+
+#[derive(Serialize, Deserialize)]
+pub struct EnqueuedJobResponse {
+    pub job_id: i32,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct JobResponse {
+    pub id: i32,
+    /// One of `queued`, `running`, `succeeded`, `failed` or `dead`
+    pub status: String,
+    pub attempts: i32,
+    /// Body a synchronous call would have responded with
+    pub result: Option<serde_json::Value>,
+    pub error: Option<String>,
+    pub used_credits: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ScheduleResponse {
+    pub id: i32,
+    pub function_id: i32,
+    pub cron: String,
+    pub params: Vec<serde_json::Value>,
+    pub timeout_ms: Option<u64>,
+    /// Either `active` or `paused`
+    pub status: String,
+    pub paused_reason: Option<String>,
+    /// Unix time in milliseconds
+    pub next_run_at: i64,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SchedulesResponse {
+    pub schedules: Vec<ScheduleResponse>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ScheduleRunResponse {
+    /// Unix time in milliseconds
+    pub started_at: i64,
+    /// Either `succeeded` or `failed`
+    pub status: String,
+    pub used_credits: u64,
+    pub result: Option<serde_json::Value>,
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ScheduleRunsResponse {
+    pub runs: Vec<ScheduleRunResponse>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PipelineResponse {
+    pub id: i32,
+    pub name: String,
+    pub steps: Vec<PipelineStep>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct PipelinesResponse {
+    pub pipelines: Vec<PipelineResponse>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PipelineStepResult {
+    pub id: String,
+    pub return_value: Vec<serde_json::Value>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub logs: Vec<LogEntry>,
+    pub used_credits: u64,
+}
+
+/// Results of the steps in the order they ran, all charged at once
+#[derive(Serialize, Deserialize)]
+pub struct PipelineRunResponse {
+    pub steps: Vec<PipelineStepResult>,
+    pub used_credits: u64,
+}
+
+/// Outcome of one call of a batch, either its result or its error
+#[derive(Serialize, Deserialize)]
+pub struct BatchItemResult {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub return_value: Option<Vec<serde_json::Value>>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub logs: Vec<LogEntry>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub used_credits: u64,
+}
+
+/// Results in the order of the batch items, all charged at once
+#[derive(Serialize, Deserialize)]
+pub struct CallBatchResponse {
+    pub items: Vec<BatchItemResult>,
+    pub used_credits: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SessionResponse {
+    pub session_id: String,
+    /// Idle time after which the session is closed
+    pub ttl_ms: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SnapshotResponse {
+    pub snapshot_id: usize,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct LedgerEntryResponse {
+    pub id: i32,
+    /// One of `grant`, `revoke`, `top_up`, `refund`, `transfer_in`,
+    /// `transfer_out` or `debit`
+    pub kind: String,
+    /// Credits added to the wallet, negative for debits
+    pub amount: i64,
+    pub module_id: Option<i32>,
+    pub function: Option<String>,
+    pub duration_ms: Option<i64>,
+    /// Unix time in milliseconds
+    pub created_at: i64,
+    /// Other side of a transfer
+    pub counterparty_id: Option<i32>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct LedgerResponse {
+    /// Sum of every entry of the ledger, credits held by running calls
+    /// included
+    pub balance: i64,
+    pub entries: Vec<LedgerEntryResponse>,
+    /// Cursor of the next page, if there may be one
+    pub next_before: Option<i32>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TopUpResponse {
+    pub id: i32,
+    pub amount: u64,
+    /// One of `pending`, `settled` or `failed`
+    pub status: String,
+    /// Unix time in milliseconds
+    pub created_at: i64,
+    pub settled_at: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SpendingLimitResponse {
+    pub id: i32,
+    pub module_id: Option<i32>,
+    pub period: String,
+    pub max_credits: u64,
+    pub alert_percent: Option<u8>,
+    /// Credits spent in the current period
+    pub spent: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SpendingLimitsResponse {
+    pub limits: Vec<SpendingLimitResponse>,
+    pub webhook_url: Option<String>,
+}
+
+/// Body posted to the budget webhook once spending crosses the alert
+/// threshold of a limit, at most once per period
+#[derive(Serialize, Deserialize)]
+pub struct BudgetAlert {
+    pub user_id: i32,
+    pub limit: SpendingLimitResponse,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ApiKeyResponse {
+    pub id: i32,
+    pub name: String,
+    /// Start of the key, to tell keys apart
+    pub prefix: String,
+    pub scopes: Vec<String>,
+    /// Unix time in milliseconds
+    pub created_at: i64,
+    /// Up to a minute stale
+    pub last_used_at: Option<i64>,
+}
+
+/// The only response the key itself is ever part of
+#[derive(Serialize, Deserialize)]
+pub struct CreatedApiKeyResponse {
+    pub key: String,
+    #[serde(flatten)]
+    pub api_key: ApiKeyResponse,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ApiKeysResponse {
+    pub api_keys: Vec<ApiKeyResponse>,
+}
@@ -16,7 +16,7 @@ export const ApiModule = z.object({
 });
 
 export const FunctionResult = z.object({
-  return_value: z.array(z.number()),
+  return_value: z.array(z.unknown()),
 });
 
 export const ApiResponse = z.object({